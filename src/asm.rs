@@ -0,0 +1,811 @@
+use std::collections::HashMap;
+use std::io;
+
+use super::instr::*;
+use super::sim::{MEM_DATA_START, MEM_TEXT_START};
+
+fn asm_error(line_no: usize, msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("line {}: {}", line_no, msg),
+    )
+}
+
+fn parse_int(inp: &str) -> Option<i64> {
+    let (neg, inp) = match inp.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, inp),
+    };
+    let val = if let Some(hex) = inp.strip_prefix("0x").or_else(|| inp.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else {
+        inp.parse::<i64>().ok()?
+    };
+    Some(if neg { -val } else { val })
+}
+
+fn encode_r(rs: u32, rt: u32, rd: u32, shamt: u32, funct: u32) -> u32 {
+    (rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct
+}
+
+fn encode_fr(fmt: u32, ft: u32, fs: u32, fd: u32, funct: u32) -> u32 {
+    const COP1: u32 = 0x11;
+    (COP1 << 26) | (fmt << 21) | (ft << 16) | (fs << 11) | (fd << 6) | funct
+}
+
+fn encode_i(opcode: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+    (opcode << 26) | (rs << 21) | (rt << 16) | (imm & 0xFFFF)
+}
+
+fn encode_j(opcode: u32, target: u32) -> u32 {
+    (opcode << 26) | (target & 0x3FFFFFF)
+}
+
+struct Line<'a> {
+    line_no: usize,
+    address: u32,
+    labels: &'a HashMap<String, u32>,
+}
+
+impl<'a> Line<'a> {
+    fn reg(&self, operand: &str) -> io::Result<u32> {
+        parse_reg(operand)
+            .ok_or_else(|| asm_error(self.line_no, format!("invalid register {}", operand)))
+    }
+
+    fn fpr(&self, operand: &str) -> io::Result<u32> {
+        parse_fpr(operand)
+            .ok_or_else(|| asm_error(self.line_no, format!("invalid FPU register {}", operand)))
+    }
+
+    fn imm(&self, operand: &str) -> io::Result<u32> {
+        match parse_int(operand) {
+            Some(val) if (-0x8000..=0xFFFF).contains(&val) => Ok(val as u32 & 0xFFFF),
+            Some(_) => Err(asm_error(
+                self.line_no,
+                format!("immediate {} does not fit in 16 bits", operand),
+            )),
+            None => Err(asm_error(
+                self.line_no,
+                format!("invalid immediate {}", operand),
+            )),
+        }
+    }
+
+    fn shamt(&self, operand: &str) -> io::Result<u32> {
+        match parse_int(operand) {
+            Some(val) if (0..32).contains(&val) => Ok(val as u32),
+            _ => Err(asm_error(
+                self.line_no,
+                format!("invalid shift amount {}", operand),
+            )),
+        }
+    }
+
+    fn label(&self, operand: &str) -> io::Result<u32> {
+        if let Some(address) = self.labels.get(operand) {
+            return Ok(*address);
+        }
+        match parse_int(operand) {
+            Some(val) => Ok(val as u32),
+            None => Err(asm_error(
+                self.line_no,
+                format!("undefined label {}", operand),
+            )),
+        }
+    }
+
+    fn branch_offset(&self, operand: &str) -> io::Result<u32> {
+        // Offsets are relative to the instruction following the branch
+        let target = self.label(operand)?;
+        let offset = (target as i64 - (self.address as i64 + 4)) / 4;
+        if !(-0x8000..0x8000).contains(&offset) {
+            return Err(asm_error(
+                self.line_no,
+                format!("branch to {} is out of range", operand),
+            ));
+        }
+        Ok(offset as u32 & 0xFFFF)
+    }
+
+    // Parses `offset(base)` operands, the offset being optional
+    fn mem_operand(&self, operand: &str) -> io::Result<(u32, u32)> {
+        let open = operand.find('(');
+        let close = operand.rfind(')');
+        match (open, close) {
+            (Some(open), Some(close)) if open < close => {
+                let offset = operand[..open].trim();
+                let offset = if offset.is_empty() {
+                    0
+                } else {
+                    self.imm(offset)?
+                };
+                let base = self.reg(operand[open + 1..close].trim())?;
+                Ok((offset, base))
+            }
+            _ => Err(asm_error(
+                self.line_no,
+                format!("invalid memory operand {}", operand),
+            )),
+        }
+    }
+
+    // Expands pseudo-instructions into real ones, encoding everything else
+    // as a single word. The number of words must match `stmt_words`.
+    fn expand(&self, mnemonic: &str, ops: &[&str]) -> io::Result<Vec<u32>> {
+        let expect = |count: usize| {
+            if ops.len() == count {
+                Ok(())
+            } else {
+                Err(asm_error(
+                    self.line_no,
+                    format!("{} takes {} operands, found {}", mnemonic, count, ops.len()),
+                ))
+            }
+        };
+        const LUI: u32 = 0xF;
+        const ORI: u32 = 0xD;
+        let words = match mnemonic {
+            "nop" => {
+                expect(0)?;
+                vec![0]
+            }
+            "move" => {
+                expect(2)?;
+                vec![self.encode("addu", &[ops[0], ops[1], "$zero"])?]
+            }
+            "b" => {
+                expect(1)?;
+                vec![self.encode("beq", &["$zero", "$zero", ops[0]])?]
+            }
+            "li" => {
+                expect(2)?;
+                let rt = self.reg(ops[0])?;
+                let val = match parse_int(ops[1]) {
+                    Some(val) if (-0x8000_0000..=0xFFFF_FFFF).contains(&val) => val,
+                    _ => {
+                        return Err(asm_error(
+                            self.line_no,
+                            format!("invalid 32-bit immediate {}", ops[1]),
+                        ))
+                    }
+                };
+                match li_words(val) {
+                    1 if val < 0 => vec![encode_i(0x9, 0, rt, val as u32)],
+                    1 => vec![encode_i(ORI, 0, rt, val as u32)],
+                    _ => vec![
+                        encode_i(LUI, 0, rt, (val as u32) >> 16),
+                        encode_i(ORI, rt, rt, val as u32),
+                    ],
+                }
+            }
+            "la" => {
+                expect(2)?;
+                let rt = self.reg(ops[0])?;
+                let address = self.label(ops[1])?;
+                vec![
+                    encode_i(LUI, 0, rt, address >> 16),
+                    encode_i(ORI, rt, rt, address),
+                ]
+            }
+            _ => vec![self.encode(mnemonic, ops)?],
+        };
+        Ok(words)
+    }
+
+    fn encode(&self, mnemonic: &str, ops: &[&str]) -> io::Result<u32> {
+        let expect = |count: usize| {
+            if ops.len() == count {
+                Ok(())
+            } else {
+                Err(asm_error(
+                    self.line_no,
+                    format!("{} takes {} operands, found {}", mnemonic, count, ops.len()),
+                ))
+            }
+        };
+        let word = match mnemonic {
+            "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu" => {
+                expect(3)?;
+                let funct = match mnemonic {
+                    "add" => 0x20,
+                    "addu" => 0x21,
+                    "sub" => 0x22,
+                    "subu" => 0x23,
+                    "and" => 0x24,
+                    "or" => 0x25,
+                    "xor" => 0x26,
+                    "nor" => 0x27,
+                    "slt" => 0x2A,
+                    _ => 0x2B,
+                };
+                encode_r(
+                    self.reg(ops[1])?,
+                    self.reg(ops[2])?,
+                    self.reg(ops[0])?,
+                    0,
+                    funct,
+                )
+            }
+            "sll" | "srl" | "sra" => {
+                expect(3)?;
+                let funct = match mnemonic {
+                    "sll" => 0x0,
+                    "srl" => 0x2,
+                    _ => 0x3,
+                };
+                encode_r(
+                    0,
+                    self.reg(ops[1])?,
+                    self.reg(ops[0])?,
+                    self.shamt(ops[2])?,
+                    funct,
+                )
+            }
+            "sllv" | "srlv" | "srav" => {
+                expect(3)?;
+                let funct = match mnemonic {
+                    "sllv" => 0x4,
+                    "srlv" => 0x6,
+                    _ => 0x7,
+                };
+                encode_r(
+                    self.reg(ops[2])?,
+                    self.reg(ops[1])?,
+                    self.reg(ops[0])?,
+                    0,
+                    funct,
+                )
+            }
+            "jr" => {
+                expect(1)?;
+                encode_r(self.reg(ops[0])?, 0, 0, 0, 0x8)
+            }
+            "jalr" => {
+                // `jalr rs` links into $ra, `jalr rd, rs` into rd
+                if ops.len() == 1 {
+                    encode_r(self.reg(ops[0])?, 0, 31, 0, 0x9)
+                } else {
+                    expect(2)?;
+                    encode_r(self.reg(ops[1])?, 0, self.reg(ops[0])?, 0, 0x9)
+                }
+            }
+            "movf" | "movt" => {
+                // The condition code defaults to 0 when omitted
+                let cc = if ops.len() == 2 {
+                    0
+                } else {
+                    expect(3)?;
+                    match parse_int(ops[2]) {
+                        Some(cc) if (0..8).contains(&cc) => cc as u32,
+                        _ => {
+                            return Err(asm_error(
+                                self.line_no,
+                                format!("invalid condition code {}", ops[2]),
+                            ))
+                        }
+                    }
+                };
+                let tf = (mnemonic == "movt") as u32;
+                encode_r(self.reg(ops[1])?, (cc << 2) | tf, self.reg(ops[0])?, 0, 0x1)
+            }
+            "mult" | "multu" | "div" | "divu" => {
+                expect(2)?;
+                let funct = match mnemonic {
+                    "mult" => 0x18,
+                    "multu" => 0x19,
+                    "div" => 0x1A,
+                    _ => 0x1B,
+                };
+                encode_r(self.reg(ops[0])?, self.reg(ops[1])?, 0, 0, funct)
+            }
+            "mfhi" | "mflo" => {
+                expect(1)?;
+                let funct = if mnemonic == "mfhi" { 0x10 } else { 0x12 };
+                encode_r(0, 0, self.reg(ops[0])?, 0, funct)
+            }
+            "mthi" | "mtlo" => {
+                expect(1)?;
+                let funct = if mnemonic == "mthi" { 0x11 } else { 0x13 };
+                encode_r(self.reg(ops[0])?, 0, 0, 0, funct)
+            }
+            "syscall" => {
+                expect(0)?;
+                encode_r(0, 0, 0, 0, 0xC)
+            }
+            "break" => {
+                expect(0)?;
+                encode_r(0, 0, 0, 0, 0xD)
+            }
+            "addi" | "addiu" | "slti" | "sltiu" | "andi" | "ori" | "xori" => {
+                expect(3)?;
+                let opcode = match mnemonic {
+                    "addi" => 0x8,
+                    "addiu" => 0x9,
+                    "slti" => 0xA,
+                    "sltiu" => 0xB,
+                    "andi" => 0xC,
+                    "ori" => 0xD,
+                    _ => 0xE,
+                };
+                encode_i(
+                    opcode,
+                    self.reg(ops[1])?,
+                    self.reg(ops[0])?,
+                    self.imm(ops[2])?,
+                )
+            }
+            "lui" => {
+                expect(2)?;
+                encode_i(0xF, 0, self.reg(ops[0])?, self.imm(ops[1])?)
+            }
+            "lb" | "lh" | "lw" | "lbu" | "lhu" | "sb" | "sh" | "sw" => {
+                expect(2)?;
+                let opcode = match mnemonic {
+                    "lb" => 0x20,
+                    "lh" => 0x21,
+                    "lw" => 0x23,
+                    "lbu" => 0x24,
+                    "lhu" => 0x25,
+                    "sb" => 0x28,
+                    "sh" => 0x29,
+                    _ => 0x2B,
+                };
+                let (offset, base) = self.mem_operand(ops[1])?;
+                encode_i(opcode, base, self.reg(ops[0])?, offset)
+            }
+            "pref" | "cache" => {
+                expect(2)?;
+                let code = match parse_int(ops[0]) {
+                    Some(val) if (0..32).contains(&val) => val as u32,
+                    _ => {
+                        return Err(asm_error(
+                            self.line_no,
+                            format!("invalid {} operation {}", mnemonic, ops[0]),
+                        ))
+                    }
+                };
+                let (offset, base) = self.mem_operand(ops[1])?;
+                let opcode = if mnemonic == "pref" { 0x33 } else { 0x2F };
+                encode_i(opcode, base, code, offset)
+            }
+            "lwc1" | "swc1" => {
+                expect(2)?;
+                let opcode = if mnemonic == "lwc1" { 0x31 } else { 0x39 };
+                let (offset, base) = self.mem_operand(ops[1])?;
+                encode_i(opcode, base, self.fpr(ops[0])?, offset)
+            }
+            "add.s" | "sub.s" | "mul.s" | "div.s" => {
+                expect(3)?;
+                const FMT_SINGLE: u32 = 0x10;
+                let funct = match mnemonic {
+                    "add.s" => 0x0,
+                    "sub.s" => 0x1,
+                    "mul.s" => 0x2,
+                    _ => 0x3,
+                };
+                encode_fr(
+                    FMT_SINGLE,
+                    self.fpr(ops[2])?,
+                    self.fpr(ops[1])?,
+                    self.fpr(ops[0])?,
+                    funct,
+                )
+            }
+            "mfc1" | "mtc1" => {
+                expect(2)?;
+                let fmt = if mnemonic == "mfc1" { 0x0 } else { 0x4 };
+                encode_fr(fmt, self.reg(ops[0])?, self.fpr(ops[1])?, 0, 0)
+            }
+            "beq" | "bne" => {
+                expect(3)?;
+                let opcode = if mnemonic == "beq" { 0x4 } else { 0x5 };
+                encode_i(
+                    opcode,
+                    self.reg(ops[0])?,
+                    self.reg(ops[1])?,
+                    self.branch_offset(ops[2])?,
+                )
+            }
+            "blez" | "bgtz" => {
+                expect(2)?;
+                let opcode = if mnemonic == "blez" { 0x6 } else { 0x7 };
+                encode_i(opcode, self.reg(ops[0])?, 0, self.branch_offset(ops[1])?)
+            }
+            "tgei" | "tlti" | "teqi" | "tnei" => {
+                expect(2)?;
+                let rt = match mnemonic {
+                    "tgei" => 0x8,
+                    "tlti" => 0xA,
+                    "teqi" => 0xC,
+                    _ => 0xE,
+                };
+                encode_i(0x1, self.reg(ops[0])?, rt, self.imm(ops[1])?)
+            }
+            "bltz" | "bgez" | "bltzal" | "bgezal" => {
+                expect(2)?;
+                let rt = match mnemonic {
+                    "bltz" => 0x0,
+                    "bgez" => 0x1,
+                    "bltzal" => 0x10,
+                    _ => 0x11,
+                };
+                encode_i(0x1, self.reg(ops[0])?, rt, self.branch_offset(ops[1])?)
+            }
+            "j" | "jal" => {
+                expect(1)?;
+                let opcode = if mnemonic == "j" { 0x2 } else { 0x3 };
+                encode_j(opcode, self.label(ops[0])? >> 2)
+            }
+            _ => {
+                return Err(asm_error(
+                    self.line_no,
+                    format!("unknown instruction {}", mnemonic),
+                ))
+            }
+        };
+        Ok(word)
+    }
+}
+
+// Drops a trailing `#` comment, leaving any `#` inside a string alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (pos, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..pos],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Splits off any number of leading `label:` definitions. A `:` inside a
+// string is not a label.
+fn split_labels(line: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = line.trim();
+    while let Some(pos) = rest
+        .find(':')
+        .filter(|pos| rest.find('"').map_or(true, |quote| *pos < quote))
+    {
+        labels.push(rest[..pos].trim());
+        rest = rest[pos + 1..].trim();
+    }
+    (labels, rest)
+}
+
+fn split_operands(rest: &str) -> Vec<&str> {
+    rest.split(',')
+        .map(|op| op.trim())
+        .filter(|op| !op.is_empty())
+        .collect()
+}
+
+// `li` takes one instruction when the value fits an addiu or ori
+// immediate, and a lui/ori pair otherwise
+fn li_words(val: i64) -> u32 {
+    if (-0x8000..=0xFFFF).contains(&val) {
+        1
+    } else {
+        2
+    }
+}
+
+// How many words a statement assembles to, known in the first pass so
+// that labels after pseudo-instructions get the right addresses
+fn stmt_words(mnemonic: &str, operands: &[&str]) -> u32 {
+    match mnemonic {
+        "la" => 2,
+        "li" => operands
+            .get(1)
+            .and_then(|val| parse_int(val))
+            .map_or(1, li_words),
+        _ => 1,
+    }
+}
+
+// The contents of a `"..."` literal, with \n, \t, \0, \\ and \" escapes
+fn parse_string(line_no: usize, inp: &str) -> io::Result<Vec<u8>> {
+    let inner = inp
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|inner| inner.len() + 2 == inp.len())
+        .ok_or_else(|| asm_error(line_no, format!("invalid string {}", inp)))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                _ => return Err(asm_error(line_no, format!("invalid escape in {}", inp))),
+            }
+        } else {
+            c
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Ok(bytes)
+}
+
+// Appends the bytes of a `.word`, `.byte` or `.asciiz` directive to `data`.
+// Words are little-endian, as in memory, and where each starts is added to
+// `words` so the loader can lay them out in another byte order.
+fn data_directive(
+    line_no: usize,
+    directive: &str,
+    operands: &str,
+    data: &mut Vec<u8>,
+    words: &mut Vec<usize>,
+) -> io::Result<()> {
+    let int = |operand: &str, min: i64, max: i64| match parse_int(operand) {
+        Some(val) if (min..=max).contains(&val) => Ok(val),
+        _ => Err(asm_error(
+            line_no,
+            format!("invalid {} value {}", directive, operand),
+        )),
+    };
+    match directive {
+        ".word" => {
+            for operand in split_operands(operands) {
+                let val = int(operand, i32::MIN as i64, u32::MAX as i64)?;
+                words.push(data.len());
+                data.extend_from_slice(&(val as u32).to_le_bytes());
+            }
+        }
+        ".byte" => {
+            for operand in split_operands(operands) {
+                data.push(int(operand, i8::MIN as i64, u8::MAX as i64)? as u8);
+            }
+        }
+        ".asciiz" => {
+            data.extend(parse_string(line_no, operands.trim())?);
+            data.push(0);
+        }
+        _ => {
+            return Err(asm_error(
+                line_no,
+                format!("unknown data directive {}", directive),
+            ))
+        }
+    }
+    Ok(())
+}
+
+struct Stmt<'a> {
+    line_no: usize,
+    address: u32,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+// What the first pass found
+struct Collected<'a> {
+    labels: HashMap<String, u32>,
+    stmts: Vec<Stmt<'a>>,
+    data: Vec<u8>,
+    data_words: Vec<usize>,
+}
+
+// First pass: records the address of every label and every instruction,
+// without encoding anything, so that forward references can be resolved.
+// Data directives are complete after this pass, so their bytes are built
+// here.
+fn collect<'a>(src: &'a str, base: u32, data_base: u32) -> io::Result<Collected<'a>> {
+    let mut labels = HashMap::new();
+    let mut stmts = Vec::new();
+    let mut data = Vec::new();
+    let mut data_words = Vec::new();
+    let mut in_data = false;
+    let mut address = base;
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let (defs, rest) = split_labels(strip_comment(line));
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(pos) => (&rest[..pos], rest[pos..].trim()),
+            None => (rest, ""),
+        };
+        let mnemonic = mnemonic.to_lowercase();
+        match mnemonic.as_str() {
+            ".text" => in_data = false,
+            ".data" => in_data = true,
+            // Words are aligned, and so are labels on them
+            ".word" if in_data => data.resize((data.len() + 3) & !0x3, 0),
+            _ => {}
+        }
+        let label_address = if in_data {
+            data_base + data.len() as u32
+        } else {
+            address
+        };
+        for label in defs {
+            if label.is_empty() || labels.insert(label.to_string(), label_address).is_some() {
+                return Err(asm_error(line_no, format!("invalid label {}", label)));
+            }
+        }
+        if rest.is_empty() || mnemonic == ".text" || mnemonic == ".data" {
+            continue;
+        }
+        if in_data {
+            data_directive(line_no, &mnemonic, operands, &mut data, &mut data_words)?;
+            continue;
+        }
+        let operands = split_operands(operands);
+        let words = stmt_words(&mnemonic, &operands);
+        stmts.push(Stmt {
+            line_no,
+            address,
+            mnemonic,
+            operands,
+        });
+        address += 4 * words;
+    }
+    Ok(Collected {
+        labels,
+        stmts,
+        data,
+        data_words,
+    })
+}
+
+// An assembled program: the words of its `.text` section, the bytes of its
+// `.data` section and the offsets in those of each `.word`
+#[derive(Debug, Default)]
+pub struct Program {
+    pub text: Vec<u32>,
+    pub data: Vec<u8>,
+    pub data_words: Vec<usize>,
+}
+
+// Assembles a program whose `.text` section is placed from `base` and
+// whose `.data` section, of `.word`, `.byte` and `.asciiz` directives, is
+// placed from `data_base`. Code comes first when no section is named.
+pub fn assemble_program(src: &str, base: u32, data_base: u32) -> io::Result<Program> {
+    let Collected {
+        labels,
+        stmts,
+        data,
+        data_words,
+    } = collect(src, base, data_base)?;
+    // Second pass: encode, patching in branch offsets and jump targets
+    let mut words = Vec::with_capacity(stmts.len());
+    for stmt in stmts.iter() {
+        let line = Line {
+            line_no: stmt.line_no,
+            address: stmt.address,
+            labels: &labels,
+        };
+        words.extend(line.expand(&stmt.mnemonic, &stmt.operands)?);
+    }
+    Ok(Program {
+        text: words,
+        data,
+        data_words,
+    })
+}
+
+// Assembles one instruction per line into words placed from `base`
+// onwards. Labels may be referenced before they are defined.
+pub fn assemble_at(src: &str, base: u32) -> io::Result<Vec<u32>> {
+    let program = assemble_program(src, base, MEM_DATA_START as u32)?;
+    if !program.data.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "data sections are only supported when loading a program",
+        ));
+    }
+    Ok(program.text)
+}
+
+pub fn assemble(src: &str) -> io::Result<Vec<u32>> {
+    assemble_at(src, MEM_TEXT_START as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instr::{decode, Instr};
+
+    // Decodes `word` back into its operation and operand fields
+    fn fields(word: u32) -> String {
+        match decode(word).expect("Cannot decode assembled word") {
+            Instr::JType(instr) => format!("{:?} {:#x}", instr.op(), instr.target()),
+            Instr::IType(instr) => format!(
+                "{:?} {} {} {:#x}",
+                instr.op(),
+                instr.rs(),
+                instr.rt(),
+                instr.imm()
+            ),
+            Instr::RType(instr) => format!(
+                "{:?} {} {} {} {}",
+                instr.op(),
+                instr.rs(),
+                instr.rt(),
+                instr.rd(),
+                instr.shamt()
+            ),
+            Instr::FRType(instr) => format!(
+                "{:?} {} {} {}",
+                instr.op(),
+                instr.ft(),
+                instr.fs(),
+                instr.fd()
+            ),
+        }
+    }
+
+    #[test]
+    fn loop_round_trips_through_the_decoder() {
+        let words = assemble(
+            "addiu $t0, $zero, 3
+            loop: addiu $t0, $t0, -1
+            sw $t0, -4($sp)
+            bne $t0, $zero, loop
+            addu $v0, $t0, $zero",
+        )
+        .unwrap();
+        assert_eq!(
+            words,
+            [0x24080003, 0x2508FFFF, 0xAFA8FFFC, 0x1500FFFD, 0x01001021]
+        );
+        // The branch offset counts words from the delay slot
+        let listing: Vec<String> = words.iter().map(|word| fields(*word)).collect();
+        assert_eq!(
+            listing,
+            [
+                "ADDIU 0 8 0x3",
+                "ADDIU 8 8 0xffff",
+                "SW 29 8 0xfffc",
+                "BNE 8 0 0xfffd",
+                "ADDU 8 0 2 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_branch_resolves_to_a_later_label() {
+        let src = "beq $t0, $t1, done
+            addiu $t0, $t0, 1
+            addiu $t1, $t1, 1
+            done: jr $ra";
+        // done is two instructions past the delay slot
+        let words = assemble(src).unwrap();
+        assert_eq!(words[0], 0x11090002);
+        // The offset is relative, so the base does not change it
+        let words = assemble_at(src, 0x00500000).unwrap();
+        assert_eq!(words[0] & 0xFFFF, 2);
+    }
+
+    #[test]
+    fn jump_targets_follow_the_base() {
+        let src = "j end
+            addiu $t0, $t0, 1
+            end: jr $ra";
+        assert_eq!(assemble(src).unwrap()[0], 0x08100002);
+        assert_eq!(assemble_at(src, 0x00500000).unwrap()[0], 0x08140002);
+    }
+
+    #[test]
+    fn li_with_a_wide_immediate_is_lui_then_ori() {
+        assert_eq!(
+            assemble("li $t0, 0x12345678").unwrap(),
+            [0x3C081234, 0x35085678]
+        );
+        // Immediates that fit take a single instruction
+        assert_eq!(assemble("li $t0, 0x5678").unwrap(), [0x34085678]);
+        assert_eq!(assemble("li $t0, -2").unwrap(), [0x2408FFFE]);
+        assert_eq!(
+            assemble("move $t1, $t0\nb end\nend: nop").unwrap(),
+            [0x01004821, 0x10000000, 0]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// A direct-mapped cache model. It only tracks which lines are resident, so
+// it never changes what the program computes, only the hit/miss counts.
+pub struct Cache {
+    line_size: usize,
+    num_sets: usize,
+    tags: Vec<Option<usize>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Cache {
+    pub fn new(line_size: usize, num_sets: usize) -> Self {
+        assert!(line_size > 0 && num_sets > 0);
+        Self {
+            line_size,
+            num_sets,
+            tags: vec![None; num_sets],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Returns whether the access hit, filling the line on a miss
+    pub fn access(&mut self, address: usize) -> bool {
+        let line = address / self.line_size;
+        let set = line % self.num_sets;
+        let tag = line / self.num_sets;
+        if self.tags[set] == Some(tag) {
+            self.hits += 1;
+            true
+        } else {
+            self.tags[set] = Some(tag);
+            self.misses += 1;
+            false
+        }
+    }
+
+    // Zeroes the hit and miss counts, keeping the resident lines
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    pub fn line_size(&self) -> usize {
+        self.line_size
+    }
+
+    pub fn num_sets(&self) -> usize {
+        self.num_sets
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+
+use super::sim::*;
+
+// gdb's MIPS register numbering: GPRs, then sr, lo, hi, badvaddr, cause
+// and pc, then the FPRs, fcsr and fir
+const NUM_GDB_REGS: usize = 72;
+const GDB_LO: usize = 33;
+const GDB_HI: usize = 34;
+const GDB_PC: usize = 37;
+const GDB_FPR_START: usize = 38;
+
+// How many cycles a continue runs between checks for gdb's interrupt byte
+const INTERRUPT_POLL_CYCLES: u32 = 1024;
+const INTERRUPT_BYTE: u8 = 0x03;
+
+// A minimal gdb remote serial protocol stub. It supports reading
+// registers and memory, software breakpoints, stepping and continuing.
+pub struct GdbStub<'a> {
+    comp: &'a mut MipsComputer,
+    breakpoints: HashSet<u32>,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn hex_u32(val: u32) -> String {
+    // Registers are sent in target (little-endian) byte order
+    val.to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_hex_u32(inp: &str) -> Option<u32> {
+    u32::from_str_radix(inp, 16).ok()
+}
+
+// Reads one `$data#cs` packet, skipping acks and interrupts before it.
+// Returns None at EOF.
+fn read_packet<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut byte = [0_u8; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+    let mut data = Vec::new();
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+    let mut cs = [0_u8; 2];
+    reader.read_exact(&mut cs)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn write_packet<W: Write>(writer: &mut W, data: &str) -> io::Result<()> {
+    write!(writer, "${}#{:02x}", data, checksum(data))?;
+    writer.flush()
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(comp: &'a mut MipsComputer) -> Self {
+        Self {
+            comp,
+            breakpoints: HashSet::new(),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let state = self.comp.curr_state();
+        (0..NUM_GDB_REGS)
+            .map(|reg| {
+                let val = match reg {
+                    0..=31 => state.get_reg(reg).unwrap_or(0),
+                    GDB_LO => state.lo(),
+                    GDB_HI => state.hi(),
+                    GDB_PC => state.pc(),
+                    _ if reg >= GDB_FPR_START => state.get_fpr(reg - GDB_FPR_START).unwrap_or(0),
+                    _ => 0,
+                };
+                hex_u32(val)
+            })
+            .collect()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match args.split_once(',') {
+            Some((addr, len)) => (parse_hex_u32(addr), parse_hex_u32(len)),
+            None => (None, None),
+        };
+        let (addr, len) = match (addr, len) {
+            (Some(addr), Some(len)) => (addr, len),
+            _ => return String::from("E01"),
+        };
+        let mut out = String::new();
+        for idx in 0..len {
+            match self.comp.read_byte(addr.wrapping_add(idx) as usize) {
+                Some(byte) => out.push_str(&format!("{:02x}", byte)),
+                None => return String::from("E14"),
+            }
+        }
+        out
+    }
+
+    // Handles `Z0,addr,kind` and `z0,addr,kind`
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        if fields.next() != Some("0") {
+            // Only software breakpoints are supported
+            return String::new();
+        }
+        match fields.next().and_then(parse_hex_u32) {
+            Some(addr) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                String::from("OK")
+            }
+            None => String::from("E01"),
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.comp.halt_reason() {
+            Some(reason) if reason.is_exit() => String::from("W00"),
+            _ => String::from("S05"),
+        }
+    }
+
+    // Runs until a breakpoint, a halt or, every INTERRUPT_POLL_CYCLES
+    // cycles, `interrupted` says gdb asked to stop
+    fn resume(&mut self, single_step: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        let mut until_poll = INTERRUPT_POLL_CYCLES;
+        loop {
+            if self.comp.halt_reason().is_some() {
+                break;
+            }
+            self.comp.cycle();
+            if single_step || self.breakpoints.contains(&self.comp.pc()) {
+                break;
+            }
+            until_poll -= 1;
+            if until_poll == 0 {
+                if interrupted() {
+                    // Stopped by SIGINT
+                    return String::from("S02");
+                }
+                until_poll = INTERRUPT_POLL_CYCLES;
+            }
+        }
+        self.stop_reply()
+    }
+
+    // Returns the reply to a packet, or None if the session should end.
+    // `interrupted` is polled while continuing.
+    pub fn handle(
+        &mut self,
+        packet: &str,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> Option<String> {
+        let reply = match packet.chars().next() {
+            Some('?') => self.stop_reply(),
+            Some('g') => self.read_registers(),
+            Some('m') => self.read_memory(&packet[1..]),
+            Some('Z') => self.breakpoint(&packet[1..], true),
+            Some('z') => self.breakpoint(&packet[1..], false),
+            Some('s') => self.resume(true, interrupted),
+            Some('c') => self.resume(false, interrupted),
+            Some('D') => String::from("OK"),
+            Some('k') => return None,
+            _ if packet.starts_with("qSupported") => String::from("PacketSize=1000"),
+            // An empty reply means the packet is not supported
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+}
+
+// Whether gdb has sent its interrupt byte, without waiting for input.
+// Other bytes arriving mid-run, such as acks, are dropped.
+fn poll_interrupt(reader: &mut io::BufReader<TcpStream>) -> io::Result<bool> {
+    reader.get_ref().set_nonblocking(true)?;
+    let mut byte = [0_u8; 1];
+    let mut interrupted = false;
+    loop {
+        match reader.read(&mut byte) {
+            Ok(1) if byte[0] == INTERRUPT_BYTE => interrupted = true,
+            Ok(1) => {}
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                reader.get_ref().set_nonblocking(false)?;
+                return Err(e);
+            }
+        }
+    }
+    reader.get_ref().set_nonblocking(false)?;
+    Ok(interrupted)
+}
+
+// Waits for gdb on `port` and serves one session
+pub fn serve(comp: &mut MipsComputer, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for gdb on port {}...\n", port);
+    let (stream, addr) = listener.accept()?;
+    println!("gdb connected from {}\n", addr);
+    let mut reader = io::BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut stub = GdbStub::new(comp);
+    while let Some(packet) = read_packet(&mut reader)? {
+        writer.write_all(b"+")?;
+        let detach = packet.starts_with('D');
+        // A failed poll ends the run too, and the next read reports it
+        let mut interrupted = || poll_interrupt(&mut reader).unwrap_or(true);
+        match stub.handle(&packet, &mut interrupted) {
+            Some(reply) => write_packet(&mut writer, &reply)?,
+            None => break,
+        }
+        if detach {
+            break;
+        }
+    }
+    println!("gdb session ended\n");
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::io;
+
+// The contents of an Intel HEX or Motorola S-record file: runs of bytes
+// with the address each starts at, and the entry point if one was given
+#[derive(Debug, Default)]
+pub struct HexImage {
+    pub chunks: Vec<(u32, Vec<u8>)>,
+    pub entry: Option<u32>,
+}
+
+// Both formats are text, so a binary that happens to start with `:` or `S`
+// is not mistaken for one
+pub fn is_hex_file(contents: &[u8]) -> bool {
+    matches!(contents.first(), Some(b':') | Some(b'S'))
+        && contents
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+}
+
+pub fn parse(src: &str) -> io::Result<HexImage> {
+    if src.trim_start().starts_with(':') {
+        parse_ihex(src)
+    } else {
+        parse_srec(src)
+    }
+}
+
+fn record_error(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, msg),
+    )
+}
+
+// Decodes the hex digits of a record into bytes
+fn record_bytes(digits: &str, line: usize) -> io::Result<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        return Err(record_error(line, "odd number of hex digits"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| {
+            digits
+                .get(idx..idx + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| record_error(line, "invalid hex digit"))
+        })
+        .collect()
+}
+
+fn be_value(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, byte| (acc << 8) | *byte as u32)
+}
+
+// Records are `:LLAAAATT<data>CC`, the checksum making all bytes sum to 0
+pub fn parse_ihex(src: &str) -> io::Result<HexImage> {
+    let mut image = HexImage::default();
+    let mut base = 0_u32;
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| record_error(line_no, "record does not start with ':'"))?;
+        let bytes = record_bytes(digits, line_no)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(record_error(line_no, "record length mismatch"));
+        }
+        let sum = bytes.iter().fold(0_u8, |acc, byte| acc.wrapping_add(*byte));
+        if sum != 0 {
+            return Err(record_error(line_no, "bad checksum"));
+        }
+        let offset = be_value(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => image
+                .chunks
+                .push((base.wrapping_add(offset), data.to_vec())),
+            0x01 => break,
+            0x02 if data.len() == 2 => base = be_value(data) << 4,
+            0x03 if data.len() == 4 => {
+                let segment = be_value(&data[..2]);
+                image.entry = Some((segment << 4) + be_value(&data[2..]));
+            }
+            0x04 if data.len() == 2 => base = be_value(data) << 16,
+            0x05 if data.len() == 4 => image.entry = Some(be_value(data)),
+            _ => return Err(record_error(line_no, "unknown record type")),
+        }
+    }
+    Ok(image)
+}
+
+// Records are `S<type><count><address><data><checksum>`, the count covering
+// the address, data and checksum, which is the complement of their sum
+pub fn parse_srec(src: &str) -> io::Result<HexImage> {
+    let mut image = HexImage::default();
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let kind = line
+            .strip_prefix('S')
+            .and_then(|rest| rest.chars().next())
+            .ok_or_else(|| record_error(line_no, "record does not start with 'S'"))?;
+        let digits = line
+            .get(2..)
+            .ok_or_else(|| record_error(line_no, "invalid record type"))?;
+        let bytes = record_bytes(digits, line_no)?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(record_error(line_no, "record length mismatch"));
+        }
+        let sum = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0_u8, |acc, byte| acc.wrapping_add(*byte));
+        if !sum != bytes[bytes.len() - 1] {
+            return Err(record_error(line_no, "bad checksum"));
+        }
+        let addr_len = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(record_error(line_no, "unknown record type")),
+        };
+        if bytes.len() < addr_len + 2 {
+            return Err(record_error(line_no, "record too short"));
+        }
+        let address = be_value(&bytes[1..1 + addr_len]);
+        let data = &bytes[1 + addr_len..bytes.len() - 1];
+        match kind {
+            '1' | '2' | '3' => image.chunks.push((address, data.to_vec())),
+            '7' | '8' | '9' => image.entry = Some(address),
+            // Headers and record counts carry nothing to load
+            _ => {}
+        }
+    }
+    Ok(image)
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub struct JType {
     opcode: u32,
@@ -91,11 +93,236 @@ impl RType {
     }
 }
 
+// Coprocessor 1 (FPU) register-format instructions
+#[derive(Debug)]
+pub struct FRType {
+    opcode: u32,
+    fmt: u32,
+    ft: u32,
+    fs: u32,
+    fd: u32,
+    funct: u32,
+    op: FOp,
+}
+
+impl FRType {
+    pub fn op(&self) -> FOp {
+        self.op
+    }
+
+    pub fn opcode(&self) -> u32 {
+        self.opcode
+    }
+
+    pub fn fmt(&self) -> u32 {
+        self.fmt
+    }
+
+    pub fn ft(&self) -> u32 {
+        self.ft
+    }
+
+    pub fn fs(&self) -> u32 {
+        self.fs
+    }
+
+    pub fn fd(&self) -> u32 {
+        self.fd
+    }
+
+    pub fn funct(&self) -> u32 {
+        self.funct
+    }
+}
+
 #[derive(Debug)]
 pub enum Instr {
     JType(JType),
     IType(IType),
     RType(RType),
+    FRType(FRType),
+}
+
+fn reg_name(reg: u32) -> String {
+    format!("${}", REG_NAMES[reg as usize])
+}
+
+// Renders the instruction in assembler syntax. Branch offsets are shown in
+// bytes relative to the delay slot, as they are encoded.
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::JType(instr) => {
+                let name = format!("{:?}", instr.op).to_lowercase();
+                write!(f, "{} {:#010X}", name, instr.target << 2)
+            }
+            Instr::IType(instr) => {
+                let name = format!("{:?}", instr.op).to_lowercase();
+                let rs = reg_name(instr.rs);
+                let rt = reg_name(instr.rt);
+                let simm = instr.imm as u16 as i16 as i32;
+                match instr.op {
+                    IOp::BEQ | IOp::BNE => write!(f, "{} {}, {}, {}", name, rs, rt, simm << 2),
+                    IOp::BLEZ | IOp::BGTZ | IOp::BLTZ | IOp::BGEZ | IOp::BLTZAL | IOp::BGEZAL => {
+                        write!(f, "{} {}, {}", name, rs, simm << 2)
+                    }
+                    IOp::ADDI | IOp::ADDIU | IOp::SLTI | IOp::SLTIU => {
+                        write!(f, "{} {}, {}, {}", name, rt, rs, simm)
+                    }
+                    IOp::TGEI | IOp::TLTI | IOp::TEQI | IOp::TNEI => {
+                        write!(f, "{} {}, {}", name, rs, simm)
+                    }
+                    IOp::ANDI | IOp::ORI | IOp::XORI => {
+                        write!(f, "{} {}, {}, {:#X}", name, rt, rs, instr.imm)
+                    }
+                    IOp::LUI => write!(f, "{} {}, {:#X}", name, rt, instr.imm),
+                    IOp::LWC1 | IOp::SWC1 => {
+                        write!(f, "{} $f{}, {}({})", name, instr.rt, simm, rs)
+                    }
+                    // rt holds a hint or cache operation, not a register
+                    IOp::PREF | IOp::CACHE => {
+                        write!(f, "{} {}, {}({})", name, instr.rt, simm, rs)
+                    }
+                    _ => write!(f, "{} {}, {}({})", name, rt, simm, rs),
+                }
+            }
+            Instr::RType(instr) => {
+                let name = format!("{:?}", instr.op).to_lowercase();
+                let rs = reg_name(instr.rs);
+                let rt = reg_name(instr.rt);
+                let rd = reg_name(instr.rd);
+                match instr.op {
+                    ROp::SLL if instr.rd == 0 && instr.rt == 0 && instr.shamt == 0 => {
+                        write!(f, "nop")
+                    }
+                    ROp::SLL | ROp::SRL | ROp::SRA => {
+                        write!(f, "{} {}, {}, {}", name, rd, rt, instr.shamt)
+                    }
+                    ROp::SLLV | ROp::SRLV | ROp::SRAV => {
+                        write!(f, "{} {}, {}, {}", name, rd, rt, rs)
+                    }
+                    ROp::JR | ROp::MTHI | ROp::MTLO => write!(f, "{} {}", name, rs),
+                    ROp::JALR => write!(f, "{} {}, {}", name, rd, rs),
+                    ROp::MULT | ROp::MULTU | ROp::DIV | ROp::DIVU => {
+                        write!(f, "{} {}, {}", name, rs, rt)
+                    }
+                    ROp::MFHI | ROp::MFLO => write!(f, "{} {}", name, rd),
+                    ROp::SYSCALL | ROp::BREAK => write!(f, "{}", name),
+                    ROp::MOVF | ROp::MOVT => {
+                        write!(f, "{} {}, {}, {}", name, rd, rs, instr.rt >> 2)
+                    }
+                    _ => write!(f, "{} {}, {}, {}", name, rd, rs, rt),
+                }
+            }
+            Instr::FRType(instr) => match instr.op {
+                FOp::MFC1 => write!(f, "mfc1 {}, $f{}", reg_name(instr.ft), instr.fs),
+                FOp::MTC1 => write!(f, "mtc1 {}, $f{}", reg_name(instr.ft), instr.fs),
+                op => {
+                    let name = match op {
+                        FOp::ADDS => "add.s",
+                        FOp::SUBS => "sub.s",
+                        FOp::MULS => "mul.s",
+                        _ => "div.s",
+                    };
+                    write!(f, "{} $f{}, $f{}, $f{}", name, instr.fd, instr.fs, instr.ft)
+                }
+            },
+        }
+    }
+}
+
+impl Instr {
+    // The GPRs the instruction reads
+    pub fn read_regs(&self) -> Vec<u32> {
+        match self {
+            Instr::JType(_) => vec![],
+            Instr::IType(instr) => match instr.op {
+                IOp::BEQ | IOp::BNE | IOp::SB | IOp::SH | IOp::SW => vec![instr.rs, instr.rt],
+                IOp::LUI => vec![],
+                _ => vec![instr.rs],
+            },
+            Instr::RType(instr) => match instr.op {
+                ROp::SLL | ROp::SRL | ROp::SRA => vec![instr.rt],
+                ROp::JR | ROp::JALR | ROp::MTHI | ROp::MTLO | ROp::MOVF | ROp::MOVT => {
+                    vec![instr.rs]
+                }
+                ROp::MFHI | ROp::MFLO | ROp::BREAK => vec![],
+                // The syscall number is passed in $v0
+                ROp::SYSCALL => vec![2],
+                _ => vec![instr.rs, instr.rt],
+            },
+            Instr::FRType(instr) => match instr.op {
+                FOp::MTC1 => vec![instr.ft],
+                _ => vec![],
+            },
+        }
+    }
+
+    // Where a jump or branch at `pc` goes when taken. Register jumps have
+    // no static target.
+    pub fn branch_target(&self, pc: u32) -> Option<u32> {
+        match self {
+            Instr::JType(instr) => Some((pc & 0xF0000000) | (instr.target << 2)),
+            Instr::IType(instr) => match instr.op {
+                IOp::BEQ
+                | IOp::BNE
+                | IOp::BLEZ
+                | IOp::BGTZ
+                | IOp::BLTZ
+                | IOp::BGEZ
+                | IOp::BLTZAL
+                | IOp::BGEZAL => {
+                    let offset = (instr.imm as u16 as i16 as i32) << 2;
+                    Some(pc.wrapping_add(4).wrapping_add(offset as u32))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The GPR the instruction writes, if any
+    pub fn written_reg(&self) -> Option<u32> {
+        match self {
+            Instr::JType(instr) => match instr.op {
+                JOp::JAL => Some(31),
+                JOp::J => None,
+            },
+            Instr::IType(instr) => match instr.op {
+                IOp::BLTZAL | IOp::BGEZAL => Some(31),
+                IOp::ADDI
+                | IOp::ADDIU
+                | IOp::SLTI
+                | IOp::SLTIU
+                | IOp::ANDI
+                | IOp::ORI
+                | IOp::XORI
+                | IOp::LUI
+                | IOp::LB
+                | IOp::LH
+                | IOp::LW
+                | IOp::LBU
+                | IOp::LHU => Some(instr.rt),
+                _ => None,
+            },
+            Instr::RType(instr) => match instr.op {
+                ROp::JR
+                | ROp::MULT
+                | ROp::MULTU
+                | ROp::DIV
+                | ROp::DIVU
+                | ROp::MTHI
+                | ROp::MTLO
+                | ROp::SYSCALL
+                | ROp::BREAK => None,
+                _ => Some(instr.rd),
+            },
+            Instr::FRType(instr) => match instr.op {
+                FOp::MFC1 => Some(instr.ft),
+                _ => None,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -130,6 +357,14 @@ pub enum IOp {
     BGEZ,
     BLTZAL,
     BGEZAL,
+    LWC1,
+    SWC1,
+    PREF,
+    CACHE,
+    TGEI,
+    TLTI,
+    TEQI,
+    TNEI,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -161,6 +396,50 @@ pub enum ROp {
     MTHI,
     MTLO,
     SYSCALL,
+    BREAK,
+    MOVF,
+    MOVT,
+}
+
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+// Accepts both ABI names ($t0) and numbers ($8), with or without the `$`
+pub fn parse_reg(name: &str) -> Option<u32> {
+    let name = name.trim_start_matches('$');
+    if let Ok(num) = name.parse::<u32>() {
+        if (num as usize) < REG_NAMES.len() {
+            return Some(num);
+        }
+        return None;
+    }
+    REG_NAMES
+        .iter()
+        .position(|reg| *reg == name)
+        .map(|pos| pos as u32)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FOp {
+    ADDS,
+    SUBS,
+    MULS,
+    DIVS,
+    MFC1,
+    MTC1,
+}
+
+// Accepts FPU register names of the form $f0 to $f31
+pub fn parse_fpr(name: &str) -> Option<u32> {
+    let num = name.strip_prefix("$f")?.parse::<u32>().ok()?;
+    if num < 32 {
+        Some(num)
+    } else {
+        None
+    }
 }
 
 // Extract the top 6 bits
@@ -171,9 +450,11 @@ fn extract_opcode(instr: u32) -> u32 {
     (instr & MASK) >> POS
 }
 
-pub fn parse_instr(instr: u32) -> Instr {
+// Decodes an instruction word, returning None for encodings that are
+// reserved or not implemented
+pub fn try_parse_instr(instr: u32) -> Option<Instr> {
     let opcode = extract_opcode(instr);
-    match opcode {
+    let instr = match opcode {
         0x2 => Instr::JType(parse_jump_instr(instr, JOp::J)),
         0x3 => Instr::JType(parse_jump_instr(instr, JOp::JAL)),
         0x4 => Instr::IType(parse_immediate_instr(instr, IOp::BEQ)),
@@ -196,9 +477,146 @@ pub fn parse_instr(instr: u32) -> Instr {
         0x28 => Instr::IType(parse_immediate_instr(instr, IOp::SB)),
         0x29 => Instr::IType(parse_immediate_instr(instr, IOp::SH)),
         0x2B => Instr::IType(parse_immediate_instr(instr, IOp::SW)),
-        0x1 => Instr::IType(parse_immediate_instr_and_op(instr)),
-        0x0 => Instr::RType(parse_register_instr(instr)),
-        _ => panic!("Unknown instruction!"),
+        0x31 => Instr::IType(parse_immediate_instr(instr, IOp::LWC1)),
+        0x39 => Instr::IType(parse_immediate_instr(instr, IOp::SWC1)),
+        0x33 => Instr::IType(parse_immediate_instr(instr, IOp::PREF)),
+        0x2F => Instr::IType(parse_immediate_instr(instr, IOp::CACHE)),
+        0x1 => Instr::IType(parse_immediate_instr_and_op(instr)?),
+        0x0 => Instr::RType(parse_register_instr(instr)?),
+        0x11 => Instr::FRType(parse_cop1_instr(instr)?),
+        _ => return None,
+    };
+    Some(instr)
+}
+
+#[deprecated(note = "use decode")]
+pub fn parse_instr(instr: u32) -> Instr {
+    try_parse_instr(instr).expect("Unknown instruction!")
+}
+
+// A word that is not an instruction this simulator implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    word: u32,
+}
+
+impl DecodeError {
+    pub fn word(&self) -> u32 {
+        self.word
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown instruction {:#010X}", self.word)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes an instruction word without panicking, so that other tools can
+/// build disassemblers or analyzers on top of the simulator's decoder.
+///
+/// A J-type jump:
+///
+/// ```
+/// use mips_sim::instr::{decode, Instr, JOp};
+///
+/// let instr = decode(0x08100000).unwrap();
+/// match &instr {
+///     Instr::JType(j) => {
+///         assert!(matches!(j.op(), JOp::J));
+///         assert_eq!(j.target(), 0x100000);
+///     }
+///     _ => panic!("not a J-type instruction"),
+/// }
+/// assert_eq!(instr.to_string(), "j 0x00400000");
+/// ```
+///
+/// An I-type immediate add:
+///
+/// ```
+/// use mips_sim::instr::{decode, IOp, Instr};
+///
+/// let instr = decode(0x24080005).unwrap();
+/// match &instr {
+///     Instr::IType(i) => {
+///         assert!(matches!(i.op(), IOp::ADDIU));
+///         assert_eq!((i.rs(), i.rt(), i.imm()), (0, 8, 5));
+///     }
+///     _ => panic!("not an I-type instruction"),
+/// }
+/// assert_eq!(instr.to_string(), "addiu $t0, $zero, 5");
+/// ```
+///
+/// An R-type register add:
+///
+/// ```
+/// use mips_sim::instr::{decode, Instr, ROp};
+///
+/// let instr = decode(0x01095021).unwrap();
+/// match &instr {
+///     Instr::RType(r) => {
+///         assert!(matches!(r.op(), ROp::ADDU));
+///         assert_eq!((r.rs(), r.rt(), r.rd()), (8, 9, 10));
+///     }
+///     _ => panic!("not an R-type instruction"),
+/// }
+/// assert_eq!(instr.to_string(), "addu $t2, $t0, $t1");
+/// ```
+///
+/// An FPU single-precision add:
+///
+/// ```
+/// use mips_sim::instr::{decode, FOp, Instr};
+///
+/// let instr = decode(0x46062080).unwrap();
+/// match &instr {
+///     Instr::FRType(fr) => {
+///         assert!(matches!(fr.op(), FOp::ADDS));
+///         assert_eq!((fr.fs(), fr.ft(), fr.fd()), (4, 6, 2));
+///     }
+///     _ => panic!("not an FPU instruction"),
+/// }
+/// assert_eq!(instr.to_string(), "add.s $f2, $f4, $f6");
+/// ```
+///
+/// Words that are not implemented instructions are an error:
+///
+/// ```
+/// use mips_sim::instr::decode;
+///
+/// let err = decode(0xFC000000).unwrap_err();
+/// assert_eq!(err.word(), 0xFC000000);
+/// ```
+pub fn decode(word: u32) -> Result<Instr, DecodeError> {
+    try_parse_instr(word).ok_or(DecodeError { word })
+}
+
+// The inverse of `decode`. The decoded structs keep every field of the
+// word, so `encode(&decode(word)?) == word` for any decodable word.
+pub fn encode(instr: &Instr) -> u32 {
+    match instr {
+        Instr::JType(instr) => (instr.opcode << 26) | instr.target,
+        Instr::IType(instr) => {
+            (instr.opcode << 26) | (instr.rs << 21) | (instr.rt << 16) | instr.imm
+        }
+        Instr::RType(instr) => {
+            (instr.opcode << 26)
+                | (instr.rs << 21)
+                | (instr.rt << 16)
+                | (instr.rd << 11)
+                | (instr.shamt << 6)
+                | instr.funct
+        }
+        Instr::FRType(instr) => {
+            (instr.opcode << 26)
+                | (instr.fmt << 21)
+                | (instr.ft << 16)
+                | (instr.fs << 11)
+                | (instr.fd << 6)
+                | instr.funct
+        }
     }
 }
 
@@ -229,7 +647,7 @@ fn parse_immediate_instr(instr: u32, op: IOp) -> IType {
     }
 }
 
-fn parse_immediate_instr_and_op(instr: u32) -> IType {
+fn parse_immediate_instr_and_op(instr: u32) -> Option<IType> {
     const RS_MASK: u32 = 0x3E00000;
     const RS_SHIFT: u32 = 21;
     const RT_MASK: u32 = 0x1F0000;
@@ -241,20 +659,24 @@ fn parse_immediate_instr_and_op(instr: u32) -> IType {
     let op = match rt {
         0x0 => IOp::BLTZ,
         0x1 => IOp::BGEZ,
-        0x20 => IOp::BLTZAL,
-        0x21 => IOp::BGEZAL,
-        _ => panic!("Uknown branch instruction for REGIMM => {:#08b}", rt),
+        0x08 => IOp::TGEI,
+        0x0A => IOp::TLTI,
+        0x0C => IOp::TEQI,
+        0x0E => IOp::TNEI,
+        0x10 => IOp::BLTZAL,
+        0x11 => IOp::BGEZAL,
+        _ => return None,
     };
-    IType {
+    Some(IType {
         rs,
         rt,
         imm,
         opcode: extract_opcode(instr),
         op,
-    }
+    })
 }
 
-fn parse_register_instr(instr: u32) -> RType {
+fn parse_register_instr(instr: u32) -> Option<RType> {
     const RS_MASK: u32 = 0x3E00000;
     const RS_SHIFT: u32 = 21;
     const RT_MASK: u32 = 0x1F0000;
@@ -273,6 +695,9 @@ fn parse_register_instr(instr: u32) -> RType {
     assert_eq!(extract_opcode(instr), 0);
     let op = match funct {
         0x0 => ROp::SLL,
+        // The low bit of rt selects the sense, the top 3 bits the FP cc
+        0x1 if rt & 0x1 == 0 => ROp::MOVF,
+        0x1 => ROp::MOVT,
         0x2 => ROp::SRL,
         0x3 => ROp::SRA,
         0x4 => ROp::SLLV,
@@ -299,10 +724,11 @@ fn parse_register_instr(instr: u32) -> RType {
         0x11 => ROp::MTHI,
         0x13 => ROp::MTLO,
         0xC => ROp::SYSCALL,
-        _ => panic!("Unknown R Type instruction"),
+        0xD => ROp::BREAK,
+        _ => return None,
     };
 
-    RType {
+    Some(RType {
         opcode: 0,
         rs,
         rt,
@@ -310,5 +736,77 @@ fn parse_register_instr(instr: u32) -> RType {
         shamt,
         funct,
         op,
+    })
+}
+
+fn parse_cop1_instr(instr: u32) -> Option<FRType> {
+    const FMT_MASK: u32 = 0x3E00000;
+    const FMT_SHIFT: u32 = 21;
+    const FT_MASK: u32 = 0x1F0000;
+    const FT_SHIFT: u32 = 16;
+    const FS_MASK: u32 = 0xF800;
+    const FS_SHIFT: u32 = 11;
+    const FD_MASK: u32 = 0x7C0;
+    const FD_SHIFT: u32 = 6;
+    const FUNCT_MASK: u32 = 0x3F;
+    const FMT_SINGLE: u32 = 0x10;
+    let fmt = (instr & FMT_MASK) >> FMT_SHIFT;
+    let ft = (instr & FT_MASK) >> FT_SHIFT;
+    let fs = (instr & FS_MASK) >> FS_SHIFT;
+    let fd = (instr & FD_MASK) >> FD_SHIFT;
+    let funct = instr & FUNCT_MASK;
+
+    // For the moves, the fmt field selects the direction and ft is a GPR
+    let op = match (fmt, funct) {
+        (0x0, _) => FOp::MFC1,
+        (0x4, _) => FOp::MTC1,
+        (FMT_SINGLE, 0x0) => FOp::ADDS,
+        (FMT_SINGLE, 0x1) => FOp::SUBS,
+        (FMT_SINGLE, 0x2) => FOp::MULS,
+        (FMT_SINGLE, 0x3) => FOp::DIVS,
+        _ => return None,
+    };
+
+    Some(FRType {
+        opcode: extract_opcode(instr),
+        fmt,
+        ft,
+        fs,
+        fd,
+        funct,
+        op,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_unhandled_regimm_rt_is_a_decode_error() {
+        // REGIMM with rt = 0x1F, which no instruction uses
+        let word = (0x01 << 26) | (8 << 21) | (0x1F << 16) | 4;
+        assert_eq!(decode(word).unwrap_err().word(), word);
+        // The traps sharing the opcode still decode
+        let tgei = (0x01 << 26) | (8 << 21) | (0x08 << 16) | 4;
+        assert_eq!(decode(tgei).unwrap().to_string(), "tgei $t0, 4");
+    }
+
+    #[test]
+    fn encode_inverts_decode_for_random_valid_words() {
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        let mut valid = 0;
+        for _ in 0..200_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let word = (state >> 32) as u32;
+            if let Ok(instr) = decode(word) {
+                assert_eq!(encode(&instr), word, "{:#010X} ({})", word, instr);
+                valid += 1;
+            }
+        }
+        // Enough of the space decodes for the check to mean something
+        assert!(valid > 10_000, "only {} valid words", valid);
     }
 }
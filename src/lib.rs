@@ -1,3 +1,13 @@
+pub mod asm;
+pub mod cache;
+pub mod gdb;
+pub mod hexfile;
 pub mod instr;
 pub mod shell;
 pub mod sim;
+pub mod symbols;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod timing;
+pub mod trace;
+pub mod verify;
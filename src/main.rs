@@ -1,21 +1,156 @@
+use mips_sim::gdb;
 use mips_sim::shell::*;
 use mips_sim::sim::*;
+use mips_sim::trace::TextSink;
 use std::env;
 use std::fs::File;
-use std::io;
+use std::io::{self, BufReader};
 use std::process::exit;
 
+fn usage(prog: &str) -> ! {
+    println!(
+        "Usage: {} [--gdb port] [--trace-to file] [--script file|-] [--history n] [--fill word|--fill-random seed] [--clean-exits kind,..] <program-file-1> <program-file-2> ...",
+        prog
+    );
+    println!("       {} disasm <program-file>", prog);
+    exit(1);
+}
+
+// Ctrl-C stops a running program rather than the simulator. With nothing
+// running, at the prompt, it ends the simulator as it normally would.
+#[cfg(unix)]
+mod sigint {
+    use std::io;
+    use std::os::raw::c_int;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, OnceLock};
+
+    // C's sighandler_t: a handler's address or one of the SIG_ values
+    #[allow(non_camel_case_types)]
+    type sighandler_t = usize;
+
+    const SIGINT: c_int = 2;
+    const SIG_DFL: sighandler_t = 0;
+    const SIG_ERR: sighandler_t = !0;
+    // The simulator's interrupt flag and the shell's busy flag
+    static FLAGS: OnceLock<(Arc<AtomicBool>, Arc<AtomicBool>)> = OnceLock::new();
+
+    extern "C" {
+        fn signal(signum: c_int, handler: sighandler_t) -> sighandler_t;
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    extern "C" fn handle(signum: c_int) {
+        match FLAGS.get() {
+            Some((interrupt, busy)) if busy.load(Ordering::SeqCst) => {
+                interrupt.store(true, Ordering::SeqCst);
+            }
+            // Idle at the prompt, so die of the signal as if uncaught
+            _ => unsafe {
+                signal(signum, SIG_DFL);
+                raise(signum);
+            },
+        }
+    }
+
+    pub fn install(interrupt: Arc<AtomicBool>, busy: Arc<AtomicBool>) -> io::Result<()> {
+        if FLAGS.set((interrupt, busy)).is_err() {
+            return Ok(());
+        }
+        let handler = handle as extern "C" fn(c_int) as sighandler_t;
+        if unsafe { signal(SIGINT, handler) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <program-file-1> <program-file-2> ...", args[0]);
-        exit(1);
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        if args.len() != 3 {
+            usage(&args[0]);
+        }
+        let comp = MipsComputer::new(&args[2..])?;
+        return comp.disasm_text(&mut io::stdout());
+    }
+    let mut gdb_port: Option<u16> = None;
+    let mut trace_path: Option<String> = None;
+    let mut script_path: Option<String> = None;
+    let mut clean_exits = None;
+    let mut config = MipsConfig::default();
+    let mut filenames = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--gdb" => match rest.next().and_then(|port| port.parse().ok()) {
+                Some(port) => gdb_port = Some(port),
+                None => usage(&args[0]),
+            },
+            "--fill" => match rest
+                .next()
+                .and_then(|word| u32::from_str_radix(word.trim_start_matches("0x"), 16).ok())
+            {
+                Some(word) => config.fill = FillPattern::Word(word),
+                None => usage(&args[0]),
+            },
+            "--history" => match rest.next().and_then(|depth| depth.parse().ok()) {
+                Some(depth) => config.history_depth = depth,
+                None => usage(&args[0]),
+            },
+            "--fill-random" => match rest.next().and_then(|seed| seed.parse().ok()) {
+                Some(seed) => config.fill = FillPattern::Random(seed),
+                None => usage(&args[0]),
+            },
+            "--clean-exits" => match rest.next().map(|kinds| {
+                let kinds: Vec<&str> = kinds.split(',').collect();
+                parse_clean_exits(&kinds)
+            }) {
+                Some(Ok(reasons)) => clean_exits = Some(reasons),
+                _ => usage(&args[0]),
+            },
+            "--trace-to" => match rest.next() {
+                Some(path) => trace_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--script" => match rest.next() {
+                Some(path) => script_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            _ => filenames.push(arg.clone()),
+        }
+    }
+    if filenames.is_empty() {
+        usage(&args[0]);
     }
     println!("MIPS Simulator\n");
-    let mut comp = MipsComputer::new(&args[1..])?;
-    let mut dump_file = File::create("dumpsim").expect("Can't open dumpsim file");
+    let mut comp = MipsComputer::with_config(config, &filenames)?;
+    if let Some(path) = trace_path {
+        let file = File::create(path)?;
+        comp.set_trace_sink(Some(Box::new(TextSink::new(file))));
+    }
+    if let Some(port) = gdb_port {
+        return gdb::serve(&mut comp, port);
+    }
+    #[cfg(unix)]
+    let interrupt = comp.interrupt_handle();
+    let dump_file = File::create("dumpsim").expect("Can't open dumpsim file");
+    let mut shell = Shell::new(comp, dump_file);
+    #[cfg(unix)]
+    if let Err(e) = sigint::install(interrupt, shell.busy_handle()) {
+        println!("Warning: Ctrl-C will quit rather than stop a run: {}\n", e);
+    }
+    if let Some(reasons) = clean_exits {
+        shell.set_clean_exits(reasons);
+    }
+    // A script, or `-` for commands piped to stdin, runs headless
+    match script_path.as_deref() {
+        Some("-") => shell.set_script(Box::new(io::stdin().lock())),
+        Some(path) => shell.set_script(Box::new(BufReader::new(File::open(path)?))),
+        None => {}
+    }
     loop {
-        if let Err(e) = prompt(&mut comp, &mut dump_file) {
+        if let Err(e) = shell.prompt() {
             println!("Error: {}", e);
         }
     }
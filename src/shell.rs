@@ -1,22 +1,419 @@
+use super::instr::*;
 use super::sim::*;
+use super::timing::CycleCosts;
+use super::trace::*;
 use std::io;
 use std::io::prelude::*;
 use std::process::exit;
-use std::usize;
-use std::{fs::File, num::ParseIntError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{fmt, fs::File, num::ParseIntError};
+
+const HELP_LINES: &[&str] = &[
+    "go                    - run program to completion     ",
+    "run n                 - execute program for n instrs  ",
+    "step                  - execute program for 1 instr   ",
+    "back [n]              - undo the last n instrs (1)    ",
+    "history n|off         - keep n instrs for back (off)  ",
+    "until addr            - run until PC reaches addr     ",
+    "finish                - run until the function returns",
+    "call addr [args..]    - call a function, print $v0    ",
+    "break addr            - stop when PC reaches addr     ",
+    "break-range low high  - stop when PC enters the range ",
+    "breaks                - list breakpoints              ",
+    "clear-breaks          - remove all breakpoints        ",
+    "mdump low high        - dump memory from low to high  ",
+    "mdump [@reg]          - dump memory around PC or reg  ",
+    "words addr w0 w1 ..   - write words from addr onwards ",
+    "rdump                 - dump the register & bus value ",
+    "dis [addr] [n]        - disassemble n instrs (8)      ",
+    "validate              - find undecodable text words   ",
+    "dirty [reset]         - show words stored since load  ",
+    "load-symbols file     - read addr=name symbol lines   ",
+    "trace json|text file  - log each instr to file        ",
+    "trace off             - stop logging instrs           ",
+    "trace-regs reg..|all  - trace writes to these regs   ",
+    "verify file           - check regs/memory against file",
+    "compare-spim file     - check regs against a SPIM dump",
+    "snap name             - save the registers as name    ",
+    "regdiff name          - show regs changed since snap  ",
+    "dump-all file         - write regs and nonzero memory ",
+    "goto-main [symbol]    - set PC to main or a symbol    ",
+    "info                  - one line machine status       ",
+    "exc                   - show the last exception       ",
+    "format plain|pretty   - choose how dumps are shown    ",
+    "rwatch [reg|clear]    - report writes to a register   ",
+    "uninit on|off         - warn on unwritten reg reads   ",
+    "hilo on|off           - warn on mfhi/mflo after mult  ",
+    "flags on|off|clear    - show add/sub flags in rdump   ",
+    "check-abi on|off      - check $s/$sp/$ra across calls ",
+    "stack [n]             - dump n words from $sp (16)    ",
+    "backtrace             - show the chain of calls to PC ",
+    "memstats              - show load/store statistics    ",
+    "reset                 - rewind to the loaded program  ",
+    "reset-stats           - zero counters and statistics  ",
+    "icache line sets      - enable the instruction cache  ",
+    "dcache line sets      - enable the data cache         ",
+    "miss-penalty n        - stall n cycles on a cache miss",
+    "cachestats            - show cache hits and misses    ",
+    "pipeline on|off       - count cycles on a 5-stage pipe",
+    "pipestats             - show pipeline stalls & flushes",
+    "branch-log on|off     - log every branch and jump     ",
+    "branches [summary]    - show the log or taken counts  ",
+    "load file             - load a program into text      ",
+    "set-endianness order  - binary word order, big|little ",
+    "limit n|off           - halt after n instructions     ",
+    "clean-exits [kind..]  - set exits scripts treat as ok ",
+    "kbd text              - queue a line of keyboard input",
+    "input reg_num reg_val - set GPR reg_num to reg_val    ",
+    "high value            - set the HI register to value  ",
+    "low value             - set the LO register to value  ",
+    "set target value      - set a reg, hi, lo or pc       ",
+    "? [command]           - display help, or command help ",
+    "quit                  - exit the program              ",
+];
 
 fn help() {
-    print!("----------------MIPS ISIM Help------------------------\n");
-    print!("go                    - run program to completion     \n");
-    print!("run n                 - execute program for n instrs  \n");
-    print!("step                  - execute program for 1 instr   \n");
-    print!("mdump low high        - dump memory from low to high  \n");
-    print!("rdump                 - dump the register & bus value \n");
-    print!("input reg_num reg_val - set GPR reg_num to reg_val    \n");
-    print!("high value            - set the HI register to value  \n");
-    print!("low value             - set the LO register to value  \n");
-    print!("?                     - display this help menu        \n");
-    print!("quit                  - exit the program              \n\n");
+    println!("----------------MIPS ISIM Help------------------------");
+    for line in HELP_LINES {
+        println!("{}", line);
+    }
+    println!();
+}
+
+// Syntax, examples and edge cases beyond the one line overview
+const HELP_DETAILS: &[(&str, &str)] = &[
+    (
+        "run",
+        "Executes n instructions, stopping early on a halt or breakpoint.
+n is decimal. Example: run 100",
+    ),
+    (
+        "back",
+        "Undoes the last n instructions, restoring registers and memory.
+Only the instructions kept by `history` can be undone, so n may be cut
+short. Example: back 3",
+    ),
+    (
+        "history",
+        "Keeps the last n instructions, n being decimal, so that back can
+undo them, or stops keeping any with off, the default. Example:
+history 1000",
+    ),
+    (
+        "until",
+        "Runs until PC reaches addr, a halt or a breakpoint.
+addr is hexadecimal, with or without a 0x prefix. Example: until 400010",
+    ),
+    (
+        "call",
+        "Calls the function at addr with up to 4 args in $a0-$a3, running
+until it returns, and prints $v0. PC and $ra are restored afterwards.
+addr is hexadecimal; args are decimal, or hexadecimal with 0x.
+Example: call 0x400020 5 0x10",
+    ),
+    (
+        "break",
+        "Stops a run when PC reaches addr. addr is hexadecimal, with or
+without a 0x prefix. Example: break 0x400008",
+    ),
+    (
+        "break-range",
+        "Stops a run when PC enters [low, high]. A loop inside the range
+does not stop again. Both bounds are hexadecimal.
+Example: break-range 0x400100 0x400140",
+    ),
+    (
+        "mdump",
+        "With two addresses, dumps the words from low to high inclusive.
+Addresses are hexadecimal, with or without a 0x prefix; reversed
+bounds are swapped, and unmapped words are shown as undefined.
+With no address, dumps 16 words around PC; with @reg, around the
+value of that register.
+Examples: mdump 0x10000000 0x10000020, mdump @sp",
+    ),
+    (
+        "words",
+        "Writes the words one after another from addr, which is
+hexadecimal and word aligned. Words are decimal, or hexadecimal with
+0x, and may be negative. Stops with an error at the first unmapped
+address, keeping the words written before it.
+Example: words 0x10000000 1 2 0xFF",
+    ),
+    (
+        "dis",
+        "Disassembles n instructions from addr, PC by default. addr is
+hexadecimal, n decimal. Example: dis 0x400000 4",
+    ),
+    (
+        "trace",
+        "Logs each retired instruction to file, as JSON lines with register
+and memory writes and cycle counts, or as text in the dis layout.
+Example: trace json out.jsonl",
+    ),
+    (
+        "trace-regs",
+        "Limits the register writes in traces to the given registers, by
+name or number, or all of them. Example: trace-regs $t0 $v0",
+    ),
+    (
+        "verify",
+        "Checks the `location = value` lines of file, eg, `$t0 = 0x5`,
+`hi = 3` or `[0x10000000] = 42`, reporting each as PASS or FAIL.
+The exit code is nonzero if any failed.",
+    ),
+    (
+        "compare-spim",
+        "Checks the GPRs, HI and LO against a register dump saved from
+SPIM or QtSpim, reporting each as PASS or FAIL. PC is not compared,
+as SPIM stops inside its exit code.",
+    ),
+    (
+        "snap",
+        "Saves the GPRs, HI and LO under name, replacing an earlier
+snapshot of that name. Compare later with regdiff.",
+    ),
+    (
+        "regdiff",
+        "Lists the GPRs, HI and LO that differ from snapshot name, with
+the saved and current values. Example: snap a, run 10, regdiff a",
+    ),
+    (
+        "check-abi",
+        "When on, checks $s0-$s7, $gp, $sp, $fp and $ra hold the same
+values after call or finish returns as on entry to the function,
+reporting violations. finish skips the check before the first jal.",
+    ),
+    (
+        "branches",
+        "Lists each logged branch and jump with its target, or with
+summary, how often each branch was taken. Needs branch-log on. Only
+the last 10000 are kept.",
+    ),
+    (
+        "backtrace",
+        "Lists the calls made with jal, jalr, bltzal and bgezal that have
+not returned, innermost first, with symbols if loaded. A jr to the
+return address of any of them returns from it and the calls inside
+it. Alias: bt",
+    ),
+    (
+        "load",
+        "Loads a program into memory and points PC at it. Raw binaries go
+to the text region; Intel HEX and S-record files to their
+addresses; .s and .asm sources are assembled, with .data going to
+the data region.",
+    ),
+    (
+        "clean-exits",
+        "Chooses which endings count as a clean exit when commands come
+from a --script: exit (syscall 10), return (main returning) and loop
+(a jump or branch to itself). A script then exits with status 2 if
+the program halted any other way, eg, on an exception, and with 1 if
+a verify failed. With no kinds, shows the current ones; all three
+count by default. Example: clean-exits exit return",
+    ),
+    (
+        "set-endianness",
+        "Sets the byte order flat binaries are read in, little by default.
+Words already loaded from flat binaries are byte-swapped, so they
+decode as if loaded with the new order. HEX, S-record and source
+loads are left alone, as is what the program already wrote to
+registers and memory. A source's .word data is laid out in the order
+set when it is loaded.",
+    ),
+    (
+        "limit",
+        "Halts after n more retired instructions, n being decimal, or
+removes the limit with off. Example: limit 1000",
+    ),
+    (
+        "input",
+        "Sets a GPR. Both the register number and value are hexadecimal,
+so input 10 5 sets $s0. Prefer set for decimal values and names.",
+    ),
+    (
+        "set",
+        "Sets a register by name, hi, lo or pc. value is decimal, or
+hexadecimal with 0x, and may be negative. Example: set $t0 -1",
+    ),
+    (
+        "icache",
+        "Models a direct-mapped instruction cache with line bytes per
+line and sets lines. Both are decimal and nonzero. Each miss adds
+the miss penalty to the cycle count. Example: icache 16 64",
+    ),
+    (
+        "dcache",
+        "Models a direct-mapped data cache in front of loads and stores,
+as icache does for fetches. Uncached kseg1 addresses and device
+registers bypass it. Example: dcache 16 64",
+    ),
+    (
+        "dirty",
+        "Lists the words the program stored to since it was loaded, with the
+value each held at load and the value it holds now. Stores that left a
+word unchanged are not shown. Loading a program or `dirty reset` starts
+over.",
+    ),
+    (
+        "reset",
+        "Rewinds to just after the program was loaded: memory it stored to
+gets its loaded contents back, registers and PC are restored and the
+counters and statistics are zeroed, as reset-stats does. Settings,
+breakpoints and symbols are kept.",
+    ),
+    (
+        "flags",
+        "Tracks whether the last add or subtract overflowed as a signed
+operation or carried (borrowed) as an unsigned one. Each add or
+subtract replaces the flags; `flags clear` or loading a program
+clears them. `flags on` shows them in rdump. Example: flags on",
+    ),
+];
+
+// The overview lines for `command`, followed by any details
+fn command_help(command: &str) {
+    println!("{}", command_help_text(command));
+}
+
+// The overview line for `command` followed by its details, if any
+fn command_help_text(command: &str) -> String {
+    let mut text = String::new();
+    for line in HELP_LINES {
+        if line.split_whitespace().next() == Some(command) {
+            text.push('\n');
+            text.push_str(line.trim_end());
+        }
+    }
+    if let Some((_, details)) = HELP_DETAILS.iter().find(|(name, _)| *name == command) {
+        text.push_str("\n\n");
+        text.push_str(details);
+    }
+    text.push('\n');
+    text
+}
+
+const COMMANDS: &[&str] = &[
+    "go",
+    "run",
+    "step",
+    "back",
+    "history",
+    "until",
+    "finish",
+    "call",
+    "break",
+    "break-range",
+    "breaks",
+    "clear-breaks",
+    "mdump",
+    "words",
+    "rdump",
+    "info",
+    "exc",
+    "goto-main",
+    "snap",
+    "regdiff",
+    "dump-all",
+    "verify",
+    "compare-spim",
+    "trace",
+    "trace-regs",
+    "dis",
+    "validate",
+    "dirty",
+    "load-symbols",
+    "format",
+    "rwatch",
+    "uninit",
+    "hilo",
+    "flags",
+    "check-abi",
+    "stack",
+    "backtrace",
+    "memstats",
+    "reset",
+    "reset-stats",
+    "icache",
+    "dcache",
+    "miss-penalty",
+    "cachestats",
+    "pipeline",
+    "pipestats",
+    "branch-log",
+    "branches",
+    "load",
+    "set-endianness",
+    "limit",
+    "clean-exits",
+    "kbd",
+    "input",
+    "high",
+    "low",
+    "set",
+    "?",
+    "quit",
+];
+
+// Short forms for the most used commands, which would otherwise be ambiguous
+const ALIASES: &[(&str, &str)] = &[
+    ("g", "go"),
+    ("r", "run"),
+    ("s", "step"),
+    ("q", "quit"),
+    ("bt", "backtrace"),
+];
+
+// Expands aliases and unambiguous prefixes into full command names. Unknown
+// tokens are returned as is; ambiguous ones yield the candidates.
+fn resolve_command(token: &str) -> Result<&str, Vec<&'static str>> {
+    if COMMANDS.contains(&token) {
+        return Ok(token);
+    }
+    if let Some((_, command)) = ALIASES.iter().find(|(alias, _)| *alias == token) {
+        return Ok(command);
+    }
+    let candidates: Vec<&'static str> = COMMANDS
+        .iter()
+        .copied()
+        .filter(|command| !token.is_empty() && command.starts_with(token))
+        .collect();
+    match candidates.len() {
+        0 => Ok(token),
+        1 => Ok(candidates[0]),
+        _ => Err(candidates),
+    }
+}
+
+fn edit_distance(first: &str, second: &str) -> usize {
+    let second: Vec<char> = second.chars().collect();
+    let mut prev: Vec<usize> = (0..=second.len()).collect();
+    for (i, a) in first.chars().enumerate() {
+        let mut curr = vec![i + 1; second.len() + 1];
+        for (j, b) in second.iter().enumerate() {
+            let subst = prev[j] + (a != *b) as usize;
+            curr[j + 1] = subst.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[second.len()]
+}
+
+fn invalid_command(token: &str) -> ShellError {
+    const MAX_DISTANCE: usize = 2;
+    let closest = COMMANDS
+        .iter()
+        .map(|command| (edit_distance(token, command), *command))
+        .min();
+    let suggestion = match closest {
+        Some((distance, command)) if distance <= MAX_DISTANCE => Some(command),
+        _ => None,
+    };
+    ShellError::UnknownCommand {
+        command: String::from(token),
+        suggestion,
+    }
 }
 
 fn parse_hex(inp: &str) -> Result<usize, ParseIntError> {
@@ -24,119 +421,1412 @@ fn parse_hex(inp: &str) -> Result<usize, ParseIntError> {
     usize::from_str_radix(inp, 16)
 }
 
-pub fn prompt(comp: &mut MipsComputer, dump_file: &mut File) -> io::Result<()> {
-    print!("MIPS-SIM> ");
-    io::stdout().flush()?;
-    let mut buf = String::new();
-    let bytes = io::stdin().read_line(&mut buf)?;
-    if bytes == 0 {
-        println!("Bye.");
-        exit(0);
-    }
-    buf = buf.trim_end().to_string();
-    println!("");
-
-    let parts: Vec<&str> = buf.split(" ").collect();
-    match parts[0] {
-        "go" => comp.go(),
-        "mdump" => {
-            if parts.len() < 3 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "mdump requires 2 params",
-                ));
-            }
-            let start: usize = match parse_hex(parts[1]) {
-                Ok(val) => val,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            let end: usize = match parse_hex(parts[2]) {
-                Ok(val) => val,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            comp.mdump(start, end, dump_file)?;
-        }
-        "?" => help(),
-        "quit" => {
-            println!("Bye.");
-            exit(0);
-        }
-        "rdump" => comp.rdump(dump_file)?,
-        "step" => comp.step(),
-        "run" => {
-            if parts.len() < 2 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "run requires 1 param",
-                ));
-            }
-            let cycles: u32 = match parts[1].parse() {
-                Ok(val) => val,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            comp.run(cycles);
-        }
-        "input" => {
-            if parts.len() < 3 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "input requires 2 params",
-                ));
-            }
-            let register_no: usize = match parse_hex(parts[1]) {
-                Ok(val) => val,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            let register_value: u32 = match parse_hex(parts[2]) {
-                Ok(val) => val as u32,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            comp.curr_state_mut().set_reg(register_no, register_value);
-            comp.next_state_mut().set_reg(register_no, register_value);
-        }
-        "high" => {
-            if parts.len() < 3 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "high requires 1 param",
-                ));
-            }
-            let high_reg_val: u32 = match parse_hex(parts[1]) {
-                Ok(val) => val as u32,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            comp.curr_state_mut().set_hi(high_reg_val);
-            comp.next_state_mut().set_hi(high_reg_val);
-        }
-        "low" => {
-            if parts.len() < 3 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "low requires 1 param",
-                ));
-            }
-            let low_reg_val: u32 = match parse_hex(parts[1]) {
-                Ok(val) => val as u32,
-                Err(e) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
-                }
-            };
-            comp.curr_state_mut().set_lo(low_reg_val);
-            comp.next_state_mut().set_lo(low_reg_val);
-        }
-        _ => println!("Invalid Command"),
+// Accepts hex with a 0x prefix, decimal otherwise, optionally negative
+fn parse_value(inp: &str) -> Result<u32, ParseIntError> {
+    let (neg, inp) = match inp.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, inp),
+    };
+    let val = match inp.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => inp.parse::<u32>()?,
+    };
+    Ok(if neg { val.wrapping_neg() } else { val })
+}
+
+// Why a command could not be carried out
+#[derive(Debug)]
+pub enum ShellError {
+    UnknownCommand {
+        command: String,
+        suggestion: Option<&'static str>,
+    },
+    AmbiguousCommand {
+        command: String,
+        candidates: Vec<&'static str>,
+    },
+    MissingArg {
+        command: String,
+        expected: usize,
+    },
+    BadNumber {
+        arg: String,
+        error: ParseIntError,
+    },
+    // An argument that is well-formed but not one the command accepts
+    InvalidArg(String),
+    // Some of the expectations given to `verify` did not hold
+    VerifyFailed {
+        failed: usize,
+    },
+    // Some of the registers in a SPIM dump given to `compare-spim` differed
+    CompareFailed {
+        failed: usize,
+    },
+    Io(io::Error),
+}
+
+impl ShellError {
+    fn bad_number(arg: &str, error: ParseIntError) -> Self {
+        ShellError::BadNumber {
+            arg: String::from(arg),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::UnknownCommand {
+                command,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "Invalid Command '{}', did you mean '{}'? Type ? for help.",
+                command, suggestion
+            ),
+            ShellError::UnknownCommand { command, .. } => {
+                write!(f, "Invalid Command '{}'. Type ? for help.", command)
+            }
+            ShellError::AmbiguousCommand {
+                command,
+                candidates,
+            } => write!(
+                f,
+                "Ambiguous command '{}', did you mean: {}",
+                command,
+                candidates.join(", ")
+            ),
+            ShellError::MissingArg { command, expected } => write!(
+                f,
+                "{} requires {} param{}",
+                command,
+                expected,
+                if *expected == 1 { "" } else { "s" }
+            ),
+            ShellError::BadNumber { arg, error } => {
+                write!(f, "'{}' is not a valid number: {}", arg, error)
+            }
+            ShellError::InvalidArg(msg) => write!(f, "{}", msg),
+            ShellError::VerifyFailed { failed } => {
+                write!(f, "verify found {} mismatch(es)", failed)
+            }
+            ShellError::CompareFailed { failed } => {
+                write!(f, "compare-spim found {} mismatch(es)", failed)
+            }
+            ShellError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShellError::BadNumber { error, .. } => Some(error),
+            ShellError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(e: io::Error) -> Self {
+        ShellError::Io(e)
+    }
+}
+
+// The error for `command` given fewer than its `expected` args
+fn missing_arg(command: &str, expected: usize) -> ShellError {
+    ShellError::MissingArg {
+        command: String::from(command),
+        expected,
+    }
+}
+
+// Checks that `parts` holds the command name followed by `expected` args
+fn require_args(command: &str, parts: &[&str], expected: usize) -> Result<(), ShellError> {
+    if parts.len() < expected + 1 {
+        return Err(missing_arg(command, expected));
     }
     Ok(())
 }
+
+// What a command did, so that code embedding the shell can inspect outcomes
+#[derive(Debug)]
+pub enum CommandResult {
+    // The simulator executed `cycles` instructions and is still running
+    Ran { cycles: u32 },
+    // The simulator is halted, whether it ran anything or not
+    Halted,
+    // Output was written to stdout and the dump file
+    Dumped,
+    // State was changed without simulating anything
+    Done,
+    Error(ShellError),
+    Quit,
+}
+
+fn ran(comp: &MipsComputer, start: u32) -> CommandResult {
+    if comp.halt_reason().is_some() {
+        CommandResult::Halted
+    } else {
+        CommandResult::Ran {
+            cycles: comp.instr_count() - start,
+        }
+    }
+}
+
+pub struct Shell {
+    comp: MipsComputer,
+    dump_file: File,
+    // Set once a `verify` fails, so that scripts exit with a nonzero status
+    verify_failed: bool,
+    // The halt reasons counting as a clean exit for scripts' exit status
+    clean_exits: Vec<HaltReason>,
+    // Set while a command runs, rather than the prompt waiting for input
+    busy: Arc<AtomicBool>,
+    // Where commands come from when run headless, from a script rather than
+    // typed at the prompt
+    script: Option<Box<dyn BufRead>>,
+}
+
+// The ways a program can end, as named by `clean-exits` and --clean-exits
+const EXIT_NAMES: &[(&str, HaltReason)] = &[
+    ("exit", HaltReason::Exit),
+    ("return", HaltReason::ReturnFromMain),
+    ("loop", HaltReason::SelfLoop),
+];
+
+pub fn parse_clean_exits(names: &[&str]) -> Result<Vec<HaltReason>, ShellError> {
+    names
+        .iter()
+        .map(|name| {
+            EXIT_NAMES
+                .iter()
+                .find(|(exit_name, _)| exit_name == name)
+                .map(|(_, reason)| *reason)
+                .ok_or_else(|| {
+                    ShellError::InvalidArg(format!(
+                        "Unknown exit {}, expected exit, return or loop",
+                        name
+                    ))
+                })
+        })
+        .collect()
+}
+
+impl Shell {
+    pub fn new(comp: MipsComputer, dump_file: File) -> Self {
+        Self {
+            comp,
+            dump_file,
+            verify_failed: false,
+            clean_exits: EXIT_NAMES.iter().map(|(_, reason)| *reason).collect(),
+            busy: Arc::new(AtomicBool::new(false)),
+            script: None,
+        }
+    }
+
+    // Reads commands from `script` rather than stdin, exiting with a status
+    // telling how the program ended once it runs out
+    pub fn set_script(&mut self, script: Box<dyn BufRead>) {
+        self.script = Some(script);
+    }
+
+    // A flag set while a command runs and clear while the prompt waits,
+    // so that a signal handler can tell whether there is a run to stop
+    pub fn busy_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.busy)
+    }
+
+    pub fn set_clean_exits(&mut self, reasons: Vec<HaltReason>) {
+        self.clean_exits = reasons;
+    }
+
+    fn exit(&self) -> ! {
+        println!("Bye.");
+        exit(self.exit_status());
+    }
+
+    // Scripts exit with 1 if a verify failed, or 2 if the program halted
+    // other than by a clean exit, eg, on an exception. A program that is
+    // still running counts as clean. Interactive sessions only fail on a
+    // failed verify.
+    fn exit_status(&self) -> i32 {
+        let unclean = self.script.is_some()
+            && self
+                .comp
+                .halt_reason()
+                .is_some_and(|reason| !self.clean_exits.contains(&reason));
+        if self.verify_failed {
+            1
+        } else if unclean {
+            2
+        } else {
+            0
+        }
+    }
+
+    pub fn comp(&self) -> &MipsComputer {
+        &self.comp
+    }
+
+    pub fn comp_mut(&mut self) -> &mut MipsComputer {
+        &mut self.comp
+    }
+
+    // Reads a line from stdin, runs it and renders the result
+    pub fn prompt(&mut self) -> io::Result<()> {
+        print!("MIPS-SIM> ");
+        io::stdout().flush()?;
+        let mut buf = String::new();
+        let bytes = match &mut self.script {
+            Some(script) => script.read_line(&mut buf)?,
+            None => io::stdin().read_line(&mut buf)?,
+        };
+        if bytes == 0 {
+            self.exit();
+        }
+        println!();
+        self.busy.store(true, Ordering::SeqCst);
+        let result = self.execute_command(&buf);
+        self.busy.store(false, Ordering::SeqCst);
+        match result {
+            CommandResult::Error(e) => {
+                println!("Error: {}\n", e);
+                Ok(())
+            }
+            CommandResult::Quit => self.exit(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn execute_command(&mut self, line: &str) -> CommandResult {
+        let line = line.trim();
+        // Blank lines and comments let command scripts be annotated
+        if line.is_empty() || line.starts_with('#') {
+            return CommandResult::Done;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let command = match resolve_command(parts[0]) {
+            Ok(command) => command,
+            Err(candidates) => {
+                return CommandResult::Error(ShellError::AmbiguousCommand {
+                    command: String::from(parts[0]),
+                    candidates,
+                });
+            }
+        };
+        match self.dispatch(command, &parts) {
+            Ok(result) => result,
+            Err(e) => CommandResult::Error(e),
+        }
+    }
+
+    fn dispatch(&mut self, command: &str, parts: &[&str]) -> Result<CommandResult, ShellError> {
+        let comp = &mut self.comp;
+        let dump_file = &mut self.dump_file;
+        let start = comp.instr_count();
+        match command {
+            "go" => {
+                comp.go();
+                return Ok(ran(comp, start));
+            }
+            "mdump" if parts.len() < 3 => {
+                // Without explicit bounds, dump a window centered on PC or `@reg`
+                const WINDOW_WORDS: usize = 16;
+                let center = match parts.get(1) {
+                    None => comp.curr_state().pc(),
+                    Some(arg) => {
+                        // A lone address is a start without its stop
+                        let reg = match arg.strip_prefix('@') {
+                            Some(name) => parse_reg(name),
+                            None => return Err(missing_arg(command, 2)),
+                        };
+                        match reg.and_then(|reg| comp.curr_state().get_reg(reg as usize)) {
+                            Some(val) => val,
+                            None => {
+                                return Err(ShellError::InvalidArg(String::from(
+                                    "mdump requires 2 params, or none, or @reg",
+                                )));
+                            }
+                        }
+                    }
+                } as usize;
+                let start = (center & !0x3).saturating_sub(WINDOW_WORDS / 2 * 4);
+                let end = start + (WINDOW_WORDS - 1) * 4;
+                comp.mdump(start, end, dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "mdump" => {
+                let start: usize =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))?;
+                let end: usize =
+                    parse_hex(parts[2]).map_err(|e| ShellError::bad_number(parts[2], e))?;
+                comp.mdump(start, end, dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "words" => {
+                require_args(command, parts, 2)?;
+                let address =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))? as u32;
+                let mut words = Vec::new();
+                for arg in &parts[2..] {
+                    words.push(parse_value(arg).map_err(|e| ShellError::bad_number(arg, e))?);
+                }
+                comp.write_words(address, &words)?;
+            }
+            "?" => {
+                match parts.get(1) {
+                    None => help(),
+                    Some(name) => match resolve_command(name) {
+                        Ok(name) if COMMANDS.contains(&name) => command_help(name),
+                        Ok(name) => return Err(invalid_command(name)),
+                        Err(candidates) => {
+                            return Err(ShellError::AmbiguousCommand {
+                                command: String::from(*name),
+                                candidates,
+                            });
+                        }
+                    },
+                }
+                return Ok(CommandResult::Dumped);
+            }
+            "quit" => return Ok(CommandResult::Quit),
+            "rdump" => {
+                comp.rdump(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "format" => match parts.get(1) {
+                Some(&"plain") => comp.set_dump_format(DumpFormat::Plain),
+                Some(&"pretty") => comp.set_dump_format(DumpFormat::Pretty),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "format requires plain or pretty",
+                    )));
+                }
+            },
+            "dis" => {
+                let start = match parts.get(1) {
+                    None => comp.pc(),
+                    Some(arg) => parse_hex(arg).map_err(|e| ShellError::bad_number(arg, e))? as u32,
+                };
+                let count: usize = match parts.get(2) {
+                    None => 8,
+                    Some(arg) => arg.parse().map_err(|e| ShellError::bad_number(arg, e))?,
+                };
+                comp.dis(start, count, dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "validate" => {
+                comp.validate(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "dirty" => match parts.get(1) {
+                None => {
+                    comp.dirty(dump_file)?;
+                    return Ok(CommandResult::Dumped);
+                }
+                Some(&"reset") => comp.clear_dirty(),
+                Some(_) => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "dirty takes no argument or reset",
+                    )));
+                }
+            },
+            "load-symbols" => {
+                require_args(command, parts, 1)?;
+                comp.load_symbols(parts[1])?;
+            }
+            "trace" => match (parts.get(1), parts.get(2)) {
+                (Some(&"off"), _) => comp.set_trace_sink(None),
+                (Some(&"json"), Some(path)) => {
+                    let file = File::create(path)?;
+                    comp.set_trace_sink(Some(Box::new(JsonLinesSink::new(file))));
+                }
+                (Some(&"text"), Some(path)) => {
+                    let file = File::create(path)?;
+                    comp.set_trace_sink(Some(Box::new(TextSink::new(file))));
+                }
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "trace requires json file, text file or off",
+                    )));
+                }
+            },
+            "trace-regs" => match parts.get(1) {
+                None => {
+                    let names: Vec<String> = (0..MIPS_REGS)
+                        .filter(|reg| comp.trace_regs() & (1 << reg) != 0)
+                        .map(|reg| format!("${}", REG_NAMES[reg]))
+                        .collect();
+                    println!("Tracing writes to: {}\n", names.join(" "));
+                    return Ok(CommandResult::Dumped);
+                }
+                Some(&"all") => comp.set_trace_regs(u32::MAX),
+                Some(_) => {
+                    let mut mask = 0;
+                    for target in &parts[1..] {
+                        match parse_reg(target) {
+                            Some(reg) => mask |= 1 << reg,
+                            None => {
+                                return Err(ShellError::InvalidArg(format!(
+                                    "Unknown register {}",
+                                    target
+                                )));
+                            }
+                        }
+                    }
+                    comp.set_trace_regs(mask);
+                }
+            },
+            "verify" => {
+                require_args(command, parts, 1)?;
+                let failed = comp.verify(parts[1], dump_file)?;
+                if failed > 0 {
+                    self.verify_failed = true;
+                    return Err(ShellError::VerifyFailed { failed });
+                }
+                return Ok(CommandResult::Dumped);
+            }
+            "compare-spim" => {
+                require_args(command, parts, 1)?;
+                let failed = comp.compare_spim(parts[1], dump_file)?;
+                if failed > 0 {
+                    self.verify_failed = true;
+                    return Err(ShellError::CompareFailed { failed });
+                }
+                return Ok(CommandResult::Dumped);
+            }
+            "dump-all" => {
+                require_args(command, parts, 1)?;
+                let mut file = File::create(parts[1])?;
+                comp.dump_all(&mut file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "goto-main" => {
+                let name = parts.get(1).copied().unwrap_or("main");
+                let address = match comp.symbols().address_of(name) {
+                    Some(address) => address,
+                    None => {
+                        return Err(ShellError::InvalidArg(format!(
+                            "No symbol named {}, use load-symbols first",
+                            name
+                        )));
+                    }
+                };
+                comp.curr_state_mut().set_pc(address);
+                comp.next_state_mut().set_pc(address);
+                println!("PC set to {:#010X} <{}>\n", address, name);
+            }
+            "info" => {
+                comp.info(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "exc" => {
+                comp.exc(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "reset" => comp.reset(),
+            "reset-stats" => comp.reset_stats(),
+            "memstats" => {
+                comp.memstats(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "cachestats" => {
+                comp.cachestats(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "pipeline" => match parts.get(1) {
+                Some(&"on") => comp.set_pipeline(true),
+                Some(&"off") => comp.set_pipeline(false),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "pipeline requires on or off",
+                    )));
+                }
+            },
+            "pipestats" => {
+                comp.pipestats(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "branch-log" => match parts.get(1) {
+                Some(&"on") => comp.set_branch_log(true),
+                Some(&"off") => comp.set_branch_log(false),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "branch-log requires on or off",
+                    )));
+                }
+            },
+            "branches" => {
+                let summary = match parts.get(1) {
+                    None => false,
+                    Some(&"summary") => true,
+                    Some(arg) => {
+                        return Err(ShellError::InvalidArg(format!(
+                            "Unknown branches option {}",
+                            arg
+                        )));
+                    }
+                };
+                comp.branches(summary, dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "icache" | "dcache" => {
+                require_args(command, parts, 2)?;
+                let line_size: usize = parts[1]
+                    .parse()
+                    .map_err(|e| ShellError::bad_number(parts[1], e))?;
+                let num_sets: usize = parts[2]
+                    .parse()
+                    .map_err(|e| ShellError::bad_number(parts[2], e))?;
+                if line_size == 0 || num_sets == 0 {
+                    return Err(ShellError::InvalidArg(format!(
+                        "{} geometry must be non-zero",
+                        command
+                    )));
+                }
+                if command == "icache" {
+                    comp.enable_icache(line_size, num_sets);
+                } else {
+                    comp.enable_dcache(line_size, num_sets);
+                }
+            }
+            "miss-penalty" => {
+                require_args(command, parts, 1)?;
+                let penalty = parts[1]
+                    .parse()
+                    .map_err(|e| ShellError::bad_number(parts[1], e))?;
+                let costs = CycleCosts {
+                    miss_penalty: penalty,
+                    ..*comp.cycle_costs()
+                };
+                comp.set_cycle_costs(costs);
+            }
+            "rwatch" => match parts.get(1) {
+                None => {
+                    let names: Vec<String> = comp
+                        .reg_watches()
+                        .iter()
+                        .map(|reg| format!("${}", REG_NAMES[*reg]))
+                        .collect();
+                    println!("Watching: {}\n", names.join(" "));
+                    return Ok(CommandResult::Dumped);
+                }
+                Some(&"clear") => comp.clear_reg_watches(),
+                Some(target) => match parse_reg(target) {
+                    Some(reg) => {
+                        comp.watch_reg(reg as usize);
+                    }
+                    None => {
+                        return Err(ShellError::InvalidArg(format!(
+                            "Unknown register {}",
+                            target
+                        )));
+                    }
+                },
+            },
+            "uninit" => match parts.get(1) {
+                Some(&"on") => comp.set_uninit_check(true),
+                Some(&"off") => comp.set_uninit_check(false),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "uninit requires on or off",
+                    )));
+                }
+            },
+            "hilo" => match parts.get(1) {
+                Some(&"on") => comp.set_hilo_check(true),
+                Some(&"off") => comp.set_hilo_check(false),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "hilo requires on or off",
+                    )));
+                }
+            },
+            "flags" => match parts.get(1) {
+                Some(&"on") => comp.set_show_flags(true),
+                Some(&"off") => comp.set_show_flags(false),
+                Some(&"clear") => comp.clear_arith_flags(),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "flags requires on, off or clear",
+                    )));
+                }
+            },
+            "check-abi" => match parts.get(1) {
+                Some(&"on") => comp.set_abi_check(true),
+                Some(&"off") => comp.set_abi_check(false),
+                _ => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "check-abi requires on or off",
+                    )));
+                }
+            },
+            "backtrace" => {
+                comp.backtrace(dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "stack" => {
+                let count: usize = if parts.len() < 2 {
+                    16
+                } else {
+                    parts[1]
+                        .parse()
+                        .map_err(|e| ShellError::bad_number(parts[1], e))?
+                };
+                comp.stack(count, dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "step" => {
+                comp.step();
+                return Ok(ran(comp, start));
+            }
+            "back" => {
+                let count: usize = match parts.get(1) {
+                    None => 1,
+                    Some(arg) => arg.parse().map_err(|e| ShellError::bad_number(arg, e))?,
+                };
+                if comp.config().history_depth == 0 {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "no undo history is kept, enable it with history n",
+                    )));
+                }
+                let undone = comp.back(count);
+                println!("Undid {} instr(s), PC is {:#010X}\n", undone, comp.pc());
+            }
+            "call" => {
+                require_args(command, parts, 1)?;
+                let address =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))? as u32;
+                if parts.len() > 6 {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "call takes at most 4 args",
+                    )));
+                }
+                let mut args = Vec::new();
+                for arg in &parts[2..] {
+                    args.push(parse_value(arg).map_err(|e| ShellError::bad_number(arg, e))?);
+                }
+                if let Some(v0) = comp.call(address, &args) {
+                    println!("$v0 = {:#010X} ({})\n", v0, v0 as i32);
+                }
+                return Ok(ran(comp, start));
+            }
+            "snap" => {
+                require_args(command, parts, 1)?;
+                comp.snapshot(parts[1]);
+            }
+            "regdiff" => {
+                require_args(command, parts, 1)?;
+                comp.regdiff(parts[1], dump_file)?;
+                return Ok(CommandResult::Dumped);
+            }
+            "finish" => {
+                comp.finish();
+                return Ok(ran(comp, start));
+            }
+            "until" => {
+                require_args(command, parts, 1)?;
+                let address: usize =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))?;
+                comp.run_until(address as u32);
+                return Ok(ran(comp, start));
+            }
+            "set-endianness" => {
+                let endianness = match parts.get(1) {
+                    Some(&"big") => Endianness::Big,
+                    Some(&"little") => Endianness::Little,
+                    _ => {
+                        return Err(ShellError::InvalidArg(String::from(
+                            "set-endianness requires big or little",
+                        )));
+                    }
+                };
+                let swapped = comp.set_endianness(endianness);
+                println!("Byte-swapped {} word(s) loaded from binaries", swapped);
+                println!("Warning: registers and data already written are unaffected\n");
+            }
+            "load" => {
+                require_args(command, parts, 1)?;
+                comp.load_program(parts[1])?;
+            }
+            "break" | "break-range" => {
+                let count = if command == "break" { 1 } else { 2 };
+                require_args(command, parts, count)?;
+                let low =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))? as u32;
+                let high = parse_hex(parts[count])
+                    .map_err(|e| ShellError::bad_number(parts[count], e))?
+                    as u32;
+                if !comp.add_breakpoint(low, high) {
+                    println!("Already covered by an existing breakpoint\n");
+                }
+            }
+            "breaks" => {
+                for (idx, (low, high)) in comp.breakpoints().iter().enumerate() {
+                    if low == high {
+                        println!("{}: {:#010X}", idx, low);
+                    } else {
+                        println!("{}: {:#010X}..={:#010X}", idx, low, high);
+                    }
+                }
+                println!();
+                return Ok(CommandResult::Dumped);
+            }
+            "clear-breaks" => comp.clear_breakpoints(),
+            "clean-exits" => {
+                if parts.len() < 2 {
+                    let names: Vec<&str> = EXIT_NAMES
+                        .iter()
+                        .filter(|(_, reason)| self.clean_exits.contains(reason))
+                        .map(|(name, _)| *name)
+                        .collect();
+                    println!("Clean exits: {}\n", names.join(" "));
+                    return Ok(CommandResult::Dumped);
+                }
+                self.clean_exits = parse_clean_exits(&parts[1..])?;
+            }
+            "history" => match parts.get(1) {
+                Some(&"off") => comp.set_history_depth(0),
+                Some(arg) => {
+                    let depth = arg.parse().map_err(|e| ShellError::bad_number(arg, e))?;
+                    comp.set_history_depth(depth);
+                }
+                None => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "history requires a count or off",
+                    )));
+                }
+            },
+            "limit" => match parts.get(1) {
+                Some(&"off") => comp.set_max_instructions(None),
+                Some(arg) => {
+                    let max = arg.parse().map_err(|e| ShellError::bad_number(arg, e))?;
+                    comp.set_max_instructions(Some(max));
+                }
+                None => {
+                    return Err(ShellError::InvalidArg(String::from(
+                        "limit requires a count or off",
+                    )));
+                }
+            },
+            "kbd" => {
+                let mut line = parts[1..].join(" ");
+                line.push('\n');
+                comp.push_keyboard_input(line.as_bytes());
+            }
+            "run" => {
+                require_args(command, parts, 1)?;
+                let cycles: u32 = parts[1]
+                    .parse()
+                    .map_err(|e| ShellError::bad_number(parts[1], e))?;
+                comp.run(cycles);
+                return Ok(ran(comp, start));
+            }
+            "input" => {
+                require_args(command, parts, 2)?;
+                let register_no: usize =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))?;
+                let register_value: u32 =
+                    parse_hex(parts[2]).map_err(|e| ShellError::bad_number(parts[2], e))? as u32;
+                comp.curr_state_mut().set_reg(register_no, register_value);
+                comp.next_state_mut().set_reg(register_no, register_value);
+            }
+            "high" => {
+                require_args(command, parts, 1)?;
+                let high_reg_val: u32 =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))? as u32;
+                comp.curr_state_mut().set_hi(high_reg_val);
+                comp.next_state_mut().set_hi(high_reg_val);
+            }
+            "low" => {
+                require_args(command, parts, 1)?;
+                let low_reg_val: u32 =
+                    parse_hex(parts[1]).map_err(|e| ShellError::bad_number(parts[1], e))? as u32;
+                comp.curr_state_mut().set_lo(low_reg_val);
+                comp.next_state_mut().set_lo(low_reg_val);
+            }
+            "set" => {
+                require_args(command, parts, 2)?;
+                let value: u32 =
+                    parse_value(parts[2]).map_err(|e| ShellError::bad_number(parts[2], e))?;
+                match parts[1] {
+                    "pc" => {
+                        comp.curr_state_mut().set_pc(value);
+                        comp.next_state_mut().set_pc(value);
+                    }
+                    "hi" => {
+                        comp.curr_state_mut().set_hi(value);
+                        comp.next_state_mut().set_hi(value);
+                    }
+                    "lo" => {
+                        comp.curr_state_mut().set_lo(value);
+                        comp.next_state_mut().set_lo(value);
+                    }
+                    target => {
+                        let reg = match parse_reg(target) {
+                            Some(reg) => reg as usize,
+                            None => {
+                                return Err(ShellError::InvalidArg(format!(
+                                    "Unknown register {}",
+                                    target
+                                )));
+                            }
+                        };
+                        comp.curr_state_mut().set_reg(reg, value);
+                        comp.next_state_mut().set_reg(reg, value);
+                    }
+                }
+            }
+            _ => return Err(invalid_command(command)),
+        }
+        Ok(CommandResult::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build, temp_path};
+    use std::fs;
+    use std::path::PathBuf;
+
+    const NOP: &str = "sll $zero, $zero, 0\n";
+
+    // A shell around `src`, and the path of the dump file it writes
+    fn shell_with_dump(src: &str) -> (Shell, PathBuf) {
+        let comp = build(src);
+        let path = temp_path("");
+        let dump_file = File::create(&path).expect("Cannot create dump file");
+        (Shell::new(comp, dump_file), path)
+    }
+
+    fn shell(src: &str) -> Shell {
+        shell_with_dump(src).0
+    }
+
+    // What `line` wrote to the dump file
+    fn dumped(shell: &mut Shell, path: &PathBuf, line: &str) -> String {
+        let before = fs::read_to_string(path).unwrap().len();
+        let result = shell.execute_command(line);
+        assert!(
+            matches!(result, CommandResult::Dumped),
+            "{} gave {:?}",
+            line,
+            result
+        );
+        fs::read_to_string(path).unwrap()[before..].to_string()
+    }
+
+    #[test]
+    fn set_moves_pc_and_writes_registers() {
+        let (mut shell, path) = shell_with_dump(&NOP.repeat(5));
+        assert!(matches!(
+            shell.execute_command("set pc 0x00400010"),
+            CommandResult::Done
+        ));
+        assert!(matches!(
+            shell.execute_command("set $t0 -1"),
+            CommandResult::Done
+        ));
+        let regs = dumped(&mut shell, &path, "rdump");
+        assert!(regs.contains("PC                : 0x00400010"), "{}", regs);
+        assert!(regs.contains("R8: 0xFFFFFFFF"), "{}", regs);
+        // Execution carries on from the new PC
+        shell.execute_command("step");
+        let regs = dumped(&mut shell, &path, "rdump");
+        assert!(regs.contains("PC                : 0x00400014"), "{}", regs);
+        assert!(regs.contains("R8: 0xFFFFFFFF"), "{}", regs);
+    }
+
+    #[test]
+    fn mdump_without_args_centers_on_pc() {
+        let (mut shell, path) = shell_with_dump(&NOP.repeat(3));
+        shell.execute_command("set pc 0x00400040");
+        let out = dumped(&mut shell, &path, "mdump");
+        let addresses: Vec<&str> = out
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("0x"))
+            .map(|line| &line[..8])
+            .collect();
+        assert_eq!(addresses.len(), 16);
+        assert_eq!(addresses[0], "00400020");
+        assert_eq!(addresses[8], "00400040");
+        assert_eq!(addresses[15], "0040005C");
+    }
+
+    #[test]
+    fn input_accepts_tabs_and_repeated_spaces() {
+        let (mut shell, path) = shell_with_dump(NOP);
+        let result = shell.execute_command("input\t8\t2A\n");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        let result = shell.execute_command("  input   9  \t FF  ");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        let regs = dumped(&mut shell, &path, "rdump");
+        assert!(regs.contains("R8: 0x0000002A"), "{}", regs);
+        assert!(regs.contains("R9: 0x000000FF"), "{}", regs);
+    }
+
+    #[test]
+    fn abbreviations_resolve_to_commands() {
+        let (mut shell, path) = shell_with_dump(&NOP.repeat(8));
+        assert!(matches!(
+            shell.execute_command("s"),
+            CommandResult::Ran { cycles: 1 }
+        ));
+        assert!(matches!(
+            shell.execute_command("r 5"),
+            CommandResult::Ran { cycles: 5 }
+        ));
+        let regs = dumped(&mut shell, &path, "rdump");
+        assert!(regs.contains("Instruction count : 6"), "{}", regs);
+        assert_eq!(resolve_command("s"), Ok("step"));
+        assert_eq!(resolve_command("r"), Ok("run"));
+        assert_eq!(resolve_command("?"), Ok("?"));
+        assert!(matches!(shell.execute_command("?"), CommandResult::Dumped));
+    }
+
+    #[test]
+    fn unknown_command_suggests_the_closest_one() {
+        let mut shell = shell(NOP);
+        match shell.execute_command("gp") {
+            CommandResult::Error(e) => {
+                let message = e.to_string();
+                assert!(message.contains("'gp'"), "{}", message);
+                assert!(message.contains("'go'"), "{}", message);
+                assert!(message.contains("?"), "{}", message);
+            }
+            other => panic!("expected a suggestion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_no_ops() {
+        let (mut shell, path) = shell_with_dump(NOP);
+        for line in ["# run 5 cycles", "   # indented", "", "  \t\n"] {
+            let result = shell.execute_command(line);
+            assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        }
+        let regs = dumped(&mut shell, &path, "rdump");
+        assert!(regs.contains("Instruction count : 0"), "{}", regs);
+    }
+
+    #[test]
+    fn run_returns_a_structured_result() {
+        let mut shell = shell(&(NOP.repeat(4) + "addiu $v0, $zero, 10\nsyscall\n"));
+        match shell.execute_command("run 3") {
+            CommandResult::Ran { cycles } => assert_eq!(cycles, 3),
+            other => panic!("expected Ran, got {:?}", other),
+        }
+        assert!(matches!(
+            shell.execute_command("run 10"),
+            CommandResult::Halted
+        ));
+        assert!(matches!(shell.execute_command("quit"), CommandResult::Quit));
+    }
+
+    #[test]
+    fn info_shows_pc_and_the_next_instruction() {
+        let (mut shell, path) = shell_with_dump("sll $zero, $zero, 0\naddiu $t0, $zero, 5\n");
+        shell.execute_command("step");
+        let out = dumped(&mut shell, &path, "info");
+        assert!(out.contains("PC 0x00400004"), "{}", out);
+        assert!(out.contains("addiu $t0, $zero, 5"), "{}", out);
+    }
+
+    #[test]
+    fn load_reads_a_program_after_startup() {
+        let path = temp_path(".bin");
+        // addiu $t0, $zero, 5 followed by addu $t2, $t0, $t1
+        let words: Vec<u8> = [0x24080005_u32, 0x01095021]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        fs::write(&path, words).unwrap();
+        let (mut shell, dump) = shell_with_dump("");
+        let line = format!("load {}", path.display());
+        let result = shell.execute_command(&line);
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        let out = dumped(&mut shell, &dump, "info");
+        assert!(out.contains("PC 0x00400000"), "{}", out);
+        assert!(out.contains("addiu $t0, $zero, 5"), "{}", out);
+    }
+
+    #[test]
+    fn dump_all_shows_a_written_data_word() {
+        let mut shell = shell(
+            "lui $t0, 0x1000
+            addiu $t1, $zero, 0x1234
+            sw $t1, 8($t0)",
+        );
+        shell.execute_command("go");
+        let path = temp_path(".dump");
+        let line = format!("dump-all {}", path.display());
+        let result = shell.execute_command(&line);
+        assert!(matches!(result, CommandResult::Dumped), "{:?}", result);
+        let out = fs::read_to_string(&path).unwrap();
+        assert!(out.contains("Region data"), "{}", out);
+        assert!(out.contains("0x10000008 : 0x00001234"), "{}", out);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn goto_main_moves_pc_to_the_symbol() {
+        let mut shell = shell("sll $zero, $zero, 0\nsll $zero, $zero, 0\nsll $zero, $zero, 0\n");
+        let result = shell.execute_command("goto-main");
+        assert!(
+            matches!(result, CommandResult::Error(ShellError::InvalidArg(_))),
+            "{:?}",
+            result
+        );
+        let main = MEM_TEXT_START as u32 + 8;
+        shell.comp_mut().symbols_mut().insert(main, "main");
+        let result = shell.execute_command("goto-main");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        assert_eq!(shell.comp().pc(), main);
+    }
+
+    #[test]
+    fn a_range_breakpoint_stops_on_entering_a_loop_body() {
+        let mut shell = shell(
+            "addiu $t0, $zero, 3
+            sll $zero, $zero, 0
+            loop: addiu $t0, $t0, -1
+            addiu $t1, $t1, 1
+            bne $t0, $zero, loop",
+        );
+        let result = shell.execute_command("break-range 0x400008 0x400010");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        // A point breakpoint inside the range adds nothing
+        shell.execute_command("break 0x40000C");
+        assert_eq!(shell.comp().breakpoints().len(), 1);
+        shell.execute_command("go");
+        assert_eq!(shell.comp().pc(), MEM_TEXT_START as u32 + 8);
+        assert_eq!(shell.comp().instr_count(), 2);
+        assert_eq!(shell.comp().registers()[9], 0);
+    }
+
+    #[test]
+    fn reset_stats_only_clears_counters() {
+        let mut shell = shell(
+            "loop: addiu $t0, $t0, 1
+            b loop",
+        );
+        shell.execute_command("run 5");
+        assert!(matches!(
+            shell.execute_command("reset-stats"),
+            CommandResult::Done
+        ));
+        assert_eq!(shell.comp().instr_count(), 0);
+        assert_eq!(shell.comp().registers()[8], 3);
+        shell.execute_command("run 3");
+        assert_eq!(shell.comp().instr_count(), 3);
+        assert_eq!(shell.comp().registers()[8], 4);
+    }
+
+    #[test]
+    fn value_commands_need_exactly_their_args() {
+        let mut shell = shell("nop\nnop\n");
+        for (valid, too_few, expected) in [
+            ("run 1", "run", 1),
+            ("input 8 5", "input 8", 2),
+            ("high 7", "high", 1),
+            ("low 9", "low", 1),
+        ] {
+            let result = shell.execute_command(valid);
+            assert!(
+                !matches!(result, CommandResult::Error(_)),
+                "{} gave {:?}",
+                valid,
+                result
+            );
+            match shell.execute_command(too_few) {
+                CommandResult::Error(ShellError::MissingArg {
+                    command,
+                    expected: got,
+                }) => {
+                    assert!(too_few.starts_with(&command));
+                    assert_eq!(got, expected, "{}", too_few);
+                }
+                other => panic!("{} gave {:?}", too_few, other),
+            }
+        }
+        let state = shell.comp().curr_state();
+        assert_eq!((state.get_reg(8), state.hi(), state.lo()), (Some(5), 7, 9));
+    }
+
+    #[test]
+    fn exc_shows_the_faulting_address() {
+        let (mut shell, path) = shell_with_dump(
+            "lui $t0, 0x1000
+            lw $t1, -8($t0)",
+        );
+        shell.execute_command("go");
+        let out = dumped(&mut shell, &path, "exc");
+        assert!(
+            out.contains("Cause             : AddressErrorLoad"),
+            "{}",
+            out
+        );
+        assert!(out.contains("EPC               : 0x00400004"), "{}", out);
+        assert!(out.contains("BadVAddr          : 0x0FFFFFF8"), "{}", out);
+        assert!(out.contains("Handler           : none"), "{}", out);
+    }
+
+    #[test]
+    fn verify_passes_and_fails_expectation_files() {
+        let (mut shell, path) = shell_with_dump(
+            "addiu $t0, $zero, 5
+            lui $t1, 0x1000
+            addiu $t2, $zero, 42
+            sw $t2, 0($t1)",
+        );
+        shell.execute_command("go");
+        let passing = temp_path(".expect");
+        fs::write(&passing, "$t0 = 0x5\n[0x10000000] = 42\n").unwrap();
+        let out = dumped(&mut shell, &path, &format!("verify {}", passing.display()));
+        assert_eq!(out.matches("PASS").count(), 2, "{}", out);
+        assert!(!shell.verify_failed);
+
+        let failing = temp_path(".expect");
+        fs::write(&failing, "$t0 = 0x5\n$t2 = 41\n").unwrap();
+        let before = fs::read_to_string(&path).unwrap().len();
+        let result = shell.execute_command(&format!("verify {}", failing.display()));
+        assert!(
+            matches!(
+                result,
+                CommandResult::Error(ShellError::VerifyFailed { failed: 1 })
+            ),
+            "{:?}",
+            result
+        );
+        let out = fs::read_to_string(&path).unwrap()[before..].to_string();
+        assert_eq!(out.matches("FAIL").count(), 1, "{}", out);
+        assert!(shell.verify_failed);
+        fs::remove_file(&passing).unwrap();
+        fs::remove_file(&failing).unwrap();
+    }
+
+    #[test]
+    fn compare_spim_reports_its_own_mismatches() {
+        let mut shell = shell("addiu $t0, $zero, 10\n");
+        shell.execute_command("go");
+        let dump = temp_path(".spim");
+        fs::write(&dump, "R8  [t0] = 0000000b  R9  [t1] = 00000000\n").unwrap();
+        let result = shell.execute_command(&format!("compare-spim {}", dump.display()));
+        fs::remove_file(&dump).unwrap();
+        match result {
+            CommandResult::Error(e @ ShellError::CompareFailed { failed: 1 }) => {
+                assert_eq!(e.to_string(), "compare-spim found 1 mismatch(es)");
+            }
+            result => panic!("{:?}", result),
+        }
+        assert!(shell.verify_failed);
+    }
+
+    #[test]
+    fn regdiff_flags_exactly_the_clobbered_register() {
+        let (mut shell, path) = shell_with_dump(
+            "addiu $s0, $zero, 1
+            addiu $t0, $zero, 2
+            addu $t0, $t0, $zero
+            addiu $s0, $zero, 9",
+        );
+        shell.execute_command("run 2");
+        shell.execute_command("snap a");
+        let out = dumped(&mut shell, &path, "regdiff a");
+        assert!(out.contains("No registers changed"), "{}", out);
+        shell.execute_command("go");
+        let out = dumped(&mut shell, &path, "regdiff a");
+        let lines: Vec<&str> = out.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines, ["$s0   : 0x00000001 -> 0x00000009"]);
+    }
+
+    #[test]
+    fn help_for_mdump_gives_the_address_syntax() {
+        let mut shell = shell("nop\n");
+        assert!(matches!(
+            shell.execute_command("? mdump"),
+            CommandResult::Dumped
+        ));
+        let text = command_help_text("mdump");
+        assert!(text.starts_with("\nmdump "), "{}", text);
+        assert!(text.contains("hexadecimal"), "{}", text);
+        assert!(text.contains("mdump 0x10000000 0x10000020"), "{}", text);
+        assert!(text.contains("@reg"), "{}", text);
+        assert!(matches!(
+            shell.execute_command("? nosuch"),
+            CommandResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn bt_shows_a_two_deep_call_chain() {
+        let (mut shell, path) = shell_with_dump(
+            "main: jal outer
+            addiu $v0, $zero, 10
+            syscall
+            outer: jal inner
+            jr $ra
+            inner: addiu $t0, $zero, 1
+            jr $ra",
+        );
+        let base = MEM_TEXT_START as u32;
+        let symbols = shell.comp_mut().symbols_mut();
+        symbols.insert(base, "main");
+        symbols.insert(base + 12, "outer");
+        symbols.insert(base + 20, "inner");
+        shell.execute_command("run 3");
+        let out = dumped(&mut shell, &path, "bt");
+        let frames: Vec<&str> = out.lines().filter(|line| line.starts_with('#')).collect();
+        assert_eq!(
+            frames,
+            [
+                "#0  0x00400018 <inner+0x4> in 0x00400014 <inner>",
+                "#1  0x0040000C <outer> in 0x0040000C <outer>",
+                "#2  0x00400000 <main>",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_syscall_10_exit_gives_exit_status_0() {
+        let mut exited = shell("addiu $v0, $zero, 10\nsyscall");
+        exited.set_script(Box::new(io::empty()));
+        assert_eq!(exited.exit_status(), 0);
+        exited.execute_command("go");
+        assert_eq!(exited.comp().halt_reason(), Some(HaltReason::Exit));
+        assert_eq!(exited.exit_status(), 0);
+
+        // Unless syscall 10 is not among the clean exits
+        assert!(matches!(
+            exited.execute_command("clean-exits return"),
+            CommandResult::Done
+        ));
+        assert_eq!(exited.exit_status(), 2);
+
+        // Interactive sessions don't fail on how the program ended
+        let mut faulted = shell("lui $t0, 0x1000\nlw $t1, -4($t0)");
+        faulted.execute_command("go");
+        assert_eq!(faulted.exit_status(), 0);
+        faulted.set_script(Box::new(io::empty()));
+        assert_eq!(faulted.exit_status(), 2);
+    }
+
+    #[test]
+    fn set_endianness_changes_a_loaded_words_disassembly() {
+        let program = temp_path(".bin");
+        // addiu $t0, $zero, 5
+        fs::write(&program, 0x24080005_u32.to_le_bytes()).unwrap();
+        let (mut shell, path) = shell_with_dump("");
+        shell.execute_command(&format!("load {}", program.display()));
+        fs::remove_file(&program).unwrap();
+        let out = dumped(&mut shell, &path, "dis 0x400000 1");
+        assert!(out.contains("24080005  addiu $t0, $zero, 5"), "{}", out);
+        let result = shell.execute_command("set-endianness big");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        assert_eq!(shell.comp().endianness(), Endianness::Big);
+        let out = dumped(&mut shell, &path, "dis 0x400000 1");
+        assert!(out.contains("05000824"), "{}", out);
+        assert!(!out.contains("addiu"), "{}", out);
+    }
+
+    #[test]
+    fn words_writes_consecutive_words() {
+        let (mut shell, path) = shell_with_dump("nop\n");
+        let result = shell.execute_command("words 0x10000010 0x11 22 0xFFFFFFFF");
+        assert!(matches!(result, CommandResult::Done), "{:?}", result);
+        let out = dumped(&mut shell, &path, "mdump 0x10000010 0x10000018");
+        let values: Vec<&str> = out
+            .lines()
+            .filter_map(|line| line.split(" : ").nth(1))
+            .collect();
+        assert_eq!(values, ["0x00000011", "0x00000016", "0xFFFFFFFF"]);
+
+        // The last data word fits, the one after it does not
+        let result = shell.execute_command("words 0x100FFFFC 1 2");
+        assert!(matches!(result, CommandResult::Error(_)), "{:?}", result);
+        assert_eq!(shell.comp().read_byte(0x100FFFFC), Some(1));
+    }
+
+    #[test]
+    fn validate_flags_exactly_the_garbage_word() {
+        let program = temp_path(".bin");
+        // addiu, a word with the unused opcode 0x3F, then addu
+        let words = [0x24080005_u32, 0xFC000000, 0x01095021];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        fs::write(&program, bytes).unwrap();
+        let (mut shell, path) = shell_with_dump("");
+        shell.execute_command(&format!("load {}", program.display()));
+        fs::remove_file(&program).unwrap();
+        assert_eq!(shell.comp().undecodable_words(), [0x00400004]);
+        let out = dumped(&mut shell, &path, "validate");
+        let lines: Vec<&str> = out.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(
+            lines,
+            [
+                "0x00400004 : FC000000  undecodable",
+                "1 of 3 text words fail to decode",
+            ]
+        );
+    }
+
+    #[test]
+    fn dirty_lists_exactly_the_two_written_words() {
+        let (mut shell, path) = shell_with_dump(
+            "lui $t0, 0x1000
+            addiu $t1, $zero, 7
+            sw $t1, 0($t0)
+            sw $t1, 8($t0)
+            sw $t1, 8($t0)
+            sw $zero, 12($t0)",
+        );
+        shell.execute_command("go");
+        let out = dumped(&mut shell, &path, "dirty");
+        let lines: Vec<&str> = out.lines().filter(|line| !line.is_empty()).collect();
+        // Storing the value a word already holds does not dirty it
+        assert_eq!(
+            lines,
+            [
+                "0x10000000 : 00000000 -> 00000007",
+                "0x10000008 : 00000000 -> 00000007",
+                "2 word(s) modified",
+            ]
+        );
+        shell.execute_command("dirty reset");
+        assert!(shell.comp().dirty_words().is_empty());
+    }
+
+    #[test]
+    fn mdump_with_no_args_or_a_register_dumps() {
+        let mut shell = shell("nop\n");
+        assert!(matches!(
+            shell.execute_command("mdump"),
+            CommandResult::Dumped
+        ));
+        assert!(matches!(
+            shell.execute_command("mdump @sp"),
+            CommandResult::Dumped
+        ));
+        assert!(matches!(
+            shell.execute_command("mdump @bogus"),
+            CommandResult::Error(ShellError::InvalidArg(_))
+        ));
+    }
+
+    #[test]
+    fn mdump_with_only_a_start_is_missing_an_arg() {
+        let mut shell = shell(NOP);
+        match shell.execute_command("mdump 0x10000000") {
+            CommandResult::Error(ShellError::MissingArg { command, expected }) => {
+                assert_eq!(command, "mdump");
+                assert_eq!(expected, 2);
+            }
+            other => panic!("expected MissingArg, got {:?}", other),
+        }
+    }
+}
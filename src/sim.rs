@@ -1,32 +1,312 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::IsTerminal;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use super::asm::assemble_program;
+use super::cache::*;
+use super::hexfile;
 use super::instr::*;
+use super::symbols::*;
+use super::timing::*;
+use super::trace::*;
+use super::verify::{self, Expectation, Location};
 
 pub const MIPS_REGS: usize = 32;
+pub const FPU_REGS: usize = 32;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct CpuState {
     pc: u32,
     regs: [u32; MIPS_REGS],
     hi: u32,
     lo: u32,
+    // Single-precision FPU registers, kept as raw bit patterns
+    fpr: [u32; FPU_REGS],
+    // The 8 FPU condition codes, bit N holding cc N
+    fcc: u8,
 }
 
 struct MemRegion {
+    name: &'static str,
     start: usize,
     size: usize,
+    perms: Perms,
     mem: Vec<u8>,
 }
 
+// What a running program may do with a region. The debugger and program
+// loaders are not bound by these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Perms {
+    pub const RW: Perms = Perms {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    pub const RX: Perms = Perms {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    pub const RWX: Perms = Perms {
+        read: true,
+        write: true,
+        execute: true,
+    };
+}
+
+impl fmt::Display for Perms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.execute { 'x' } else { '-' }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+// A value a cycle overwrote, holding the old contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delta {
+    Reg(usize, u32),
+    Fpr(usize, u32),
+    Hi(u32),
+    Lo(u32),
+    Fcc(u8),
+    // Bytes starting at an address
+    Mem(usize, Vec<u8>),
+}
+
+// What one cycle changed, for undoing it. The PC is kept as is since it
+// nearly always changes.
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pc: u32,
+    deltas: Vec<Delta>,
+    run_bit: bool,
+    halt_reason: Option<HaltReason>,
+    flags: ArithFlags,
+}
+
+impl UndoRecord {
+    pub fn deltas(&self) -> &[Delta] {
+        &self.deltas
+    }
+}
+
+// The registers that differ between `old` and `new`, with their old values
+fn state_deltas(old: &CpuState, new: &CpuState) -> Vec<Delta> {
+    let mut deltas = Vec::new();
+    for reg in 0..MIPS_REGS {
+        if old.regs[reg] != new.regs[reg] {
+            deltas.push(Delta::Reg(reg, old.regs[reg]));
+        }
+    }
+    for reg in 0..FPU_REGS {
+        if old.fpr[reg] != new.fpr[reg] {
+            deltas.push(Delta::Fpr(reg, old.fpr[reg]));
+        }
+    }
+    if old.hi != new.hi {
+        deltas.push(Delta::Hi(old.hi));
+    }
+    if old.lo != new.lo {
+        deltas.push(Delta::Lo(old.lo));
+    }
+    if old.fcc != new.fcc {
+        deltas.push(Delta::Fcc(old.fcc));
+    }
+    deltas
+}
+
+// The public view of a memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionInfo {
+    pub name: &'static str,
+    pub start: usize,
+    pub size: usize,
+    pub perms: Perms,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    // The exit syscall (10) was made
+    Exit,
+    // Main returned, ie, jumped to MAIN_RETURN_SENTINEL
+    ReturnFromMain,
+    // A jump or branch to itself was taken, which would spin forever
+    SelfLoop,
+    // The PC does not point into mapped memory
+    InvalidPc,
+    // The PC moved past the last word of the loaded program
+    RanOffEnd,
+    // A `break` instruction was executed
+    Break,
+    // The configured maximum instruction count was reached
+    InstructionLimit,
+    // An instruction raised an exception and there is no handler
+    Exception(ExceptionCause),
+    // The run was interrupted from outside, eg, by Ctrl-C. Unlike the other
+    // reasons, the machine can be resumed.
+    Interrupted,
+}
+
+impl HaltReason {
+    // Whether the program ended the way programs end, rather than failing
+    // or being stopped
+    pub fn is_exit(&self) -> bool {
+        matches!(
+            self,
+            HaltReason::Exit | HaltReason::ReturnFromMain | HaltReason::SelfLoop
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCause {
+    // Signed overflow in a trapping add or subtract
+    Overflow,
+    // The instruction word is not a valid or implemented encoding
+    ReservedInstruction,
+    // A trap instruction's condition held
+    Trap,
+    // A load or store touched unmapped memory
+    AddressErrorLoad,
+    AddressErrorStore,
+    // The region's permissions forbid the access
+    ReadProtection,
+    WriteProtection,
+    ExecuteProtection,
+}
+
+// How dumps are rendered on stdout; the dump file is always plain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Plain,
+    Pretty,
+}
+
+// One executed branch or jump, with where it went or, if not taken, where
+// it would have gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchRecord {
+    pub source: u32,
+    pub target: u32,
+    pub taken: bool,
+}
+
+// A call not yet returned from, for backtraces. `entry` is the state the
+// callee started in, which finish checks the ABI against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub call_site: u32,
+    pub target: u32,
+    pub return_address: u32,
+    pub entry: CpuState,
+}
+
+#[derive(Default)]
+pub struct MemStats {
+    loads: u64,
+    stores: u64,
+    touched: HashSet<usize>,
+}
+
 pub struct MipsComputer {
     curr_state: CpuState,
     next_state: CpuState,
     run_bit: bool,
+    halt_reason: Option<HaltReason>,
     instr_cnt: u32,
     memory: [MemRegion; 5],
+    config: MipsConfig,
+    console: Box<dyn Write>,
+    keyboard: VecDeque<u8>,
+    mem_stats: MemStats,
+    icache: Option<Cache>,
+    dcache: Option<Cache>,
+    pipeline: Option<Pipeline>,
+    cycle_costs: CycleCosts,
+    cycle_count: u64,
+    text_end: usize,
+    // The end of the words read from flat binaries, which are all loaded
+    // from the start of the text region, for set_endianness to swap
+    binary_end: usize,
+    halt_on_text_end: bool,
+    dump_format: DumpFormat,
+    // GPRs whose changes are reported after each cycle
+    reg_watches: Vec<usize>,
+    // Bitsets of the GPRs written since tracking was enabled, and of those
+    // already warned about, when reads of unwritten registers are checked
+    uninit_check: bool,
+    written_regs: u32,
+    warned_regs: u32,
+    // When checking HI/LO hazards, whether the last instruction was a
+    // multiply or divide
+    hilo_check: bool,
+    flags: ArithFlags,
+    show_flags: bool,
+    hilo_pending: bool,
+    symbols: SymbolTable,
+    trace: Option<Box<dyn TraceSink>>,
+    // Bitset of the GPRs whose writes appear in trace events
+    trace_regs: u32,
+    // Stores made by the current instruction, kept only while tracing
+    traced_stores: Vec<(u32, u32)>,
+    // The address of the last faulting load or store
+    bad_vaddr: u32,
+    // The last exception raised and the PC of the instruction raising it
+    last_exception: Option<(ExceptionCause, u32)>,
+    // The most recent cycles, oldest first, and the old contents of the
+    // bytes stored to by the cycle in progress
+    history: VecDeque<UndoRecord>,
+    undo_stores: Vec<Delta>,
+    // The value each word the program stored to held when it was loaded
+    dirty: HashMap<usize, u32>,
+    // The CPU state right after the last load, and like `dirty` but never
+    // cleared by the user, so that `reset` can rewind to the load
+    loaded_state: CpuState,
+    loaded_words: HashMap<usize, u32>,
+    // Inclusive PC ranges, a point breakpoint being a range of one address
+    breakpoints: Vec<(u32, u32)>,
+    // Set when the PC enters a breakpoint range, to stop the run commands
+    break_hit: bool,
+    // Set from outside the simulator, eg, a signal handler, to stop a run
+    interrupt: Arc<AtomicBool>,
+    // Retired instructions after which cycle() halts, if any
+    max_instructions: Option<u32>,
+    // Register states saved by name, to diff against later
+    snapshots: HashMap<String, CpuState>,
+    // When set, call and finish check the callee-saved registers survived,
+    // keeping what the last check found
+    abi_check: bool,
+    abi_violations: Vec<(Location, u32, u32)>,
+    // The last BRANCH_LOG_DEPTH branches and jumps executed, oldest first,
+    // when logging them
+    branch_log: Option<VecDeque<BranchRecord>>,
+    // The calls made and not yet returned from, outermost first
+    call_stack: Vec<Frame>,
 }
 
 impl CpuState {
@@ -36,20 +316,78 @@ impl CpuState {
             regs: [0; MIPS_REGS],
             hi: 0,
             lo: 0,
+            fpr: [0; FPU_REGS],
+            fcc: 0,
+        }
+    }
+
+    pub fn get_fcc(&self, cc: u32) -> bool {
+        cc < 8 && self.fcc & (1 << cc) != 0
+    }
+
+    pub fn set_fcc(&mut self, cc: u32, val: bool) -> bool {
+        if cc >= 8 {
+            return false;
+        }
+        if val {
+            self.fcc |= 1 << cc;
+        } else {
+            self.fcc &= !(1 << cc);
+        }
+        true
+    }
+
+    pub fn get_fpr(&self, reg: usize) -> Option<u32> {
+        self.fpr.get(reg).copied()
+    }
+
+    pub fn set_fpr(&mut self, reg: usize, val: u32) -> bool {
+        if reg < FPU_REGS {
+            self.fpr[reg] = val;
+            true
+        } else {
+            false
         }
     }
 
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn get_reg(&self, reg: usize) -> Option<u32> {
+        self.regs.get(reg).copied()
+    }
+
+    pub fn registers(&self) -> [u32; MIPS_REGS] {
+        self.regs
+    }
+
+    pub fn set_pc(&mut self, val: u32) {
+        self.pc = val;
+    }
+
     pub fn set_hi(&mut self, val: u32) {
         self.hi = val;
     }
 
+    pub fn hi(&self) -> u32 {
+        self.hi
+    }
+
+    pub fn lo(&self) -> u32 {
+        self.lo
+    }
+
     pub fn set_lo(&mut self, val: u32) {
         self.lo = val;
     }
 
+    // $zero is hardwired, so writes to it are dropped
     pub fn set_reg(&mut self, reg: usize, val: u32) -> bool {
         if reg < MIPS_REGS {
-            self.regs[reg] = val;
+            if reg != 0 {
+                self.regs[reg] = val;
+            }
             true
         } else {
             false
@@ -57,12 +395,73 @@ impl CpuState {
     }
 }
 
+// Only nonzero registers are shown, to keep snapshots readable
+impl fmt::Debug for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = f.debug_struct("CpuState");
+        out.field("pc", &format_args!("{:#010X}", self.pc))
+            .field("hi", &format_args!("{:#010X}", self.hi))
+            .field("lo", &format_args!("{:#010X}", self.lo));
+        let names = REG_NAMES.iter().map(|name| format!("${}", name));
+        for (name, val) in names.zip(self.regs.iter()) {
+            if *val != 0 {
+                out.field(&name, &format_args!("{:#010X}", val));
+            }
+        }
+        for (i, val) in self.fpr.iter().enumerate() {
+            if *val != 0 {
+                out.field(&format!("$f{}", i), &format_args!("{:#010X}", val));
+            }
+        }
+        if self.fcc != 0 {
+            out.field("fcc", &format_args!("{:#010b}", self.fcc));
+        }
+        out.finish()
+    }
+}
+
+// Where a branch or jump goes when taken, or None for other instructions.
+// Register jumps have no fixed target, so their own PC stands in for it;
+// they are always taken, so it is never reported.
+fn branch_target(instr: &Instr, pc: u32) -> Option<u32> {
+    match instr {
+        Instr::RType(rtype) if matches!(rtype.op(), ROp::JR | ROp::JALR) => Some(pc),
+        _ => instr.branch_target(pc),
+    }
+}
+
+impl MemStats {
+    fn record(&mut self, address: usize, is_store: bool) {
+        if is_store {
+            self.stores += 1;
+        } else {
+            self.loads += 1;
+        }
+        // Accesses are tracked at word granularity
+        self.touched.insert(address & !0x3);
+    }
+
+    pub fn loads(&self) -> u64 {
+        self.loads
+    }
+
+    pub fn stores(&self) -> u64 {
+        self.stores
+    }
+
+    pub fn unique_words(&self) -> usize {
+        self.touched.len()
+    }
+}
+
 impl MemRegion {
-    fn new(start: usize, size: usize) -> Self {
+    fn new(name: &'static str, config: RegionConfig) -> Self {
         Self {
-            start,
-            size,
-            mem: vec![0; size],
+            name,
+            start: config.start,
+            size: config.size,
+            perms: config.perms,
+            mem: vec![0; config.size],
         }
     }
 
@@ -70,8 +469,14 @@ impl MemRegion {
         address >= self.start && address < (self.start + self.size)
     }
 
+    // Whether all `len` bytes starting at `address` are in the region, so
+    // that accesses straddling its end fault instead of indexing past it
+    fn contains_range(&self, address: usize, len: usize) -> bool {
+        address >= self.start && address + len <= self.start + self.size
+    }
+
     fn read_32(&self, address: usize) -> Option<u32> {
-        if !self.contains_address(address) {
+        if !self.contains_range(address, 4) {
             None
         } else {
             let offset = address - self.start;
@@ -84,7 +489,7 @@ impl MemRegion {
     }
 
     fn read_8(&self, address: usize) -> Option<u8> {
-        if !self.contains_address(address) {
+        if !self.contains_range(address, 1) {
             None
         } else {
             let offset = address - self.start;
@@ -93,7 +498,7 @@ impl MemRegion {
     }
 
     fn read_16(&self, address: usize) -> Option<u16> {
-        if !self.contains_address(address) {
+        if !self.contains_range(address, 2) {
             None
         } else {
             let offset = address - self.start;
@@ -104,28 +509,28 @@ impl MemRegion {
     }
 
     fn write_32(&mut self, address: usize, value: u32) -> bool {
-        if !self.contains_address(address) {
-            return false;
+        if !self.contains_range(address, 4) {
+            false
         } else {
             let offset = address - self.start;
             self.mem[offset + 3] = (value >> 24) as u8;
             self.mem[offset + 2] = (value >> 16) as u8;
             self.mem[offset + 1] = (value >> 8) as u8;
             self.mem[offset] = value as u8;
-            return true;
+            true
         }
     }
 
     // Bytes must be in little-endian order (LSB at lowest address)
     fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> bool {
-        if !self.contains_address(address) {
-            return false;
+        if !self.contains_range(address, bytes.len()) {
+            false
         } else {
             let offset = address - self.start;
             for (idx, byte) in bytes.iter().enumerate() {
                 self.mem[offset + idx] = *byte;
             }
-            return true;
+            true
         }
     }
 }
@@ -141,118 +546,1104 @@ pub const MEM_KDATA_SIZE: usize = 0x00100000;
 pub const MEM_KTEXT_START: usize = 0x80000000;
 pub const MEM_KTEXT_SIZE: usize = 0x00100000;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionConfig {
+    pub start: usize,
+    pub size: usize,
+    pub perms: Perms,
+}
+
+// Undo history is off unless asked for, as logging every cycle slows long
+// runs down and holds on to memory
+pub const DEFAULT_HISTORY_DEPTH: usize = 0;
+
+// The most branches the branch log keeps, dropping the oldest beyond it so
+// that a long loop can't grow the log without bound
+pub const BRANCH_LOG_DEPTH: usize = 10_000;
+
+// The byte order of the words in flat binary program files, and of `.word`
+// data in sources. Memory itself is always little-endian, so words of
+// big-endian files are swapped as they are loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Endianness {
+    fn default() -> Self {
+        Endianness::Little
+    }
+}
+
+// What memory and the registers hold before the program writes them.
+// Anything but zeros makes programs relying on uninitialized data misbehave
+// visibly rather than by luck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillPattern {
+    Zero,
+    // The same word everywhere, eg, 0xDEADBEEF
+    Word(u32),
+    // Words from a PRNG with this seed, so runs are reproducible
+    Random(u64),
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for FillPattern {
+    fn default() -> Self {
+        FillPattern::Zero
+    }
+}
+
+impl FillPattern {
+    fn words(self) -> impl Iterator<Item = u32> {
+        // xorshift64*, which never yields 0 from a nonzero state
+        let mut state = match self {
+            FillPattern::Random(seed) => seed | 1,
+            _ => 0,
+        };
+        std::iter::repeat_with(move || match self {
+            FillPattern::Zero => 0,
+            FillPattern::Word(word) => word,
+            FillPattern::Random(_) => {
+                state ^= state >> 12;
+                state ^= state << 25;
+                state ^= state >> 27;
+                (state.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+            }
+        })
+    }
+}
+
+// Whether the last add or subtract overflowed or carried. MIPS has no
+// condition codes, these are a debugging aid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArithFlags {
+    // The signed result did not fit in 32 bits
+    pub overflow: bool,
+    // The unsigned result carried out of bit 31, or borrowed for a subtract
+    pub carry: bool,
+}
+
+impl ArithFlags {
+    fn add(first: u32, second: u32) -> Self {
+        Self {
+            overflow: (first as i32).checked_add(second as i32).is_none(),
+            carry: first.overflowing_add(second).1,
+        }
+    }
+
+    fn sub(first: u32, second: u32) -> Self {
+        Self {
+            overflow: (first as i32).checked_sub(second as i32).is_none(),
+            carry: first < second,
+        }
+    }
+}
+
+// Where each memory region lives and how big it is, and what it initially
+// holds. The default is the zero-filled MEM_* layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MipsConfig {
+    pub data: RegionConfig,
+    pub text: RegionConfig,
+    pub stack: RegionConfig,
+    pub kdata: RegionConfig,
+    pub ktext: RegionConfig,
+    pub fill: FillPattern,
+    // How many cycles `back` can undo, 0 turning the history off
+    pub history_depth: usize,
+    pub endianness: Endianness,
+    // What $sp holds when a program starts, left alone when None
+    pub stack_pointer: Option<u32>,
+}
+
+impl Default for MipsConfig {
+    fn default() -> Self {
+        Self {
+            data: RegionConfig {
+                start: MEM_DATA_START,
+                size: MEM_DATA_SIZE,
+                perms: Perms::RW,
+            },
+            text: RegionConfig {
+                start: MEM_TEXT_START,
+                size: MEM_TEXT_SIZE,
+                perms: Perms::RX,
+            },
+            stack: RegionConfig {
+                start: MEM_STACK_START,
+                size: MEM_STACK_SIZE,
+                perms: Perms::RW,
+            },
+            kdata: RegionConfig {
+                start: MEM_KDATA_START,
+                size: MEM_KDATA_SIZE,
+                perms: Perms::RW,
+            },
+            ktext: RegionConfig {
+                start: MEM_KTEXT_START,
+                size: MEM_KTEXT_SIZE,
+                perms: Perms::RX,
+            },
+            fill: FillPattern::Zero,
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            endianness: Endianness::Little,
+            stack_pointer: None,
+        }
+    }
+}
+
+impl MipsConfig {
+    fn named_regions(&self) -> [(&'static str, RegionConfig); 5] {
+        [
+            ("data", self.data),
+            ("text", self.text),
+            ("stack", self.stack),
+            ("kdata", self.kdata),
+            ("ktext", self.ktext),
+        ]
+    }
+
+    // Regions must be non-empty, word aligned, inside the 32-bit address
+    // space, outside the kseg1 alias and must not overlap
+    pub fn validate(&self) -> io::Result<()> {
+        const ADDRESS_SPACE: usize = 0x1_0000_0000;
+        let regions = self.named_regions();
+        for (name, region) in &regions {
+            if region.size == 0
+                || region.start % 4 != 0
+                || region.size % 4 != 0
+                || region.start + region.size > ADDRESS_SPACE
+                || (region.start < KSEG1_START + KSEG_SIZE
+                    && KSEG1_START < region.start + region.size)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid {} region", name),
+                ));
+            }
+        }
+        for (idx, (name, first)) in regions.iter().enumerate() {
+            for (other, second) in &regions[idx + 1..] {
+                if first.start < second.start + second.size
+                    && second.start < first.start + first.size
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("The {} and {} regions overlap", name, other),
+                    ));
+                }
+            }
+        }
+        // The stack grows down, so its top may sit just past the region
+        if let Some(sp) = self.stack_pointer {
+            let sp = sp as usize;
+            if sp % 4 != 0 || sp < self.stack.start || sp > self.stack.start + self.stack.size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Stack pointer {:#010X} is outside the stack region", sp),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chainable construction of a MipsComputer, validating the configuration
+/// only once build is called.
+///
+/// ```
+/// use mips_sim::sim::*;
+///
+/// let top = (MEM_STACK_START + MEM_STACK_SIZE - 16) as u32;
+/// let mut comp = MipsComputerBuilder::new()
+///     .silent(true)
+///     .endianness(Endianness::Little)
+///     .stack_pointer(top)
+///     .build()
+///     .unwrap();
+/// assert_eq!(comp.registers()[29], top);
+/// assert_eq!(comp.endianness(), Endianness::Little);
+/// // addiu $t0, $zero, 5; sw $t0, -4($sp)
+/// comp.load_words(MEM_TEXT_START as u32, &[0x24080005, 0xAFA8FFFC])
+///     .unwrap();
+/// comp.run(2);
+/// assert_eq!(comp.read_byte(top as usize - 4), Some(5));
+///
+/// // The stack pointer must be word aligned and inside the stack
+/// assert!(MipsComputerBuilder::new().stack_pointer(top + 2).build().is_err());
+/// ```
+#[derive(Default)]
+pub struct MipsComputerBuilder {
+    config: MipsConfig,
+    silent: bool,
+    programs: Vec<String>,
+}
+
+impl MipsComputerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: MipsConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn data(mut self, region: RegionConfig) -> Self {
+        self.config.data = region;
+        self
+    }
+
+    pub fn text(mut self, region: RegionConfig) -> Self {
+        self.config.text = region;
+        self
+    }
+
+    pub fn stack(mut self, region: RegionConfig) -> Self {
+        self.config.stack = region;
+        self
+    }
+
+    pub fn kdata(mut self, region: RegionConfig) -> Self {
+        self.config.kdata = region;
+        self
+    }
+
+    pub fn ktext(mut self, region: RegionConfig) -> Self {
+        self.config.ktext = region;
+        self
+    }
+
+    pub fn fill(mut self, fill: FillPattern) -> Self {
+        self.config.fill = fill;
+        self
+    }
+
+    pub fn history_depth(mut self, depth: usize) -> Self {
+        self.config.history_depth = depth;
+        self
+    }
+
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.config.endianness = endianness;
+        self
+    }
+
+    pub fn stack_pointer(mut self, sp: u32) -> Self {
+        self.config.stack_pointer = Some(sp);
+        self
+    }
+
+    // Discards whatever the program writes to the console
+    pub fn silent(mut self, silent: bool) -> Self {
+        self.silent = silent;
+        self
+    }
+
+    pub fn program(mut self, filename: &str) -> Self {
+        self.programs.push(filename.to_string());
+        self
+    }
+
+    pub fn programs(mut self, filenames: &[String]) -> Self {
+        self.programs.extend_from_slice(filenames);
+        self
+    }
+
+    pub fn build(self) -> io::Result<MipsComputer> {
+        let mut comp = MipsComputer::with_config(self.config, &self.programs)?;
+        if self.silent {
+            comp.set_console_output(Box::new(io::sink()));
+        }
+        Ok(comp)
+    }
+}
+
+// kseg1 is an uncached window onto the same physical memory as kseg0,
+// which holds the kernel text and data regions
+pub const KSEG0_START: usize = 0x80000000;
+pub const KSEG1_START: usize = 0xA0000000;
+pub const KSEG_SIZE: usize = 0x20000000;
+
+// Maps kseg1 addresses onto their kseg0 aliases
+fn translate(address: usize) -> usize {
+    if (KSEG1_START..KSEG1_START + KSEG_SIZE).contains(&address) {
+        address - (KSEG1_START - KSEG0_START)
+    } else {
+        address
+    }
+}
+
+fn is_uncached(address: usize) -> bool {
+    (KSEG1_START..KSEG1_START + KSEG_SIZE).contains(&address)
+}
+
+// Non-standard syscall printing a formatted string, see syscall_printf
+pub const SYSCALL_PRINTF: u32 = 40;
+
+// The return address `call` gives a subroutine, so its return can be seen.
+// Nothing is mapped there.
+pub const CALL_RETURN_SENTINEL: u32 = 0xFFFFFFFC;
+// The return address loaded programs start with, so that main returning
+// ends the program. Nothing is mapped there either.
+pub const MAIN_RETURN_SENTINEL: u32 = 0xFFFFFFF8;
+
+// Where the general exception handler lives in kernel text
+pub const EXCEPTION_VECTOR: usize = 0x80000180;
+
+// Memory-mapped keyboard and console, at the same addresses as SPIM's
+// receiver and transmitter
+pub const MMIO_KEYBOARD_STATUS: usize = 0xFFFF0000;
+pub const MMIO_KEYBOARD_DATA: usize = 0xFFFF0004;
+pub const MMIO_CONSOLE_STATUS: usize = 0xFFFF0008;
+pub const MMIO_CONSOLE_DATA: usize = 0xFFFF000C;
+// Read-only count of the instructions retired so far
+pub const MMIO_TIMER: usize = 0xFFFF0010;
+
 impl MipsComputer {
     pub fn new(filenames: &[String]) -> io::Result<Self> {
+        MipsComputerBuilder::new().programs(filenames).build()
+    }
+
+    pub fn with_config(config: MipsConfig, filenames: &[String]) -> io::Result<Self> {
+        config.validate()?;
         let mut comp = Self {
             curr_state: CpuState::new(),
             next_state: CpuState::new(),
             run_bit: true,
+            halt_reason: None,
             instr_cnt: 0,
             memory: [
-                MemRegion::new(MEM_DATA_START, MEM_DATA_SIZE),
-                MemRegion::new(MEM_TEXT_START, MEM_TEXT_SIZE),
-                MemRegion::new(MEM_STACK_START, MEM_STACK_SIZE),
-                MemRegion::new(MEM_KDATA_START, MEM_KDATA_SIZE),
-                MemRegion::new(MEM_KTEXT_START, MEM_KTEXT_SIZE),
+                MemRegion::new("data", config.data),
+                MemRegion::new("text", config.text),
+                MemRegion::new("stack", config.stack),
+                MemRegion::new("kdata", config.kdata),
+                MemRegion::new("ktext", config.ktext),
             ],
+            config,
+            console: Box::new(io::stdout()),
+            keyboard: VecDeque::new(),
+            mem_stats: MemStats::default(),
+            icache: None,
+            dcache: None,
+            pipeline: None,
+            cycle_costs: CycleCosts::default(),
+            cycle_count: 0,
+            text_end: config.text.start,
+            binary_end: config.text.start,
+            halt_on_text_end: true,
+            dump_format: if io::stdout().is_terminal() {
+                DumpFormat::Pretty
+            } else {
+                DumpFormat::Plain
+            },
+            reg_watches: Vec::new(),
+            uninit_check: false,
+            hilo_check: false,
+            flags: ArithFlags::default(),
+            show_flags: false,
+            hilo_pending: false,
+            written_regs: 1,
+            warned_regs: 0,
+            symbols: SymbolTable::default(),
+            trace: None,
+            trace_regs: u32::MAX,
+            traced_stores: Vec::new(),
+            bad_vaddr: 0,
+            last_exception: None,
+            history: VecDeque::new(),
+            undo_stores: Vec::new(),
+            dirty: HashMap::new(),
+            loaded_state: CpuState::new(),
+            loaded_words: HashMap::new(),
+            breakpoints: Vec::new(),
+            break_hit: false,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            max_instructions: None,
+            snapshots: HashMap::new(),
+            abi_check: false,
+            abi_violations: Vec::new(),
+            branch_log: None,
+            call_stack: Vec::new(),
         };
+        if config.fill != FillPattern::Zero {
+            comp.fill(config.fill);
+        }
+        if let Some(sp) = config.stack_pointer {
+            comp.curr_state.set_reg(29, sp);
+        }
         for filename in filenames.iter() {
             comp.load_program(filename)?;
         }
         comp.next_state = comp.curr_state;
+        comp.loaded_state = comp.curr_state;
         Ok(comp)
     }
 
-    fn load_program<T: AsRef<Path>>(&mut self, path: T) -> io::Result<()> {
-        let mut file = File::open(&path).expect(&format!(
-            "Cannot open program file {}",
-            path.as_ref().display()
-        ));
-        let mut buf = [0 as u8; 4];
+    // Overwrites memory, the GPRs other than $zero, HI and LO with `pattern`
+    fn fill(&mut self, pattern: FillPattern) {
+        let mut words = pattern.words();
+        for mem_reg in &mut self.memory {
+            for (chunk, word) in mem_reg.mem.chunks_mut(4).zip(&mut words) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        for reg in 1..MIPS_REGS {
+            self.curr_state.regs[reg] = words.next().unwrap_or(0);
+        }
+        self.curr_state.hi = words.next().unwrap_or(0);
+        self.curr_state.lo = words.next().unwrap_or(0);
+    }
+
+    // Loads a flat binary at the start of the text region and points PC at
+    // it, or an Intel HEX or S-record file at the addresses in its records.
+    // A halted machine is resumed so the new program can be run.
+    pub fn load_program<T: AsRef<Path>>(&mut self, path: T) -> io::Result<()> {
+        let mut file = File::open(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Cannot open program file {}: {}",
+                    path.as_ref().display(),
+                    e
+                ),
+            )
+        })?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        if hexfile::is_hex_file(&contents) {
+            return self.load_hex(&String::from_utf8_lossy(&contents));
+        }
+        let extension = path.as_ref().extension().and_then(|ext| ext.to_str());
+        if matches!(extension, Some("s") | Some("asm")) {
+            return self.load_source(&String::from_utf8_lossy(&contents));
+        }
         let mut off = 0;
-        loop {
-            buf.fill(0);
-            let bytes_read = file.read(&mut buf)?;
-            if bytes_read == 0 {
-                // EOF
-                break;
+        for chunk in contents.chunks(4) {
+            let mut buf = [0_u8; 4];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            if self.config.endianness == Endianness::Big {
+                buf.reverse();
             }
-            self.mem_write_bytes(MEM_TEXT_START + off, &buf);
+            self.mem_write_bytes(self.config.text.start + off, &buf);
             off += 4;
         }
-        self.curr_state.pc = MEM_TEXT_START as u32;
+        self.text_end = self.text_end.max(self.config.text.start + off);
+        self.binary_end = self.binary_end.max(self.config.text.start + off);
+        self.start_program(self.config.text.start as u32);
         println!("Read {} words from program into memory.\n", off / 4);
         Ok(())
     }
 
-    fn mem_read_32(&self, address: usize) -> Option<u32> {
-        for mem_reg in &self.memory {
-            if let Some(data) = mem_reg.read_32(address) {
-                return Some(data);
+    pub fn endianness(&self) -> Endianness {
+        self.config.endianness
+    }
+
+    // Changes the byte order flat binaries are read in, byte-swapping the
+    // words loaded from them so they decode as if loaded that way. Words
+    // from HEX, S-record and source loads are left alone, as are registers
+    // and data the program produced.
+    // Returns how many words were swapped.
+    pub fn set_endianness(&mut self, endianness: Endianness) -> usize {
+        if endianness == self.config.endianness {
+            return 0;
+        }
+        self.config.endianness = endianness;
+        let start = self.config.text.start;
+        let mut swapped = 0;
+        for address in (start..self.binary_end).step_by(4) {
+            if let Some(word) = self.mem_read_32(address) {
+                self.mem_write_32(address, word.swap_bytes());
+                swapped += 1;
             }
         }
-        return None;
+        swapped
     }
 
-    fn mem_read_16(&self, address: usize) -> Option<u16> {
-        for mem_reg in &self.memory {
-            if let Some(data) = mem_reg.read_16(address) {
-                return Some(data);
-            }
+    // Readies a freshly loaded program to run from `entry`, with $ra set so
+    // that main returning to it is seen as an exit
+    fn start_program(&mut self, entry: u32) {
+        self.history.clear();
+        self.call_stack.clear();
+        self.dirty.clear();
+        self.loaded_words.clear();
+        self.flags = ArithFlags::default();
+        self.curr_state.pc = entry;
+        self.curr_state.set_reg(31, MAIN_RETURN_SENTINEL);
+        if let Some(sp) = self.config.stack_pointer {
+            self.curr_state.set_reg(29, sp);
         }
-        return None;
+        self.next_state = self.curr_state;
+        self.loaded_state = self.curr_state;
+        self.run_bit = true;
+        self.halt_reason = None;
     }
 
-    fn mem_read_8(&self, address: usize) -> Option<u8> {
-        for mem_reg in &self.memory {
-            if let Some(data) = mem_reg.read_8(address) {
-                return Some(data);
-            }
+    // Rewinds the machine to just after the last load. Words stored to
+    // since get their loaded contents back, the registers and PC are
+    // restored and the counters are zeroed. Settings, breakpoints and
+    // symbols are kept.
+    pub fn reset(&mut self) {
+        for (address, word) in std::mem::take(&mut self.loaded_words) {
+            self.mem_write_32(address, word);
         }
-        return None;
+        self.curr_state = self.loaded_state;
+        self.next_state = self.curr_state;
+        self.run_bit = true;
+        self.halt_reason = None;
+        self.last_exception = None;
+        self.history.clear();
+        self.call_stack.clear();
+        self.dirty.clear();
+        self.flags = ArithFlags::default();
+        self.hilo_pending = false;
+        self.reset_stats();
     }
 
-    fn mem_write_32(&mut self, address: usize, value: u32) -> bool {
-        for mem_reg in &mut self.memory {
-            if mem_reg.write_32(address, value) {
-                return true;
+    fn load_hex(&mut self, src: &str) -> io::Result<()> {
+        let image = hexfile::parse(src)?;
+        let text_start = self.config.text.start;
+        let text_range = text_start..text_start + self.config.text.size;
+        let mut bytes = 0;
+        for (address, data) in &image.chunks {
+            if !self.mem_write_bytes(*address as usize, data) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cannot load {} bytes at unmapped address {:#010X}",
+                        data.len(),
+                        address
+                    ),
+                ));
             }
+            if text_range.contains(&(*address as usize)) {
+                let end = (*address as usize + data.len() + 3) & !0x3;
+                self.text_end = self.text_end.max(end);
+            }
+            bytes += data.len();
         }
-        return false;
+        // Tools write an entry of 0 when there is none
+        let entry = image
+            .entry
+            .filter(|entry| *entry != 0)
+            .unwrap_or(text_start as u32);
+        self.start_program(entry);
+        println!("Read {} bytes from records into memory.\n", bytes);
+        Ok(())
     }
 
-    fn mem_write_bytes(&mut self, address: usize, bytes: &[u8]) -> bool {
-        for mem_reg in &mut self.memory {
-            if mem_reg.write_bytes(address, bytes) {
-                return true;
+    // Assembles `src`, placing its `.text` section at the start of the text
+    // region and its `.data` section at the start of the data region, with
+    // `.word` values laid out in the configured byte order
+    pub fn load_source(&mut self, src: &str) -> io::Result<()> {
+        let text_start = self.config.text.start as u32;
+        let data_start = self.config.data.start;
+        let mut program = assemble_program(src, text_start, data_start as u32)?;
+        if self.config.endianness == Endianness::Big {
+            for &offset in &program.data_words {
+                program.data[offset..offset + 4].reverse();
             }
         }
-        return false;
+        if !self.mem_write_bytes(data_start, &program.data) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot fit {} data bytes in the data region",
+                    program.data.len()
+                ),
+            ));
+        }
+        self.load_words(text_start, &program.text)?;
+        self.start_program(text_start);
+        println!(
+            "Read {} words and {} data bytes from source into memory.\n",
+            program.text.len(),
+            program.data.len()
+        );
+        Ok(())
     }
 
-    fn process_instruction(&mut self) {
-        let instr = self.mem_read_32(self.curr_state.pc as usize);
-        if let Some(instr) = instr {
-            if instr == 0 {
-                self.run_bit = false;
-            } else {
-                let instr = parse_instr(instr);
-                println!("Processing {:?}", instr);
-                let incr_pc = match instr {
-                    Instr::JType(instr) => self.process_jtype_instruction(&instr),
-                    Instr::IType(instr) => self.process_itype_instruction(&instr),
-                    Instr::RType(instr) => self.process_rtype_instruction(&instr),
-                };
-                if incr_pc {
-                    self.next_state.pc = self.curr_state.pc + 4;
-                }
+    /// Writes `words` to memory from `base`, pointing PC at the first one
+    /// when they land in text, so programs can be seeded without a file.
+    ///
+    /// ```
+    /// use mips_sim::sim::{MipsComputer, MEM_TEXT_START};
+    ///
+    /// let mut comp = MipsComputer::new(&[]).unwrap();
+    /// // addiu $t0, $zero, 5; addiu $t1, $t0, 7
+    /// comp.load_words(MEM_TEXT_START as u32, &[0x24080005, 0x25090007])
+    ///     .unwrap();
+    /// comp.step();
+    /// comp.step();
+    /// ```
+    pub fn load_words(&mut self, base: u32, words: &[u32]) -> io::Result<()> {
+        for (idx, word) in words.iter().enumerate() {
+            let address = base as usize + idx * 4;
+            if !self.mem_write_32(address, *word) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Cannot load word at unmapped address {:#010X}", address),
+                ));
             }
-        } else {
-            self.run_bit = false;
         }
+        let text_end = self.config.text.start + self.config.text.size;
+        if (self.config.text.start..text_end).contains(&(base as usize)) {
+            self.text_end = self.text_end.max(base as usize + words.len() * 4);
+            self.curr_state.pc = base;
+            self.next_state.pc = base;
+            self.loaded_state.pc = base;
+        }
+        Ok(())
     }
 
-    fn process_jtype_instruction(&mut self, instr: &JType) -> bool {
+    // Writes consecutive words from `address` on behalf of the user, so
+    // region permissions do not apply. Stops at the first unmapped word,
+    // leaving those before it written.
+    pub fn write_words(&mut self, address: u32, words: &[u32]) -> io::Result<()> {
+        if address % 4 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Address {:#010X} is not word aligned", address),
+            ));
+        }
+        for (idx, word) in words.iter().enumerate() {
+            let address = address as usize + idx * 4;
+            if !self.mem_write_32(address, *word) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cannot write word at unmapped address {:#010X}, {} of {} written",
+                        address,
+                        idx,
+                        words.len()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Redirects the bytes written to the memory-mapped console
+    pub fn set_console_output(&mut self, out: Box<dyn Write>) {
+        self.console = out;
+    }
+
+    // Queues bytes to be read from the memory-mapped keyboard
+    pub fn push_keyboard_input(&mut self, bytes: &[u8]) {
+        self.keyboard.extend(bytes);
+    }
+
+    fn mmio_read(&self, address: usize) -> Option<u32> {
+        match address {
+            MMIO_KEYBOARD_STATUS => Some(!self.keyboard.is_empty() as u32),
+            MMIO_KEYBOARD_DATA => Some(self.keyboard.front().copied().unwrap_or(0) as u32),
+            // The console is always ready to transmit
+            MMIO_CONSOLE_STATUS => Some(1),
+            MMIO_CONSOLE_DATA => Some(0),
+            MMIO_TIMER => Some(self.instr_cnt),
+            _ => None,
+        }
+    }
+
+    fn mmio_write(&mut self, address: usize, value: u32) -> bool {
+        match address {
+            MMIO_CONSOLE_DATA => {
+                // Output errors are not the simulated program's concern
+                let _ = self.console.write_all(&[value as u8]);
+                let _ = self.console.flush();
+                true
+            }
+            MMIO_CONSOLE_STATUS => true,
+            _ => false,
+        }
+    }
+
+    // Side effects of a program (rather than a debugger) reading a device
+    fn mmio_consume(&mut self, address: usize) {
+        if address == MMIO_KEYBOARD_DATA {
+            self.keyboard.pop_front();
+        }
+    }
+
+    // Whether the program may access `address` in this way. Addresses
+    // outside every region are left to fault, or not, as before.
+    fn permits(&self, address: usize, access: Access) -> bool {
+        let address = translate(address);
+        match self
+            .memory
+            .iter()
+            .find(|mem_reg| mem_reg.contains_address(address))
+        {
+            Some(mem_reg) => match access {
+                Access::Read => mem_reg.perms.read,
+                Access::Write => mem_reg.perms.write,
+                Access::Execute => mem_reg.perms.execute,
+            },
+            None => true,
+        }
+    }
+
+    fn load_32(&mut self, address: usize) -> Option<u32> {
+        if !self.permits(address, Access::Read) {
+            return None;
+        }
+        let data = self.mem_read_32(address);
+        if data.is_some() {
+            self.mem_stats.record(address, false);
+            self.dcache_access(address);
+        }
+        self.mmio_consume(address);
+        data
+    }
+
+    fn load_16(&mut self, address: usize) -> Option<u16> {
+        if !self.permits(address, Access::Read) {
+            return None;
+        }
+        let data = self.mem_read_16(address);
+        if data.is_some() {
+            self.mem_stats.record(address, false);
+            self.dcache_access(address);
+        }
+        self.mmio_consume(address);
+        data
+    }
+
+    fn load_8(&mut self, address: usize) -> Option<u8> {
+        if !self.permits(address, Access::Read) {
+            return None;
+        }
+        let data = self.mem_read_8(address);
+        if data.is_some() {
+            self.mem_stats.record(address, false);
+            self.dcache_access(address);
+        }
+        self.mmio_consume(address);
+        data
+    }
+
+    // Saves the bytes a store is about to overwrite, so `back` can restore
+    // them. Device registers have side effects and are not saved.
+    fn save_for_undo(&mut self, address: usize, len: usize) {
+        if self.config.history_depth == 0 || self.mmio_read(address).is_some() {
+            return;
+        }
+        let old: Option<Vec<u8>> = (0..len).map(|off| self.mem_read_8(address + off)).collect();
+        if let Some(old) = old {
+            self.undo_stores.push(Delta::Mem(address, old));
+        }
+    }
+
+    // Remembers what the word holding `address` contained before its first
+    // store, so `dirty` can show the old value
+    fn mark_dirty(&mut self, address: usize) {
+        let word = translate(address) & !3;
+        if self.dirty.contains_key(&word) || self.mmio_read(address).is_some() {
+            return;
+        }
+        if let Some(old) = self.mem_read_32(word) {
+            self.dirty.insert(word, old);
+            self.loaded_words.entry(word).or_insert(old);
+        }
+    }
+
+    fn store_32(&mut self, address: usize, value: u32) -> bool {
+        if !self.permits(address, Access::Write) {
+            return false;
+        }
+        self.save_for_undo(address, 4);
+        self.mark_dirty(address);
+        let written = self.mem_write_32(address, value);
+        self.record_store(address, value, written)
+    }
+
+    fn store_16(&mut self, address: usize, value: u16) -> bool {
+        if !self.permits(address, Access::Write) {
+            return false;
+        }
+        self.save_for_undo(address, 2);
+        self.mark_dirty(address);
+        let written = self.mem_write_bytes(address, &value.to_le_bytes());
+        self.record_store(address, value as u32, written)
+    }
+
+    fn store_8(&mut self, address: usize, value: u8) -> bool {
+        if !self.permits(address, Access::Write) {
+            return false;
+        }
+        self.save_for_undo(address, 1);
+        self.mark_dirty(address);
+        let written = self.mem_write_bytes(address, &[value]);
+        self.record_store(address, value as u32, written)
+    }
+
+    // Runs a load or store through the data cache, stalling on a miss.
+    // Uncached segments and device registers bypass it.
+    fn dcache_access(&mut self, address: usize) {
+        if is_uncached(address) || self.mmio_read(address).is_some() {
+            return;
+        }
+        if let Some(dcache) = &mut self.dcache {
+            if !dcache.access(address) {
+                self.cycle_count += self.cycle_costs.miss_penalty as u64;
+            }
+        }
+    }
+
+    fn record_store(&mut self, address: usize, value: u32, written: bool) -> bool {
+        if written {
+            self.mem_stats.record(address, true);
+            self.dcache_access(address);
+            if self.trace.is_some() {
+                self.traced_stores.push((address as u32, value));
+            }
+        }
+        written
+    }
+
+    fn mem_read_32(&self, address: usize) -> Option<u32> {
+        if let Some(data) = self.mmio_read(address) {
+            return Some(data);
+        }
+        let address = translate(address);
+        for mem_reg in &self.memory {
+            if let Some(data) = mem_reg.read_32(address) {
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    fn mem_read_16(&self, address: usize) -> Option<u16> {
+        if let Some(data) = self.mmio_read(address) {
+            return Some(data as u16);
+        }
+        let address = translate(address);
+        for mem_reg in &self.memory {
+            if let Some(data) = mem_reg.read_16(address) {
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    fn mem_read_8(&self, address: usize) -> Option<u8> {
+        if let Some(data) = self.mmio_read(address) {
+            return Some(data as u8);
+        }
+        let address = translate(address);
+        for mem_reg in &self.memory {
+            if let Some(data) = mem_reg.read_8(address) {
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    fn mem_write_32(&mut self, address: usize, value: u32) -> bool {
+        if self.mmio_write(address, value) {
+            return true;
+        }
+        let address = translate(address);
+        for mem_reg in &mut self.memory {
+            if mem_reg.write_32(address, value) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn mem_write_bytes(&mut self, address: usize, bytes: &[u8]) -> bool {
+        if let Some(byte) = bytes.first() {
+            if self.mmio_write(address, *byte as u32) {
+                return true;
+            }
+        }
+        let address = translate(address);
+        for mem_reg in &mut self.memory {
+            if mem_reg.write_bytes(address, bytes) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Models a direct-mapped cache in front of instruction fetches
+    pub fn enable_icache(&mut self, line_size: usize, num_sets: usize) {
+        self.icache = Some(Cache::new(line_size, num_sets));
+    }
+
+    pub fn icache(&self) -> Option<&Cache> {
+        self.icache.as_ref()
+    }
+
+    // Models a direct-mapped cache in front of loads and stores
+    pub fn enable_dcache(&mut self, line_size: usize, num_sets: usize) {
+        self.dcache = Some(Cache::new(line_size, num_sets));
+    }
+
+    pub fn dcache(&self) -> Option<&Cache> {
+        self.dcache.as_ref()
+    }
+
+    // Counts cycles with a 5-stage pipeline model as well, or stops if off
+    pub fn set_pipeline(&mut self, enabled: bool) {
+        self.pipeline = if enabled { Some(Pipeline::new()) } else { None };
+    }
+
+    pub fn pipeline(&self) -> Option<&Pipeline> {
+        self.pipeline.as_ref()
+    }
+
+    // Starts logging every branch and jump, or stops and drops the log
+    pub fn set_branch_log(&mut self, enabled: bool) {
+        self.branch_log = if enabled { Some(VecDeque::new()) } else { None };
+    }
+
+    pub fn branch_log(&self) -> Option<&VecDeque<BranchRecord>> {
+        self.branch_log.as_ref()
+    }
+
+    pub fn set_cycle_costs(&mut self, costs: CycleCosts) {
+        self.cycle_costs = costs;
+    }
+
+    pub fn cycle_costs(&self) -> &CycleCosts {
+        &self.cycle_costs
+    }
+
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    // GPR access for the instruction handlers. The index comes from a 5-bit
+    // field, so it is always in range for decoded instructions.
+    fn reg(&self, idx: u32) -> u32 {
+        debug_assert!((idx as usize) < MIPS_REGS);
+        self.curr_state.get_reg(idx as usize).unwrap_or(0)
+    }
+
+    // Writes go to the next state, where $zero stays hardwired to 0
+    fn write_reg(&mut self, idx: u32, val: u32) {
+        debug_assert!((idx as usize) < MIPS_REGS);
+        self.next_state.set_reg(idx as usize, val);
+    }
+
+    fn process_instruction(&mut self) {
+        if let Some(icache) = &mut self.icache {
+            if !is_uncached(self.curr_state.pc as usize)
+                && !icache.access(self.curr_state.pc as usize)
+            {
+                self.cycle_count += self.cycle_costs.miss_penalty as u64;
+            }
+        }
+        if !self.permits(self.curr_state.pc as usize, Access::Execute) {
+            self.bad_vaddr = self.curr_state.pc;
+            self.raise_exception(ExceptionCause::ExecuteProtection);
+            return;
+        }
+        let instr = self.mem_read_32(self.curr_state.pc as usize);
+        if let Some(instr) = instr {
+            // A zero word is `sll $0, $0, 0`, ie, a NOP, so it is executed
+            // like any other instruction rather than halting the machine.
+            let instr = match try_parse_instr(instr) {
+                Some(instr) => instr,
+                None => {
+                    self.raise_exception(ExceptionCause::ReservedInstruction);
+                    return;
+                }
+            };
+            println!("Processing {:?}", instr);
+            if self.uninit_check {
+                self.check_uninit_reads(&instr);
+            }
+            if self.hilo_check {
+                self.check_hilo_hazard(&instr);
+            }
+            self.cycle_count += self.cycle_costs.cost(&instr) as u64;
+            let incr_pc = match &instr {
+                Instr::JType(instr) => self.process_jtype_instruction(instr),
+                Instr::IType(instr) => self.process_itype_instruction(instr),
+                Instr::RType(instr) => self.process_rtype_instruction(instr),
+                Instr::FRType(instr) => self.process_frtype_instruction(instr),
+            };
+            if incr_pc {
+                self.next_state.pc = self.curr_state.pc.wrapping_add(4);
+            }
+            if let Some(log) = &mut self.branch_log {
+                if let Some(target) = branch_target(&instr, self.curr_state.pc) {
+                    let taken = !incr_pc && self.halt_reason.is_none();
+                    if log.len() == BRANCH_LOG_DEPTH {
+                        log.pop_front();
+                    }
+                    log.push_back(BranchRecord {
+                        source: self.curr_state.pc,
+                        target: if taken { self.next_state.pc } else { target },
+                        taken,
+                    });
+                }
+            }
+            self.track_calls(&instr, !incr_pc && self.halt_reason.is_none());
+            if !incr_pc
+                && self.halt_reason.is_none()
+                && self.next_state.pc == self.curr_state.pc
+                && branch_target(&instr, self.curr_state.pc).is_some()
+            {
+                println!("Infinite loop at PC {:#010X}\n", self.curr_state.pc);
+                self.halt(HaltReason::SelfLoop);
+            }
+            if let Some(pipeline) = &mut self.pipeline {
+                // Halting also leaves the PC alone, but flushes nothing
+                let taken = !incr_pc && self.halt_reason.is_none();
+                pipeline.issue(&instr, taken);
+            }
+        } else {
+            self.halt(HaltReason::InvalidPc);
+        }
+    }
+
+    // Pushes a frame for each linking jump or branch taken, and pops back to
+    // the frame a `jr` returns through. A `jr` matching no frame, eg, a
+    // jump table, leaves the stack alone. Tail calls made with `j` push
+    // nothing, so the callee's return pops its caller's frame, as it should.
+    fn track_calls(&mut self, instr: &Instr, taken: bool) {
+        let pc = self.curr_state.pc;
+        let (is_call, is_return) = match instr {
+            Instr::JType(instr) => (matches!(instr.op(), JOp::JAL), false),
+            Instr::IType(instr) => (matches!(instr.op(), IOp::BLTZAL | IOp::BGEZAL), false),
+            Instr::RType(instr) => (
+                matches!(instr.op(), ROp::JALR),
+                matches!(instr.op(), ROp::JR),
+            ),
+            Instr::FRType(_) => (false, false),
+        };
+        if is_call && taken {
+            self.call_stack.push(Frame {
+                call_site: pc,
+                target: self.next_state.pc,
+                return_address: pc.wrapping_add(4),
+                entry: self.next_state,
+            });
+        } else if is_return {
+            let target = self.next_state.pc;
+            if let Some(pos) = self
+                .call_stack
+                .iter()
+                .rposition(|frame| frame.return_address == target)
+            {
+                self.call_stack.truncate(pos);
+            }
+        }
+    }
+
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    fn process_jtype_instruction(&mut self, instr: &JType) -> bool {
         match instr.op() {
             JOp::J => {
                 const TOP_BYTE_MASK: u32 = 0xF0000000;
@@ -264,20 +1655,146 @@ impl MipsComputer {
                 const TOP_BYTE_MASK: u32 = 0xF0000000;
                 let top_byte = self.curr_state.pc & TOP_BYTE_MASK;
                 self.next_state.pc = top_byte | (instr.target() << 2);
-                self.next_state.regs[31] = self.curr_state.pc + 4;
+                self.write_reg(31, self.curr_state.pc.wrapping_add(4));
                 false
             }
         }
     }
 
+    // Warns once per register about reads of GPRs that no instruction has
+    // written yet. $zero counts as always written.
+    fn check_uninit_reads(&mut self, instr: &Instr) {
+        for reg in instr.read_regs() {
+            let bit = 1 << reg;
+            if self.written_regs & bit == 0 && self.warned_regs & bit == 0 {
+                eprintln!(
+                    "Warning: {} at PC {:#010X} reads ${} before it is written",
+                    instr, self.curr_state.pc, REG_NAMES[reg as usize]
+                );
+                self.warned_regs |= bit;
+            }
+        }
+        if let Some(reg) = instr.written_reg() {
+            self.written_regs |= 1 << reg;
+        }
+    }
+
+    // Starts or stops warning about reads of unwritten registers. Enabling
+    // it forgets earlier writes, so only writes from here on count.
+    pub fn set_uninit_check(&mut self, enabled: bool) {
+        self.uninit_check = enabled;
+        self.written_regs = 1;
+        self.warned_regs = 0;
+    }
+
+    // Real MIPS multiply and divide units take several cycles, so reading
+    // HI or LO right after one starts gets a stale or undefined value
+    fn check_hilo_hazard(&mut self, instr: &Instr) {
+        if let Some(warning) = self.hilo_hazard(instr) {
+            eprintln!("{}", warning);
+        }
+    }
+
+    // The warning for `instr` if it reads HI/LO right after a multiply or
+    // divide, remembering whether `instr` is one for the next call
+    fn hilo_hazard(&mut self, instr: &Instr) -> Option<String> {
+        let op = match instr {
+            Instr::RType(instr) => Some(instr.op()),
+            _ => None,
+        };
+        let warning = if self.hilo_pending && matches!(op, Some(ROp::MFHI) | Some(ROp::MFLO)) {
+            Some(format!(
+                "Warning: {} at PC {:#010X} reads HI/LO right after a multiply or divide",
+                instr, self.curr_state.pc
+            ))
+        } else {
+            None
+        };
+        self.hilo_pending = matches!(
+            op,
+            Some(ROp::MULT) | Some(ROp::MULTU) | Some(ROp::DIV) | Some(ROp::DIVU)
+        );
+        warning
+    }
+
+    // Starts or stops warning about HI/LO reads right after a multiply or
+    // divide
+    pub fn set_hilo_check(&mut self, enabled: bool) {
+        self.hilo_check = enabled;
+        self.hilo_pending = false;
+    }
+
+    // Shows the add/subtract overflow and carry indicators in rdump
+    pub fn set_show_flags(&mut self, enabled: bool) {
+        self.show_flags = enabled;
+    }
+
+    pub fn arith_flags(&self) -> ArithFlags {
+        self.flags
+    }
+
+    pub fn clear_arith_flags(&mut self) {
+        self.flags = ArithFlags::default();
+    }
+
+    // Starts or stops checking callee-saved registers across call and finish
+    pub fn set_abi_check(&mut self, enabled: bool) {
+        self.abi_check = enabled;
+        self.abi_violations.clear();
+    }
+
+    // The callee-saved registers the last checked call or finish changed,
+    // with their values before and after
+    pub fn abi_violations(&self) -> &[(Location, u32, u32)] {
+        &self.abi_violations
+    }
+
+    // Compares the callee-saved registers, $s0-$s7, $gp, $sp, $fp and $ra,
+    // against `saved` and reports any that changed
+    fn check_abi(&mut self, saved: &CpuState) {
+        if !self.abi_check {
+            return;
+        }
+        self.abi_violations = (16..24)
+            .chain(28..32)
+            .filter(|reg| saved.regs[*reg] != self.curr_state.regs[*reg])
+            .map(|reg| {
+                (
+                    Location::Reg(reg as u32),
+                    saved.regs[reg],
+                    self.curr_state.regs[reg],
+                )
+            })
+            .collect();
+        if self.abi_violations.is_empty() {
+            println!("Callee-saved registers preserved\n");
+        }
+        for (location, old, new) in &self.abi_violations {
+            println!(
+                "ABI violation: {} was {:#010X}, now {:#010X}",
+                location, old, new
+            );
+        }
+        if !self.abi_violations.is_empty() {
+            println!();
+        }
+    }
+
+    // Base register plus sign-extended offset, wrapping around the 32-bit
+    // address space so that addresses above 0x80000000 stay in range
+    fn effective_address(&self, instr: &IType) -> usize {
+        let offset = sign_extend32(instr.imm(), 16);
+        self.reg(instr.rs()).wrapping_add(offset as u32) as usize
+    }
+
     fn process_itype_instruction(&mut self, instr: &IType) -> bool {
         match instr.op() {
             IOp::BEQ => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                if self.curr_state.regs[instr.rs() as usize]
-                    == self.curr_state.regs[instr.rt() as usize]
-                {
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                if self.reg(instr.rs()) == self.reg(instr.rt()) {
                     self.next_state.pc = new_addr as u32;
                     return false;
                 }
@@ -285,10 +1802,10 @@ impl MipsComputer {
             }
             IOp::BNE => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                if self.curr_state.regs[instr.rs() as usize]
-                    != self.curr_state.regs[instr.rt() as usize]
-                {
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                if self.reg(instr.rs()) != self.reg(instr.rt()) {
                     self.next_state.pc = new_addr as u32;
                     return false;
                 }
@@ -296,8 +1813,10 @@ impl MipsComputer {
             }
             IOp::BLEZ => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
                 if val <= 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
@@ -306,8 +1825,10 @@ impl MipsComputer {
             }
             IOp::BGEZ => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
                 if val >= 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
@@ -316,8 +1837,10 @@ impl MipsComputer {
             }
             IOp::BGTZ => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
                 if val > 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
@@ -326,8 +1849,10 @@ impl MipsComputer {
             }
             IOp::BLTZ => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
                 if val < 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
@@ -336,9 +1861,11 @@ impl MipsComputer {
             }
             IOp::BLTZAL => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
-                self.curr_state.regs[31] = self.curr_state.pc + 4;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
+                self.write_reg(31, self.curr_state.pc.wrapping_add(4));
                 if val < 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
@@ -347,325 +1874,847 @@ impl MipsComputer {
             }
             IOp::BGEZAL => {
                 let ext_off = sign_extend32(instr.imm() << 2, 18);
-                let new_addr = self.curr_state.pc as i32 + ext_off;
-                let val = self.curr_state.regs[instr.rs() as usize] as i32;
-                self.curr_state.regs[31] = self.curr_state.pc + 4;
+                let new_addr = (self.curr_state.pc as i32)
+                    .wrapping_add(4)
+                    .wrapping_add(ext_off);
+                let val = self.reg(instr.rs()) as i32;
+                self.write_reg(31, self.curr_state.pc.wrapping_add(4));
                 if val >= 0 {
                     self.next_state.pc = new_addr as u32;
                     return false;
                 }
                 true
             }
-            IOp::ADDI | IOp::ADDIU => {
+            IOp::ADDI => {
                 let signed_imm = sign_extend32(instr.imm(), 16);
-                self.next_state.regs[instr.rt() as usize] =
-                    (self.curr_state.regs[instr.rs() as usize] as i32 + signed_imm) as u32;
-                true
+                let first = self.reg(instr.rs()) as i32;
+                self.flags = ArithFlags::add(first as u32, signed_imm as u32);
+                match first.checked_add(signed_imm) {
+                    Some(sum) => {
+                        self.write_reg(instr.rt(), sum as u32);
+                        true
+                    }
+                    None => self.raise_exception(ExceptionCause::Overflow),
+                }
             }
-            IOp::SLTI => {
+            IOp::ADDIU => {
                 let signed_imm = sign_extend32(instr.imm(), 16);
-                if (self.curr_state.regs[instr.rs() as usize] as i32) < signed_imm {
-                    self.next_state.regs[instr.rt() as usize] = 1;
-                } else {
-                    self.next_state.regs[instr.rt() as usize] = 0;
+                let first = self.reg(instr.rs());
+                self.flags = ArithFlags::add(first, signed_imm as u32);
+                self.write_reg(instr.rt(), first.wrapping_add(signed_imm as u32));
+                true
+            }
+            IOp::TGEI | IOp::TLTI | IOp::TEQI | IOp::TNEI => {
+                let value = self.reg(instr.rs()) as i32;
+                let imm = sign_extend32(instr.imm(), 16);
+                let trap = match instr.op() {
+                    IOp::TGEI => value >= imm,
+                    IOp::TLTI => value < imm,
+                    IOp::TEQI => value == imm,
+                    _ => value != imm,
+                };
+                if trap {
+                    return self.raise_exception(ExceptionCause::Trap);
                 }
                 true
             }
+            IOp::SLTI => {
+                let imm = sign_extend32(instr.imm(), 16) as u32;
+                self.write_reg(instr.rt(), set_less_than(self.reg(instr.rs()), imm));
+                true
+            }
             IOp::SLTIU => {
-                let signed_imm = sign_extend32(instr.imm(), 16);
-                if self.curr_state.regs[instr.rs() as usize] < signed_imm as u32 {
-                    self.next_state.regs[instr.rt() as usize] = 1;
-                } else {
-                    self.next_state.regs[instr.rt() as usize] = 0;
-                }
+                // The immediate is sign-extended and then compared unsigned,
+                // so 0xFFFF becomes 0xFFFFFFFF and 0x8000 becomes 0xFFFF8000
+                let imm = sign_extend32(instr.imm(), 16) as u32;
+                self.write_reg(
+                    instr.rt(),
+                    set_less_than_unsigned(self.reg(instr.rs()), imm),
+                );
                 true
             }
             IOp::ANDI => {
-                self.next_state.regs[instr.rt() as usize] =
-                    self.curr_state.regs[instr.rs() as usize] & instr.imm();
+                self.write_reg(instr.rt(), self.reg(instr.rs()) & instr.imm());
                 true
             }
             IOp::ORI => {
-                self.next_state.regs[instr.rt() as usize] =
-                    self.curr_state.regs[instr.rs() as usize] | instr.imm();
+                self.write_reg(instr.rt(), self.reg(instr.rs()) | instr.imm());
                 true
             }
             IOp::XORI => {
-                self.next_state.regs[instr.rt() as usize] =
-                    self.curr_state.regs[instr.rs() as usize] ^ instr.imm();
+                self.write_reg(instr.rt(), self.reg(instr.rs()) ^ instr.imm());
                 true
             }
             IOp::LUI => {
-                self.next_state.regs[instr.rt() as usize] = instr.imm() << 16;
+                self.write_reg(instr.rt(), instr.imm() << 16);
                 true
             }
             IOp::LB => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let byte = self
-                    .mem_read_8(address as usize)
-                    .expect("Cannot read from invalid address");
-                self.next_state.regs[instr.rt() as usize] = sign_extend32(byte as u32, 8) as u32;
-                true
+                let address = self.effective_address(instr);
+                match self.load_8(address) {
+                    Some(byte) => {
+                        self.write_reg(instr.rt(), sign_extend32(byte as u32, 8) as u32);
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
             }
             IOp::LH => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let halfword = self
-                    .mem_read_16(address as usize)
-                    .expect("Cannot read from invalid address");
-                self.next_state.regs[instr.rt() as usize] =
-                    sign_extend32(halfword as u32, 16) as u32;
-                true
+                let address = self.effective_address(instr);
+                match self.load_16(address) {
+                    Some(halfword) => {
+                        self.write_reg(instr.rt(), sign_extend32(halfword as u32, 16) as u32);
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
             }
             IOp::LW => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let word = self
-                    .mem_read_32(address as usize)
-                    .expect("Cannot read from invalid address");
-                self.next_state.regs[instr.rt() as usize] = word;
-                true
+                let address = self.effective_address(instr);
+                match self.load_32(address) {
+                    Some(word) => {
+                        self.write_reg(instr.rt(), word);
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
             }
             IOp::LBU => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let byte = self
-                    .mem_read_8(address as usize)
-                    .expect("Cannot read from invalid address");
-                self.next_state.regs[instr.rt() as usize] = byte as u32;
-                true
+                let address = self.effective_address(instr);
+                match self.load_8(address) {
+                    Some(byte) => {
+                        self.write_reg(instr.rt(), byte as u32);
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
             }
             IOp::LHU => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let halfword = self
-                    .mem_read_16(address as usize)
-                    .expect("Cannot read from invalid address");
-                self.next_state.regs[instr.rt() as usize] = halfword as u32;
-                true
+                let address = self.effective_address(instr);
+                match self.load_16(address) {
+                    Some(halfword) => {
+                        self.write_reg(instr.rt(), halfword as u32);
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
             }
             IOp::SB => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                const MASK: u32 = 0xFF;
-                let written = self.mem_write_32(
-                    address as usize,
-                    self.curr_state.regs[instr.rt() as usize] & MASK,
-                );
-                assert!(written);
-                true
+                let address = self.effective_address(instr);
+                if self.store_8(address, self.reg(instr.rt()) as u8) {
+                    true
+                } else {
+                    self.address_error(instr, true)
+                }
             }
             IOp::SH => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                const MASK: u32 = 0xFFFF;
-                let written = self.mem_write_32(
-                    address as usize,
-                    self.curr_state.regs[instr.rt() as usize] & MASK,
-                );
-                assert!(written);
-                true
+                let address = self.effective_address(instr);
+                if self.store_16(address, self.reg(instr.rt()) as u16) {
+                    true
+                } else {
+                    self.address_error(instr, true)
+                }
             }
             IOp::SW => {
-                let offset = sign_extend32(instr.imm(), 16);
-                let address = self.curr_state.regs[instr.rs() as usize] as i32 + offset;
-                let written =
-                    self.mem_write_32(address as usize, self.curr_state.regs[instr.rt() as usize]);
-                assert!(written);
-                true
+                let address = self.effective_address(instr);
+                if self.store_32(address, self.reg(instr.rt())) {
+                    true
+                } else {
+                    self.address_error(instr, true)
+                }
+            }
+            IOp::LWC1 => {
+                let address = self.effective_address(instr);
+                match self.load_32(address) {
+                    Some(word) => {
+                        self.next_state.fpr[instr.rt() as usize] = word;
+                        true
+                    }
+                    None => self.address_error(instr, false),
+                }
+            }
+            IOp::SWC1 => {
+                let address = self.effective_address(instr);
+                if self.store_32(address, self.curr_state.fpr[instr.rt() as usize]) {
+                    true
+                } else {
+                    self.address_error(instr, true)
+                }
             }
+            // A prefetch hint or a cache maintenance operation, neither of
+            // which changes what the program computes. The cache models
+            // only count hits and misses, so there is nothing to flush.
+            IOp::PREF | IOp::CACHE => true,
         }
     }
 
     fn process_rtype_instruction(&mut self, instr: &RType) -> bool {
         match instr.op() {
             ROp::SLL => {
-                self.next_state.regs[instr.rd() as usize] =
-                    self.curr_state.regs[instr.rt() as usize] << instr.shamt();
+                self.write_reg(instr.rd(), self.reg(instr.rt()) << instr.shamt());
                 true
             }
             ROp::SRL => {
-                self.next_state.regs[instr.rd() as usize] =
-                    self.curr_state.regs[instr.rt() as usize] >> instr.shamt();
+                self.write_reg(instr.rd(), self.reg(instr.rt()) >> instr.shamt());
                 true
             }
             ROp::SRA => {
-                self.next_state.regs[instr.rd() as usize] =
-                    ((self.curr_state.regs[instr.rt() as usize] as i32) >> instr.shamt()) as u32;
+                self.write_reg(
+                    instr.rd(),
+                    ((self.reg(instr.rt()) as i32) >> instr.shamt()) as u32,
+                );
                 true
             }
             ROp::SLLV => {
                 const MASK: u32 = 0x1F;
-                let shift = self.curr_state.regs[instr.rs() as usize] & MASK;
-                self.next_state.regs[instr.rd() as usize] =
-                    self.curr_state.regs[instr.rt() as usize] << shift;
+                let shift = self.reg(instr.rs()) & MASK;
+                self.write_reg(instr.rd(), self.reg(instr.rt()) << shift);
                 true
             }
             ROp::SRLV => {
                 const MASK: u32 = 0x1F;
-                let shift = self.curr_state.regs[instr.rs() as usize] & MASK;
-                self.next_state.regs[instr.rd() as usize] =
-                    self.curr_state.regs[instr.rt() as usize] >> shift;
+                let shift = self.reg(instr.rs()) & MASK;
+                self.write_reg(instr.rd(), self.reg(instr.rt()) >> shift);
                 true
             }
             ROp::SRAV => {
                 const MASK: u32 = 0x1F;
-                let shift = self.curr_state.regs[instr.rs() as usize] & MASK;
-                self.next_state.regs[instr.rd() as usize] =
-                    ((self.curr_state.regs[instr.rt() as usize] as i32) >> shift) as u32;
+                let shift = self.reg(instr.rs()) & MASK;
+                self.write_reg(instr.rd(), ((self.reg(instr.rt()) as i32) >> shift) as u32);
                 true
             }
             ROp::JR => {
-                self.next_state.pc = self.curr_state.regs[instr.rs() as usize];
+                self.next_state.pc = self.reg(instr.rs());
                 false
             }
             ROp::JALR => {
-                self.next_state.pc = self.curr_state.regs[instr.rs() as usize];
-                self.curr_state.regs[instr.rd() as usize] = self.curr_state.pc + 4;
+                self.next_state.pc = self.reg(instr.rs());
+                self.write_reg(instr.rd(), self.curr_state.pc.wrapping_add(4));
                 false
             }
             ROp::ADD => {
-                self.next_state.regs[instr.rd() as usize] =
-                    (self.curr_state.regs[instr.rs() as usize] as i32
-                        + self.curr_state.regs[instr.rt() as usize] as i32)
-                        as u32;
-                true
+                let first = self.reg(instr.rs()) as i32;
+                let second = self.reg(instr.rt()) as i32;
+                self.flags = ArithFlags::add(first as u32, second as u32);
+                match first.checked_add(second) {
+                    Some(sum) => {
+                        self.write_reg(instr.rd(), sum as u32);
+                        true
+                    }
+                    None => self.raise_exception(ExceptionCause::Overflow),
+                }
             }
             ROp::ADDU => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.regs
-                    [instr.rs() as usize]
-                    + self.curr_state.regs[instr.rt() as usize];
+                let first = self.reg(instr.rs());
+                let second = self.reg(instr.rt());
+                self.flags = ArithFlags::add(first, second);
+                self.write_reg(instr.rd(), first.wrapping_add(second));
                 true
             }
             ROp::SUB => {
-                let first = self.curr_state.regs[instr.rs() as usize] as i32;
-                let second = self.curr_state.regs[instr.rt() as usize] as i32;
-                self.next_state.regs[instr.rd() as usize] = (first - second) as u32;
-                true
+                let first = self.reg(instr.rs()) as i32;
+                let second = self.reg(instr.rt()) as i32;
+                self.flags = ArithFlags::sub(first as u32, second as u32);
+                match first.checked_sub(second) {
+                    Some(diff) => {
+                        self.write_reg(instr.rd(), diff as u32);
+                        true
+                    }
+                    None => self.raise_exception(ExceptionCause::Overflow),
+                }
             }
             ROp::SUBU => {
-                let first = self.curr_state.regs[instr.rs() as usize];
-                let second = self.curr_state.regs[instr.rt() as usize];
-                self.next_state.regs[instr.rd() as usize] = first - second;
+                let first = self.reg(instr.rs());
+                let second = self.reg(instr.rt());
+                self.flags = ArithFlags::sub(first, second);
+                self.write_reg(instr.rd(), first.wrapping_sub(second));
                 true
             }
             ROp::AND => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.regs
-                    [instr.rs() as usize]
-                    & self.curr_state.regs[instr.rt() as usize];
+                self.write_reg(instr.rd(), self.reg(instr.rs()) & self.reg(instr.rt()));
                 true
             }
             ROp::OR => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.regs
-                    [instr.rs() as usize]
-                    | self.curr_state.regs[instr.rt() as usize];
+                self.write_reg(instr.rd(), self.reg(instr.rs()) | self.reg(instr.rt()));
                 true
             }
             ROp::XOR => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.regs
-                    [instr.rs() as usize]
-                    ^ self.curr_state.regs[instr.rt() as usize];
+                self.write_reg(instr.rd(), self.reg(instr.rs()) ^ self.reg(instr.rt()));
                 true
             }
             ROp::NOR => {
-                self.next_state.regs[instr.rd() as usize] = !(self.curr_state.regs
-                    [instr.rs() as usize]
-                    | self.curr_state.regs[instr.rt() as usize]);
+                self.write_reg(instr.rd(), !(self.reg(instr.rs()) | self.reg(instr.rt())));
                 true
             }
             ROp::SLT => {
-                let first = self.curr_state.regs[instr.rs() as usize] as i32;
-                let second = self.curr_state.regs[instr.rt() as usize] as i32;
-                if first < second {
-                    self.next_state.regs[instr.rd() as usize] = 1;
-                } else {
-                    self.next_state.regs[instr.rd() as usize] = 0;
-                }
+                let first = self.reg(instr.rs());
+                let second = self.reg(instr.rt());
+                self.write_reg(instr.rd(), set_less_than(first, second));
                 true
             }
             ROp::SLTU => {
-                let first = self.curr_state.regs[instr.rs() as usize];
-                let second = self.curr_state.regs[instr.rt() as usize];
-                if first < second {
-                    self.next_state.regs[instr.rd() as usize] = 1;
-                } else {
-                    self.next_state.regs[instr.rd() as usize] = 0;
-                }
+                let first = self.reg(instr.rs());
+                let second = self.reg(instr.rt());
+                self.write_reg(instr.rd(), set_less_than_unsigned(first, second));
                 true
             }
             ROp::MULT => {
-                let first = self.curr_state.regs[instr.rs() as usize] as i64;
-                let second = self.curr_state.regs[instr.rt() as usize] as i64;
+                let first = self.reg(instr.rs()) as i32 as i64;
+                let second = self.reg(instr.rt()) as i32 as i64;
                 let product = (first * second) as u64;
-                const LOWER_MASK: u64 = (!(0 as u32)) as u64;
+                const LOWER_MASK: u64 = (!0_u32) as u64;
                 const UPPER_MASK: u64 = LOWER_MASK << 32;
                 self.next_state.hi = ((product & UPPER_MASK) >> 32) as u32;
                 self.next_state.lo = (product & LOWER_MASK) as u32;
                 true
             }
             ROp::MULTU => {
-                let first = self.curr_state.regs[instr.rs() as usize] as u64;
-                let second = self.curr_state.regs[instr.rt() as usize] as u64;
+                let first = self.reg(instr.rs()) as u64;
+                let second = self.reg(instr.rt()) as u64;
                 let product = first * second;
-                const LOWER_MASK: u64 = (!(0 as u32)) as u64;
+                const LOWER_MASK: u64 = (!0_u32) as u64;
                 const UPPER_MASK: u64 = LOWER_MASK << 32;
                 self.next_state.hi = ((product & UPPER_MASK) >> 32) as u32;
                 self.next_state.lo = (product & LOWER_MASK) as u32;
                 true
             }
             ROp::DIV => {
-                let first = self.curr_state.regs[instr.rs() as usize] as i64;
-                let second = self.curr_state.regs[instr.rt() as usize] as i64;
-                let product = (first / second) as u64;
-                const LOWER_MASK: u64 = (!(0 as u32)) as u64;
-                const UPPER_MASK: u64 = LOWER_MASK << 32;
-                self.next_state.hi = ((product & UPPER_MASK) >> 32) as u32;
-                self.next_state.lo = (product & LOWER_MASK) as u32;
+                let first = self.reg(instr.rs()) as i32;
+                let second = self.reg(instr.rt()) as i32;
+                // The result of dividing by zero is unpredictable on MIPS,
+                // so HI and LO are left as they were. INT_MIN / -1 wraps.
+                if second != 0 {
+                    self.next_state.lo = first.wrapping_div(second) as u32;
+                    self.next_state.hi = first.wrapping_rem(second) as u32;
+                }
                 true
             }
             ROp::DIVU => {
-                let first = self.curr_state.regs[instr.rs() as usize] as u64;
-                let second = self.curr_state.regs[instr.rt() as usize] as u64;
-                let product = first / second;
-                const LOWER_MASK: u64 = (!(0 as u32)) as u64;
-                const UPPER_MASK: u64 = LOWER_MASK << 32;
-                self.next_state.hi = ((product & UPPER_MASK) >> 32) as u32;
-                self.next_state.lo = (product & LOWER_MASK) as u32;
+                let first = self.reg(instr.rs());
+                let second = self.reg(instr.rt());
+                if let (Some(quotient), Some(remainder)) =
+                    (first.checked_div(second), first.checked_rem(second))
+                {
+                    self.next_state.lo = quotient;
+                    self.next_state.hi = remainder;
+                }
+                true
+            }
+            ROp::MOVF | ROp::MOVT => {
+                let cc = instr.rt() >> 2;
+                let want = matches!(instr.op(), ROp::MOVT);
+                if self.curr_state.get_fcc(cc) == want {
+                    self.write_reg(instr.rd(), self.reg(instr.rs()));
+                }
                 true
             }
             ROp::MFHI => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.hi;
+                self.write_reg(instr.rd(), self.curr_state.hi);
                 true
             }
             ROp::MFLO => {
-                self.next_state.regs[instr.rd() as usize] = self.curr_state.lo;
+                self.write_reg(instr.rd(), self.curr_state.lo);
                 true
             }
             ROp::MTHI => {
-                self.next_state.hi = self.curr_state.regs[instr.rs() as usize];
+                self.next_state.hi = self.reg(instr.rs());
                 true
             }
             ROp::MTLO => {
-                self.next_state.lo = self.curr_state.regs[instr.rs() as usize];
+                self.next_state.lo = self.reg(instr.rs());
                 true
             }
+            ROp::BREAK => {
+                // Stop on the break itself so it can be inspected
+                println!("Break at PC {:#010X}\n", self.curr_state.pc);
+                self.next_state.pc = self.curr_state.pc;
+                self.halt(HaltReason::Break);
+                false
+            }
             ROp::SYSCALL => {
-                if self.curr_state.regs[instr.rd() as usize] == 0xA {
-                    self.run_bit = false;
+                // The syscall number is passed in $v0
+                match self.reg(2) {
+                    0xA => self.halt(HaltReason::Exit),
+                    SYSCALL_PRINTF => self.syscall_printf(),
+                    _ => {}
                 }
                 true
             }
         }
     }
 
+    // Reads a NUL-terminated string, stopping at unmapped memory or after
+    // MAX_STRING_LEN bytes
+    fn read_c_string(&self, address: u32) -> Vec<u8> {
+        const MAX_STRING_LEN: u32 = 4096;
+        (0..MAX_STRING_LEN)
+            .map_while(|off| self.mem_read_8(address.wrapping_add(off) as usize))
+            .take_while(|byte| *byte != 0)
+            .collect()
+    }
+
+    // Formats the string at $a0 with up to three arguments from $a1..$a3.
+    // Supports %d, %x, %c, %s and %%; anything else is printed as is.
+    fn syscall_printf(&mut self) {
+        let format = self.read_c_string(self.reg(4));
+        let mut args = (5..=7).map(|reg| self.reg(reg));
+        let mut out = Vec::new();
+        let mut bytes = format.iter().copied();
+        while let Some(byte) = bytes.next() {
+            if byte != b'%' {
+                out.push(byte);
+                continue;
+            }
+            let spec = match bytes.next() {
+                Some(spec) => spec,
+                None => {
+                    out.push(b'%');
+                    break;
+                }
+            };
+            if spec == b'%' {
+                out.push(b'%');
+                continue;
+            }
+            let arg = match spec {
+                b'd' | b'x' | b'c' | b's' => args.next(),
+                _ => None,
+            };
+            match (spec, arg) {
+                (b'd', Some(arg)) => out.extend((arg as i32).to_string().bytes()),
+                (b'x', Some(arg)) => out.extend(format!("{:x}", arg).bytes()),
+                (b'c', Some(arg)) => out.push(arg as u8),
+                (b's', Some(arg)) => out.extend(self.read_c_string(arg)),
+                _ => out.extend([b'%', spec]),
+            }
+        }
+        // Output errors are not the simulated program's concern
+        let _ = self.console.write_all(&out);
+        let _ = self.console.flush();
+    }
+
+    // Reports how the faulting address was formed, since a bad base and a
+    // bad offset look the same once added together
+    fn address_error(&mut self, instr: &IType, is_store: bool) -> bool {
+        let address = self.effective_address(instr) as u32;
+        let offset = sign_extend32(instr.imm(), 16);
+        println!(
+            "Bad address {:#010X} = ${} ({:#010X}) {} {}",
+            address,
+            REG_NAMES[instr.rs() as usize],
+            self.reg(instr.rs()),
+            if offset < 0 { '-' } else { '+' },
+            offset.unsigned_abs()
+        );
+        self.bad_vaddr = address;
+        let cause = match (is_store, self.is_mapped(address as usize)) {
+            // The access failed despite the address being mapped, so it
+            // was the region's permissions that refused it
+            (false, true) if !self.permits(address as usize, Access::Read) => {
+                ExceptionCause::ReadProtection
+            }
+            (true, true) if !self.permits(address as usize, Access::Write) => {
+                ExceptionCause::WriteProtection
+            }
+            (false, _) => ExceptionCause::AddressErrorLoad,
+            (true, _) => ExceptionCause::AddressErrorStore,
+        };
+        self.raise_exception(cause)
+    }
+
+    // The destination is left unwritten and the PC stays on the faulting
+    // instruction. Returns false so the caller does not advance the PC.
+    fn raise_exception(&mut self, cause: ExceptionCause) -> bool {
+        println!("Exception {:?} at PC {:#010X}\n", cause, self.curr_state.pc);
+        self.last_exception = Some((cause, self.curr_state.pc));
+        self.next_state.pc = self.curr_state.pc;
+        self.halt(HaltReason::Exception(cause));
+        false
+    }
+
+    fn halt(&mut self, reason: HaltReason) {
+        self.run_bit = false;
+        self.halt_reason = Some(reason);
+    }
+
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    // Reports every change to `reg` made by an instruction. Returns false
+    // if there is no such register.
+    pub fn watch_reg(&mut self, reg: usize) -> bool {
+        if reg >= MIPS_REGS {
+            return false;
+        }
+        if !self.reg_watches.contains(&reg) {
+            self.reg_watches.push(reg);
+        }
+        true
+    }
+
+    // One line per watched register the instruction at `pc` changed from
+    // `prev_regs`
+    fn reg_watch_reports(&self, pc: u32, prev_regs: &[u32; MIPS_REGS]) -> Vec<String> {
+        self.reg_watches
+            .iter()
+            .filter(|reg| prev_regs[**reg] != self.curr_state.regs[**reg])
+            .map(|&reg| {
+                format!(
+                    "Watch ${} at PC {:#010X}: {:#010X} -> {:#010X}",
+                    REG_NAMES[reg], pc, prev_regs[reg], self.curr_state.regs[reg]
+                )
+            })
+            .collect()
+    }
+
+    pub fn clear_reg_watches(&mut self) {
+        self.reg_watches.clear();
+    }
+
+    pub fn reg_watches(&self) -> &[usize] {
+        &self.reg_watches
+    }
+
+    pub fn config(&self) -> &MipsConfig {
+        &self.config
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = RegionInfo> + '_ {
+        self.memory.iter().map(|mem_reg| RegionInfo {
+            name: mem_reg.name,
+            start: mem_reg.start,
+            size: mem_reg.size,
+            perms: mem_reg.perms,
+        })
+    }
+
+    // Whether `address` is backed by a memory region. The memory-mapped
+    // device registers are not part of any region.
+    pub fn is_mapped(&self, address: usize) -> bool {
+        let address = translate(address);
+        self.memory
+            .iter()
+            .any(|mem_reg| mem_reg.contains_address(address))
+    }
+
+    // Reads a byte for a debugger, without touching statistics or
+    // consuming keyboard input
+    pub fn read_byte(&self, address: usize) -> Option<u8> {
+        self.mem_read_8(address)
+    }
+
+    pub fn bad_vaddr(&self) -> u32 {
+        self.bad_vaddr
+    }
+
+    // The cause and EPC of the last exception raised
+    pub fn last_exception(&self) -> Option<(ExceptionCause, u32)> {
+        self.last_exception
+    }
+
+    fn exc_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        writeln!(out, "\nException state :")?;
+        writeln!(out, "-----------------------------------------")?;
+        match self.last_exception {
+            Some((cause, epc)) => {
+                writeln!(out, "Cause             : {:?}", cause)?;
+                writeln!(out, "EPC               : {:#010X}", epc)?;
+            }
+            None => writeln!(out, "Cause             : none raised")?,
+        }
+        writeln!(out, "BadVAddr          : {:#010X}", self.bad_vaddr)?;
+        let handler = match self.mem_read_32(EXCEPTION_VECTOR) {
+            Some(word) if word != 0 => "installed",
+            _ => "none",
+        };
+        writeln!(
+            out,
+            "Handler           : {} at {:#010X}\n",
+            handler, EXCEPTION_VECTOR
+        )?;
+        Ok(())
+    }
+
+    pub fn exc(&self, file: &mut File) -> io::Result<()> {
+        self.exc_intern(&mut io::stdout())?;
+        self.exc_intern(file)?;
+        Ok(())
+    }
+
+    // Zeroes the instruction and cycle counts, the memory statistics, the
+    // icache hit/miss counts and the pipeline counts, leaving registers and
+    // memory alone
+    pub fn reset_stats(&mut self) {
+        self.instr_cnt = 0;
+        self.cycle_count = 0;
+        self.mem_stats = MemStats::default();
+        if let Some(icache) = &mut self.icache {
+            icache.reset_stats();
+        }
+        if let Some(dcache) = &mut self.dcache {
+            dcache.reset_stats();
+        }
+        if let Some(pipeline) = &mut self.pipeline {
+            pipeline.reset_stats();
+        }
+    }
+
+    /// How many instructions have retired since the counters were last
+    /// reset
+    ///
+    /// ```
+    /// use mips_sim::asm::assemble;
+    /// use mips_sim::sim::{MipsComputer, MEM_TEXT_START};
+    ///
+    /// let mut comp = MipsComputer::new(&[]).unwrap();
+    /// let words = assemble(&"sll $zero, $zero, 0\n".repeat(4)).unwrap();
+    /// comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+    /// comp.run(3);
+    /// assert_eq!(comp.instr_count(), 3);
+    /// assert_eq!(comp.pc(), MEM_TEXT_START as u32 + 12);
+    /// ```
+    pub fn instr_count(&self) -> u32 {
+        self.instr_cnt
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.curr_state.pc
+    }
+
+    // Whether to halt once the PC walks past the last loaded text word,
+    // instead of executing the zeroed memory after it as NOPs
+    pub fn set_halt_on_text_end(&mut self, enabled: bool) {
+        self.halt_on_text_end = enabled;
+    }
+
+    fn process_frtype_instruction(&mut self, instr: &FRType) -> bool {
+        // Arithmetic follows IEEE 754, so NaNs and infinities propagate
+        let fs = f32::from_bits(self.curr_state.fpr[instr.fs() as usize]);
+        let ft = f32::from_bits(self.curr_state.fpr[instr.ft() as usize]);
+        let result = match instr.op() {
+            FOp::ADDS => fs + ft,
+            FOp::SUBS => fs - ft,
+            FOp::MULS => fs * ft,
+            FOp::DIVS => fs / ft,
+            // The moves copy raw bit patterns without any conversion
+            FOp::MFC1 => {
+                self.write_reg(instr.ft(), self.curr_state.fpr[instr.fs() as usize]);
+                return true;
+            }
+            FOp::MTC1 => {
+                self.next_state.fpr[instr.fs() as usize] = self.reg(instr.ft());
+                return true;
+            }
+        };
+        self.next_state.fpr[instr.fd() as usize] = result.to_bits();
+        true
+    }
+
     pub fn cycle(&mut self) {
+        if let Some(max) = self.max_instructions {
+            if self.instr_cnt >= max {
+                println!("Instruction limit of {} reached\n", max);
+                self.halt(HaltReason::InstructionLimit);
+                return;
+            }
+        }
+        let prev_state = self.curr_state;
+        let prev_run_bit = self.run_bit;
+        let prev_halt_reason = self.halt_reason;
+        let prev_flags = self.flags;
+        let prev_pc = self.curr_state.pc;
+        let prev_regs = self.curr_state.regs;
+        let prev_cycle_count = self.cycle_count;
+        self.undo_stores.clear();
+        let traced = match self.trace {
+            Some(_) => self
+                .mem_read_32(prev_pc as usize)
+                .map(|word| (word, self.disassemble(prev_pc))),
+            None => None,
+        };
         self.process_instruction();
         self.curr_state = self.next_state;
         self.instr_cnt += 1;
+        if let Some((word, mnemonic)) = traced {
+            let reg_writes = (0..MIPS_REGS)
+                .filter(|reg| self.trace_regs & (1 << reg) != 0)
+                .filter(|reg| prev_regs[*reg] != self.curr_state.regs[*reg])
+                .map(|reg| (reg as u32, self.curr_state.regs[reg]))
+                .collect();
+            let event = TraceEvent {
+                pc: prev_pc,
+                word,
+                mnemonic,
+                reg_writes,
+                mem_writes: std::mem::take(&mut self.traced_stores),
+                cycles: self.cycle_count - prev_cycle_count,
+                total_cycles: self.cycle_count,
+            };
+            self.record_trace(&event);
+        }
+        // Only entering a range counts, so a loop inside it can run on
+        let entered = self.in_breakpoint(self.curr_state.pc);
+        if entered.is_some() && entered != self.in_breakpoint(prev_pc) {
+            self.break_hit = true;
+        }
+        for report in self.reg_watch_reports(prev_pc, &prev_regs) {
+            println!("{}", report);
+        }
+        if self.run_bit && self.curr_state.pc == MAIN_RETURN_SENTINEL {
+            println!("Returned from main\n");
+            self.halt(HaltReason::ReturnFromMain);
+        }
+        let pc = self.curr_state.pc as usize;
+        if self.run_bit
+            && self.halt_on_text_end
+            && self.text_end > self.config.text.start
+            && (self.text_end..self.config.text.start + self.config.text.size).contains(&pc)
+        {
+            println!("PC {:#010X} ran off the end of the loaded text\n", pc);
+            self.halt(HaltReason::RanOffEnd);
+        }
+        if self.config.history_depth > 0 {
+            if self.history.len() == self.config.history_depth {
+                self.history.pop_front();
+            }
+            let mut deltas = std::mem::take(&mut self.undo_stores);
+            deltas.extend(state_deltas(&prev_state, &self.curr_state));
+            self.history.push_back(UndoRecord {
+                pc: prev_state.pc,
+                deltas,
+                run_bit: prev_run_bit,
+                halt_reason: prev_halt_reason,
+                flags: prev_flags,
+            });
+        }
+    }
+
+    // Keeps the last `depth` cycles for `back`, 0 turning the history off.
+    // Shrinking the depth drops the oldest cycles.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.config.history_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
+
+    // Undoes up to `count` cycles, returning how many were undone. Register
+    // and memory contents come back, but device I/O and statistics do not.
+    pub fn back(&mut self, count: usize) -> usize {
+        for undone in 0..count {
+            let record = match self.history.pop_back() {
+                Some(record) => record,
+                None => return undone,
+            };
+            self.revert(&record);
+            self.instr_cnt = self.instr_cnt.saturating_sub(1);
+        }
+        count
+    }
+
+    // Puts back every value `record` logged, latest change first
+    pub fn revert(&mut self, record: &UndoRecord) {
+        for delta in record.deltas.iter().rev() {
+            match delta {
+                Delta::Reg(reg, old) => self.curr_state.regs[*reg] = *old,
+                Delta::Fpr(reg, old) => self.curr_state.fpr[*reg] = *old,
+                Delta::Hi(old) => self.curr_state.hi = *old,
+                Delta::Lo(old) => self.curr_state.lo = *old,
+                Delta::Fcc(old) => self.curr_state.fcc = *old,
+                Delta::Mem(address, old) => {
+                    self.mem_write_bytes(*address, old);
+                }
+            }
+        }
+        self.curr_state.pc = record.pc;
+        self.next_state = self.curr_state;
+        self.run_bit = record.run_bit;
+        self.halt_reason = record.halt_reason;
+        self.flags = record.flags;
+    }
+
+    // The record of the last cycle, if history is kept
+    pub fn last_undo_record(&self) -> Option<&UndoRecord> {
+        self.history.back()
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    // Caps the number of instructions retired, None meaning no limit
+    pub fn set_max_instructions(&mut self, max: Option<u32>) {
+        self.max_instructions = max;
+    }
+
+    pub fn max_instructions(&self) -> Option<u32> {
+        self.max_instructions
+    }
+
+    // Adds a breakpoint that stops the run commands when the PC enters
+    // [low, high]. Returns false if an existing range already covers it;
+    // existing ranges it covers are dropped.
+    pub fn add_breakpoint(&mut self, low: u32, high: u32) -> bool {
+        let covers =
+            |outer: &(u32, u32), inner: &(u32, u32)| outer.0 <= inner.0 && inner.1 <= outer.1;
+        let range = (low.min(high), low.max(high));
+        if self.breakpoints.iter().any(|other| covers(other, &range)) {
+            return false;
+        }
+        self.breakpoints.retain(|other| !covers(&range, other));
+        self.breakpoints.push(range);
+        true
+    }
+
+    pub fn breakpoints(&self) -> &[(u32, u32)] {
+        &self.breakpoints
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    fn in_breakpoint(&self, pc: u32) -> Option<(u32, u32)> {
+        self.breakpoints
+            .iter()
+            .copied()
+            .find(|(low, high)| (*low..=*high).contains(&pc))
+    }
+
+    // A flag which, once set, stops the current run after the instruction
+    // in progress. Setting it between runs has no effect.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
+
+    // Clears the stop flags before a run, resuming an interrupted machine
+    fn begin_run(&mut self) {
+        self.break_hit = false;
+        self.interrupt.store(false, Ordering::SeqCst);
+        if self.halt_reason == Some(HaltReason::Interrupted) {
+            self.halt_reason = None;
+        }
+    }
+
+    // Takes the breakpoint flag set by cycle() or the interrupt flag,
+    // reporting the stop
+    fn take_break_hit(&mut self) -> bool {
+        if self.interrupt.swap(false, Ordering::SeqCst) {
+            println!("Interrupted at {:#010X}\n", self.curr_state.pc);
+            self.halt_reason = Some(HaltReason::Interrupted);
+            return true;
+        }
+        let hit = self.break_hit;
+        if hit {
+            println!("Breakpoint at {:#010X}\n", self.curr_state.pc);
+            self.break_hit = false;
+        }
+        hit
     }
 
     pub fn run(&mut self, num_cycles: u32) {
+        self.begin_run();
         if !self.run_bit {
             println!("Can't simulate, Simulator halted\n");
         } else {
@@ -676,6 +2725,9 @@ impl MipsComputer {
                     break;
                 }
                 self.cycle();
+                if self.take_break_hit() {
+                    break;
+                }
             }
         }
     }
@@ -689,14 +2741,156 @@ impl MipsComputer {
             println!("Can't simulate, Simulator halted\n");
         } else {
             println!("Simulating...\n");
+            self.begin_run();
             while self.run_bit {
                 self.cycle();
+                if self.take_break_hit() {
+                    return;
+                }
             }
             println!("Simulator halted\n");
         }
     }
 
-    fn mdump_intern<T: Write>(&self, start: usize, stop: usize, out: &mut T) -> io::Result<()> {
+    // Runs until the machine halts, returning the instructions executed, or
+    // None if `budget` instructions were not enough. Breakpoints are not
+    // checked, but an interrupt gives up like an exhausted budget.
+    pub fn run_to_halt(&mut self, budget: u32) -> Option<u32> {
+        let start = self.instr_cnt;
+        self.begin_run();
+        for _ in 0..budget {
+            if !self.run_bit {
+                break;
+            }
+            self.cycle();
+            if self.interrupt.swap(false, Ordering::SeqCst) {
+                return None;
+            }
+        }
+        if self.run_bit {
+            None
+        } else {
+            Some(self.instr_cnt - start)
+        }
+    }
+
+    // Runs until the PC reaches `address`, executing at least one
+    // instruction so that repeated calls advance through a loop
+    pub fn run_until(&mut self, address: u32) {
+        if !self.run_bit {
+            println!("Can't simulate, Simulator halted\n");
+        } else {
+            println!("Simulating until {:#010X}...\n", address);
+            self.begin_run();
+            loop {
+                self.cycle();
+                if !self.run_bit {
+                    println!("Simulator halted\n");
+                    break;
+                }
+                if self.take_break_hit() {
+                    break;
+                }
+                if self.curr_state.pc == address {
+                    println!("Stopped at {:#010X}\n", address);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Calls the subroutine at `address` with up to four arguments in
+    // $a0..$a3, running until it returns to a sentinel $ra. Returns $v0 if
+    // it did return; PC and $ra are put back either way.
+    pub fn call(&mut self, address: u32, args: &[u32]) -> Option<u32> {
+        if !self.run_bit {
+            println!("Can't simulate, Simulator halted\n");
+            return None;
+        }
+        let saved_pc = self.curr_state.pc;
+        let saved_ra = self.curr_state.regs[31];
+        for (reg, arg) in (4..8).zip(args) {
+            self.curr_state.set_reg(reg, *arg);
+        }
+        self.curr_state.set_reg(31, CALL_RETURN_SENTINEL);
+        self.curr_state.pc = address;
+        self.next_state = self.curr_state;
+        let entry_state = self.curr_state;
+        println!("Calling {:#010X}...\n", address);
+        self.begin_run();
+        let mut returned = false;
+        while self.run_bit {
+            self.cycle();
+            if self.curr_state.pc == CALL_RETURN_SENTINEL {
+                returned = true;
+                break;
+            }
+            if self.take_break_hit() {
+                break;
+            }
+        }
+        if returned {
+            self.check_abi(&entry_state);
+        } else {
+            println!("Call did not return\n");
+        }
+        self.curr_state.pc = saved_pc;
+        self.curr_state.set_reg(31, saved_ra);
+        self.next_state = self.curr_state;
+        returned.then_some(self.curr_state.regs[2])
+    }
+
+    // Runs until the current subroutine returns, ie, until its frame is
+    // popped off the call stack. With no call on the stack, eg, when
+    // stopped before the first jal, runs until PC reaches $ra instead, and
+    // skips the ABI check as there is no entry state to check against.
+    pub fn finish(&mut self) {
+        if !self.run_bit {
+            println!("Can't simulate, Simulator halted\n");
+            return;
+        }
+        let depth = self.call_stack.len();
+        let frame = self.call_stack.last().copied();
+        let return_address = match frame {
+            Some(frame) => frame.return_address,
+            None => self.curr_state.regs[31],
+        };
+        println!("Running until return to {:#010X}...\n", return_address);
+        self.begin_run();
+        loop {
+            self.cycle();
+            if !self.run_bit {
+                println!("Simulator halted\n");
+                break;
+            }
+            if self.take_break_hit() {
+                break;
+            }
+            let returned = match frame {
+                Some(_) => self.call_stack.len() < depth,
+                None => self.curr_state.pc == return_address,
+            };
+            if returned {
+                println!("Returned to {:#010X}\n", self.curr_state.pc);
+                match frame {
+                    Some(frame) => self.check_abi(&frame.entry),
+                    None if self.abi_check => {
+                        println!("No call recorded, ABI not checked\n");
+                    }
+                    None => {}
+                }
+                break;
+            }
+        }
+    }
+
+    fn mdump_intern<T: Write>(
+        &self,
+        start: usize,
+        stop: usize,
+        out: &mut T,
+        format: DumpFormat,
+    ) -> io::Result<()> {
         let mut address: usize;
 
         writeln!(out, "\nMemory content [{:#010X}..{:#010X}] :", start, stop)?;
@@ -704,11 +2898,16 @@ impl MipsComputer {
         address = start;
         while address <= stop {
             if let Some(value) = self.mem_read_32(address) {
-                writeln!(
+                write!(
                     out,
                     "    {:#010X}  ({}) : {:#010X}",
                     address, address, value
                 )?;
+                // Pretty dumps also show the signed interpretation
+                match format {
+                    DumpFormat::Plain => writeln!(out)?,
+                    DumpFormat::Pretty => writeln!(out, " ({})", value as i32)?,
+                }
             } else {
                 writeln!(
                     out,
@@ -718,56 +2917,2211 @@ impl MipsComputer {
             }
             address += 4;
         }
-        writeln!(out, "")?;
+        writeln!(out)?;
 
         Ok(())
     }
 
+    // The range mdump dumps, and the warnings to give about it. Reversed
+    // bounds are swapped and a stop past the 32-bit address space is
+    // clamped, with a warning either way. Ranges reaching unmapped memory
+    // are dumped, but warned about too.
+    fn mdump_bounds(&self, start: usize, stop: usize) -> (usize, usize, Vec<String>) {
+        let mut warnings = Vec::new();
+        let (start, stop) = if start > stop {
+            warnings.push(format!(
+                "Warning: mdump bounds are reversed, dumping {:#010X}..{:#010X}",
+                stop, start
+            ));
+            (stop, start)
+        } else {
+            (start, stop)
+        };
+        const ADDRESS_MAX: usize = u32::MAX as usize;
+        if stop > ADDRESS_MAX {
+            warnings.push(format!(
+                "Warning: mdump stop clamped to {:#010X}",
+                ADDRESS_MAX
+            ));
+        }
+        let stop = stop.min(ADDRESS_MAX);
+        let words = (start..=stop).step_by(4);
+        let unmapped = words
+            .clone()
+            .filter(|address| self.mem_read_32(*address).is_none())
+            .count();
+        if unmapped > 0 {
+            warnings.push(format!(
+                "Warning: {} of {} words in the range are unmapped",
+                unmapped,
+                words.count()
+            ));
+        }
+        (start, stop, warnings)
+    }
+
     pub fn mdump(&self, start: usize, stop: usize, file: &mut File) -> io::Result<()> {
-        self.mdump_intern(start, stop, &mut io::stdout())?;
-        self.mdump_intern(start, stop, file)?;
+        let (start, stop, warnings) = self.mdump_bounds(start, stop);
+        for warning in &warnings {
+            println!("{}", warning);
+            writeln!(file, "{}", warning)?;
+        }
+        self.mdump_intern(start, stop, &mut io::stdout(), self.dump_format)?;
+        self.mdump_intern(start, stop, file, DumpFormat::Plain)?;
         Ok(())
     }
 
-    fn rdump_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
-        writeln!(out, "\n Current reigster/bus values :")?;
-        writeln!(out, "-------------------------------")?;
-        writeln!(out, "Instruction count : {}", self.instr_cnt)?;
-        writeln!(out, "PC                : {:#010X}", self.curr_state.pc)?;
-        writeln!(out, "Registers:")?;
-        for (i, reg) in self.curr_state.regs.iter().enumerate() {
-            writeln!(out, "R{}: {:#010X}", i, reg)?;
+    fn stack_intern<T: Write>(&self, count: usize, out: &mut T) -> io::Result<()> {
+        const SP: usize = 29;
+        let sp = self.curr_state.regs[SP] as usize;
+        let stack_end = self.config.stack.start + self.config.stack.size;
+        writeln!(out, "\nStack content [$sp = {:#010X}] :", sp)?;
+        writeln!(out, "-----------------------------------------")?;
+        if !(self.config.stack.start..stack_end).contains(&sp) {
+            writeln!(out, "Warning: $sp is outside the stack region")?;
         }
-        writeln!(out, "HI: {:#010X}", self.curr_state.hi)?;
-        writeln!(out, "LO: {:#010X}", self.curr_state.lo)?;
-        writeln!(out, "")?;
+        for idx in 0..count {
+            let address = sp + idx * 4;
+            if let Some(value) = self.mem_read_32(address) {
+                writeln!(
+                    out,
+                    "    $sp+{:<4} {:#010X} : {:#010X}",
+                    idx * 4,
+                    address,
+                    value
+                )?;
+            } else {
+                writeln!(
+                    out,
+                    "    $sp+{:<4} {:#010X} : <undefined address>",
+                    idx * 4,
+                    address
+                )?;
+            }
+        }
+        writeln!(out)?;
         Ok(())
     }
 
-    pub fn rdump(&self, file: &mut File) -> io::Result<()> {
-        self.rdump_intern(&mut io::stdout())?;
-        self.rdump_intern(file)?;
+    pub fn stack(&self, count: usize, file: &mut File) -> io::Result<()> {
+        self.stack_intern(count, &mut io::stdout())?;
+        self.stack_intern(count, file)?;
         Ok(())
     }
 
-    pub fn curr_state(&self) -> &CpuState {
-        &self.curr_state
+    fn memstats_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        writeln!(out, "\nMemory access statistics :")?;
+        writeln!(out, "-----------------------------------------")?;
+        writeln!(out, "Loads             : {}", self.mem_stats.loads)?;
+        writeln!(out, "Stores            : {}", self.mem_stats.stores)?;
+        writeln!(out, "Unique words      : {}", self.mem_stats.unique_words())?;
+        writeln!(out)?;
+        Ok(())
     }
 
-    pub fn curr_state_mut(&mut self) -> &mut CpuState {
-        &mut self.curr_state
+    pub fn memstats(&self, file: &mut File) -> io::Result<()> {
+        self.memstats_intern(&mut io::stdout())?;
+        self.memstats_intern(file)?;
+        Ok(())
     }
 
-    pub fn next_state(&self) -> &CpuState {
-        &self.next_state
+    fn cachestats_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        let caches = [("Instruction", &self.icache), ("Data", &self.dcache)];
+        for (name, cache) in caches {
+            writeln!(out, "\n{} cache statistics :", name)?;
+            writeln!(out, "-----------------------------------------")?;
+            if let Some(cache) = cache {
+                writeln!(
+                    out,
+                    "Geometry          : {} sets of {} bytes",
+                    cache.num_sets(),
+                    cache.line_size()
+                )?;
+                writeln!(out, "Hits              : {}", cache.hits())?;
+                writeln!(out, "Misses            : {}", cache.misses())?;
+            } else {
+                writeln!(out, "{} cache is disabled", name)?;
+            }
+        }
+        writeln!(
+            out,
+            "\nMiss penalty      : {} cycles\n",
+            self.cycle_costs.miss_penalty
+        )?;
+        Ok(())
     }
 
-    pub fn next_state_mut(&mut self) -> &mut CpuState {
-        &mut self.next_state
+    pub fn cachestats(&self, file: &mut File) -> io::Result<()> {
+        self.cachestats_intern(&mut io::stdout())?;
+        self.cachestats_intern(file)?;
+        Ok(())
     }
-}
 
-fn sign_extend32(data: u32, size: u32) -> i32 {
-    assert!(size <= 32);
-    ((data << (32 - size)) as i32) >> (32 - size)
+    fn pipestats_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        writeln!(out, "\nPipeline statistics :")?;
+        writeln!(out, "-----------------------------------------")?;
+        if let Some(pipeline) = &self.pipeline {
+            writeln!(out, "Instructions      : {}", pipeline.instrs())?;
+            writeln!(out, "Load-use stalls   : {}", pipeline.stalls())?;
+            writeln!(out, "Branch flushes    : {}", pipeline.flushes())?;
+            writeln!(out, "Cycles            : {}", pipeline.cycles())?;
+        } else {
+            writeln!(out, "Pipeline model is disabled")?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    pub fn pipestats(&self, file: &mut File) -> io::Result<()> {
+        self.pipestats_intern(&mut io::stdout())?;
+        self.pipestats_intern(file)?;
+        Ok(())
+    }
+
+    // Either every logged branch in order, or per branch how often it was
+    // taken
+    fn branches_intern<T: Write>(&self, summary: bool, out: &mut T) -> io::Result<()> {
+        writeln!(out, "\nBranch log :")?;
+        writeln!(out, "-----------------------------------------")?;
+        let log = match &self.branch_log {
+            Some(log) => log,
+            None => {
+                writeln!(out, "Branch logging is disabled\n")?;
+                return Ok(());
+            }
+        };
+        if summary {
+            // Sorted by source, so the summary reads in program order
+            let mut counts: Vec<(u32, u64, u64)> = Vec::new();
+            for record in log {
+                let idx = match counts.binary_search_by_key(&record.source, |count| count.0) {
+                    Ok(idx) => idx,
+                    Err(idx) => {
+                        counts.insert(idx, (record.source, 0, 0));
+                        idx
+                    }
+                };
+                counts[idx].1 += record.taken as u64;
+                counts[idx].2 += 1;
+            }
+            for (source, taken, total) in counts {
+                writeln!(
+                    out,
+                    "{:#010X}{} : taken {} of {}",
+                    source,
+                    self.symbols.annotate(source),
+                    taken,
+                    total
+                )?;
+            }
+        } else {
+            for record in log {
+                writeln!(
+                    out,
+                    "{:#010X} -> {:#010X}{} {}",
+                    record.source,
+                    record.target,
+                    self.symbols.annotate(record.target),
+                    if record.taken { "taken" } else { "not taken" }
+                )?;
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    // The innermost frame first: the PC, then each call site, with the
+    // function each is in when known
+    fn backtrace_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        writeln!(out, "\nBacktrace :")?;
+        writeln!(out, "-----------------------------------------")?;
+        let sites = self.call_stack.iter().rev().map(|frame| frame.call_site);
+        let pcs = std::iter::once(self.curr_state.pc).chain(sites);
+        let funcs = self.call_stack.iter().rev().map(|frame| Some(frame.target));
+        for (idx, (pc, func)) in pcs.zip(funcs.chain(std::iter::once(None))).enumerate() {
+            write!(out, "#{:<2} {:#010X}{}", idx, pc, self.symbols.annotate(pc))?;
+            match func {
+                Some(func) => writeln!(out, " in {:#010X}{}", func, self.symbols.annotate(func))?,
+                None => writeln!(out)?,
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    pub fn backtrace(&self, file: &mut File) -> io::Result<()> {
+        self.backtrace_intern(&mut io::stdout())?;
+        self.backtrace_intern(file)?;
+        Ok(())
+    }
+
+    pub fn branches(&self, summary: bool, file: &mut File) -> io::Result<()> {
+        self.branches_intern(summary, &mut io::stdout())?;
+        self.branches_intern(summary, file)?;
+        Ok(())
+    }
+
+    // Sends every retired instruction to `sink`, or stops tracing if None
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.trace = sink;
+        self.traced_stores.clear();
+    }
+
+    // Limits the register writes in trace events to the GPRs set in `mask`
+    pub fn set_trace_regs(&mut self, mask: u32) {
+        self.trace_regs = mask;
+    }
+
+    pub fn trace_regs(&self) -> u32 {
+        self.trace_regs
+    }
+
+    fn record_trace(&mut self, event: &TraceEvent) {
+        if let Some(sink) = &mut self.trace {
+            if let Err(e) = sink.record(event) {
+                println!("Trace failed, tracing stopped: {}\n", e);
+                self.trace = None;
+            }
+        }
+    }
+
+    // Replaces the symbol table with the `addr=name` lines in `path`
+    pub fn load_symbols<T: AsRef<Path>>(&mut self, path: T) -> io::Result<()> {
+        let mut src = String::new();
+        File::open(&path)?.read_to_string(&mut src)?;
+        self.symbols = SymbolTable::parse(&src)?;
+        println!("Read {} symbols.\n", self.symbols.len());
+        Ok(())
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    // Disassembles the word at `address`, naming the target of jumps and
+    // branches when symbols are loaded
+    fn disassemble(&self, address: u32) -> String {
+        let word = match self.mem_read_32(address as usize) {
+            Some(word) => word,
+            None => return String::from("<unmapped>"),
+        };
+        let instr = match try_parse_instr(word) {
+            Some(instr) => instr,
+            None => return String::from("<reserved>"),
+        };
+        match instr.branch_target(address) {
+            Some(target) => format!("{}{}", instr, self.symbols.annotate(target)),
+            None => instr.to_string(),
+        }
+    }
+
+    fn dis_intern<T: Write>(&self, start: u32, count: usize, out: &mut T) -> io::Result<()> {
+        writeln!(out)?;
+        for idx in 0..count as u32 {
+            let address = start.wrapping_add(idx * 4);
+            if let Some(name) = self.symbols.get(address) {
+                writeln!(out, "{}:", name)?;
+            }
+            let word = self.mem_read_32(address as usize).unwrap_or(0);
+            writeln!(
+                out,
+                "    {:#010X} : {:08X}  {}",
+                address,
+                word,
+                self.disassemble(address)
+            )?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    pub fn dis(&self, start: u32, count: usize, file: &mut File) -> io::Result<()> {
+        self.dis_intern(start, count, &mut io::stdout())?;
+        self.dis_intern(start, count, file)?;
+        Ok(())
+    }
+
+    fn verify_intern<T: Write>(
+        &self,
+        expectations: &[Expectation],
+        out: &mut T,
+    ) -> io::Result<usize> {
+        writeln!(out)?;
+        let mut failed = 0;
+        for expectation in expectations {
+            let actual = match expectation.location {
+                Location::Reg(reg) => self.curr_state.get_reg(reg as usize),
+                Location::Hi => Some(self.curr_state.hi),
+                Location::Lo => Some(self.curr_state.lo),
+                Location::Pc => Some(self.curr_state.pc),
+                Location::Mem(address) => self.mem_read_32(address as usize),
+            };
+            match actual {
+                Some(value) if value == expectation.value => {
+                    writeln!(out, "PASS {} = {:#010X}", expectation.location, value)?;
+                }
+                Some(value) => {
+                    failed += 1;
+                    writeln!(
+                        out,
+                        "FAIL {} = {:#010X}, expected {:#010X}",
+                        expectation.location, value, expectation.value
+                    )?;
+                }
+                None => {
+                    failed += 1;
+                    writeln!(out, "FAIL {} is unmapped", expectation.location)?;
+                }
+            }
+        }
+        writeln!(
+            out,
+            "\n{} of {} expectations passed\n",
+            expectations.len() - failed,
+            expectations.len()
+        )?;
+        Ok(failed)
+    }
+
+    // Checks the `location = value` lines in `path` against the current
+    // state, returning how many did not hold
+    pub fn verify<P: AsRef<Path>>(&self, path: P, file: &mut File) -> io::Result<usize> {
+        let mut src = String::new();
+        File::open(&path)?.read_to_string(&mut src)?;
+        let expectations = verify::parse(&src)?;
+        let failed = self.verify_intern(&expectations, &mut io::stdout())?;
+        self.verify_intern(&expectations, file)?;
+        Ok(failed)
+    }
+
+    // Saves the registers under `name`, replacing any snapshot of that name
+    pub fn snapshot(&mut self, name: &str) {
+        self.snapshots.insert(name.to_string(), self.curr_state);
+    }
+
+    // The GPRs, HI and LO that differ from the snapshot `name`, with the
+    // saved and current values, or None if there is no such snapshot
+    pub fn reg_diff(&self, name: &str) -> Option<Vec<(Location, u32, u32)>> {
+        let saved = self.snapshots.get(name)?;
+        let curr = &self.curr_state;
+        let regs =
+            (0..MIPS_REGS).map(|reg| (Location::Reg(reg as u32), saved.regs[reg], curr.regs[reg]));
+        let hilo = [
+            (Location::Hi, saved.hi, curr.hi),
+            (Location::Lo, saved.lo, curr.lo),
+        ];
+        Some(
+            regs.chain(hilo)
+                .filter(|(_, old, new)| old != new)
+                .collect(),
+        )
+    }
+
+    fn regdiff_intern<T: Write>(
+        &self,
+        diffs: &[(Location, u32, u32)],
+        out: &mut T,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        if diffs.is_empty() {
+            writeln!(out, "No registers changed")?;
+        }
+        for (location, old, new) in diffs {
+            writeln!(
+                out,
+                "{:<6}: {:#010X} -> {:#010X}",
+                location.to_string(),
+                old,
+                new
+            )?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    pub fn regdiff(&self, name: &str, file: &mut File) -> io::Result<()> {
+        let diffs = self.reg_diff(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No snapshot named {}", name),
+            )
+        })?;
+        self.regdiff_intern(&diffs, &mut io::stdout())?;
+        self.regdiff_intern(&diffs, file)?;
+        Ok(())
+    }
+
+    // Checks the registers in the SPIM register dump at `path` against the
+    // current state, returning how many differ
+    pub fn compare_spim<P: AsRef<Path>>(&self, path: P, file: &mut File) -> io::Result<usize> {
+        let mut src = String::new();
+        File::open(&path)?.read_to_string(&mut src)?;
+        let expectations = verify::parse_spim(&src)?;
+        let failed = self.verify_intern(&expectations, &mut io::stdout())?;
+        self.verify_intern(&expectations, file)?;
+        Ok(failed)
+    }
+
+    // The addresses of the loaded text words that do not decode, which
+    // usually means the program was loaded at the wrong offset or with the
+    // wrong endianness
+    pub fn undecodable_words(&self) -> Vec<u32> {
+        (self.config.text.start..self.text_end)
+            .step_by(4)
+            .filter(|address| {
+                self.mem_read_32(*address)
+                    .map_or(true, |word| try_parse_instr(word).is_none())
+            })
+            .map(|address| address as u32)
+            .collect()
+    }
+
+    fn validate_intern<T: Write>(&self, bad: &[u32], out: &mut T) -> io::Result<()> {
+        let total = self.text_end.saturating_sub(self.config.text.start) / 4;
+        writeln!(out)?;
+        for address in bad {
+            let word = self.mem_read_32(*address as usize).unwrap_or(0);
+            writeln!(out, "{:#010X} : {:08X}  undecodable", address, word)?;
+        }
+        writeln!(
+            out,
+            "{} of {} text words fail to decode\n",
+            bad.len(),
+            total
+        )?;
+        Ok(())
+    }
+
+    // Decodes every loaded text word without running anything, returning
+    // how many failed
+    pub fn validate(&self, file: &mut File) -> io::Result<usize> {
+        let bad = self.undecodable_words();
+        self.validate_intern(&bad, &mut io::stdout())?;
+        self.validate_intern(&bad, file)?;
+        Ok(bad.len())
+    }
+
+    // The words stored to since the program was loaded whose value changed,
+    // as (address, old, new) sorted by address
+    pub fn dirty_words(&self) -> Vec<(u32, u32, u32)> {
+        let mut words: Vec<_> = self
+            .dirty
+            .iter()
+            .filter_map(|(address, old)| {
+                let new = self.mem_read_32(*address)?;
+                (new != *old).then_some((*address as u32, *old, new))
+            })
+            .collect();
+        words.sort_unstable();
+        words
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    fn dirty_intern<T: Write>(&self, words: &[(u32, u32, u32)], out: &mut T) -> io::Result<()> {
+        writeln!(out)?;
+        for (address, old, new) in words {
+            writeln!(out, "{:#010X} : {:08X} -> {:08X}", address, old, new)?;
+        }
+        writeln!(out, "{} word(s) modified\n", words.len())?;
+        Ok(())
+    }
+
+    pub fn dirty(&self, file: &mut File) -> io::Result<()> {
+        let words = self.dirty_words();
+        self.dirty_intern(&words, &mut io::stdout())?;
+        self.dirty_intern(&words, file)?;
+        Ok(())
+    }
+
+    // Lists every loaded word of the text region
+    pub fn disasm_text<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        let start = self.config.text.start;
+        self.dis_intern(start as u32, (self.text_end - start) / 4, out)
+    }
+
+    fn info_intern<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        let pc = self.curr_state.pc;
+        let status = match self.halt_reason {
+            Some(HaltReason::Interrupted) => String::from("interrupted"),
+            Some(reason) => format!("halted ({:?})", reason),
+            None => String::from("running"),
+        };
+        writeln!(
+            out,
+            "PC {:#010X}{} | {} instrs | {} | run_bit {} | {}\n",
+            pc,
+            self.symbols.annotate(pc),
+            self.instr_cnt,
+            status,
+            self.run_bit as u32,
+            self.disassemble(pc)
+        )
+    }
+
+    pub fn info(&self, file: &mut File) -> io::Result<()> {
+        self.info_intern(&mut io::stdout())?;
+        self.info_intern(file)?;
+        Ok(())
+    }
+
+    pub fn dump_format(&self) -> DumpFormat {
+        self.dump_format
+    }
+
+    pub fn set_dump_format(&mut self, format: DumpFormat) {
+        self.dump_format = format;
+    }
+
+    fn rdump_intern<T: Write>(&self, out: &mut T, format: DumpFormat) -> io::Result<()> {
+        writeln!(out, "\n Current reigster/bus values :")?;
+        writeln!(out, "-------------------------------")?;
+        writeln!(out, "Instruction count : {}", self.instr_cnt)?;
+        writeln!(out, "Cycle count       : {}", self.cycle_count)?;
+        writeln!(out, "PC                : {:#010X}", self.curr_state.pc)?;
+        writeln!(out, "Registers:")?;
+        match format {
+            DumpFormat::Plain => {
+                for (i, reg) in self.curr_state.regs.iter().enumerate() {
+                    writeln!(out, "R{}: {:#010X}", i, reg)?;
+                }
+            }
+            DumpFormat::Pretty => {
+                let labels: Vec<String> = REG_NAMES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| format!("R{:<2} ${:<4}", i, name))
+                    .collect();
+                write_grid(out, &labels, &self.curr_state.regs)?;
+            }
+        }
+        writeln!(out, "HI: {:#010X}", self.curr_state.hi)?;
+        writeln!(out, "LO: {:#010X}", self.curr_state.lo)?;
+        if self.show_flags {
+            writeln!(
+                out,
+                "Flags: overflow={} carry={}",
+                self.flags.overflow as u8, self.flags.carry as u8
+            )?;
+        }
+        writeln!(out, "FPU registers:")?;
+        match format {
+            DumpFormat::Plain => {
+                for (i, reg) in self.curr_state.fpr.iter().enumerate() {
+                    writeln!(out, "F{}: {:#010X}", i, reg)?;
+                }
+            }
+            DumpFormat::Pretty => {
+                let labels: Vec<String> = (0..FPU_REGS).map(|i| format!("$f{:<2}", i)).collect();
+                write_grid(out, &labels, &self.curr_state.fpr)?;
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    pub fn rdump(&self, file: &mut File) -> io::Result<()> {
+        self.rdump_intern(&mut io::stdout(), self.dump_format)?;
+        self.rdump_intern(file, DumpFormat::Plain)?;
+        Ok(())
+    }
+
+    // Writes the registers and every nonzero word of memory, one
+    // `address : value` line per word under a header for each region
+    pub fn dump_all<T: Write>(&self, out: &mut T) -> io::Result<()> {
+        self.rdump_intern(out, DumpFormat::Plain)?;
+        for mem_reg in &self.memory {
+            writeln!(
+                out,
+                "\nRegion {} [{:#010X}..{:#010X}] :",
+                mem_reg.name,
+                mem_reg.start,
+                mem_reg.start + mem_reg.size
+            )?;
+            writeln!(out, "-----------------------------------------")?;
+            for (idx, word) in mem_reg.mem.chunks_exact(4).enumerate() {
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                if value != 0 {
+                    writeln!(out, "{:#010X} : {:#010X}", mem_reg.start + idx * 4, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A 64-bit FNV-1a hash of the architectural state, so that simulators
+    // can be compared step by step. It covers, in order, the PC, the 32
+    // GPRs, HI, LO, then every nonzero aligned word of memory by ascending
+    // address, as the address followed by the value, all as little-endian
+    // u32s. FPRs, counters and settings are left out, so machines differing
+    // only in how they were configured or how long they ran hash the same.
+    pub fn state_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xCBF29CE484222325;
+        const FNV_PRIME: u64 = 0x100000001B3;
+        let mut hash = FNV_OFFSET;
+        let mut feed = |val: u32| {
+            for byte in val.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+        let state = &self.curr_state;
+        feed(state.pc);
+        state.regs.iter().for_each(|reg| feed(*reg));
+        feed(state.hi);
+        feed(state.lo);
+        let mut regions: Vec<&MemRegion> = self.memory.iter().collect();
+        regions.sort_by_key(|mem_reg| mem_reg.start);
+        for mem_reg in regions {
+            for (idx, word) in mem_reg.mem.chunks_exact(4).enumerate() {
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                if value != 0 {
+                    feed((mem_reg.start + idx * 4) as u32);
+                    feed(value);
+                }
+            }
+        }
+        hash
+    }
+
+    pub fn mem_stats(&self) -> &MemStats {
+        &self.mem_stats
+    }
+
+    /// A copy of the register file of the current state
+    ///
+    /// ```
+    /// use mips_sim::asm::assemble;
+    /// use mips_sim::sim::{MipsComputer, MEM_TEXT_START};
+    ///
+    /// let mut comp = MipsComputer::new(&[]).unwrap();
+    /// let words = assemble("addi $t0, $zero, 5").unwrap();
+    /// comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+    /// comp.step();
+    /// assert_eq!(comp.registers()[8], 5);
+    /// ```
+    pub fn registers(&self) -> [u32; MIPS_REGS] {
+        self.curr_state.registers()
+    }
+
+    pub fn curr_state(&self) -> &CpuState {
+        &self.curr_state
+    }
+
+    pub fn curr_state_mut(&mut self) -> &mut CpuState {
+        &mut self.curr_state
+    }
+
+    pub fn next_state(&self) -> &CpuState {
+        &self.next_state
+    }
+
+    pub fn next_state_mut(&mut self) -> &mut CpuState {
+        &mut self.next_state
+    }
+}
+
+fn sign_extend32(data: u32, size: u32) -> i32 {
+    assert!(size <= 32);
+    ((data << (32 - size)) as i32) >> (32 - size)
+}
+
+// Shared by SLT and SLTI: 0xFFFFFFFF (-1) is less than 1
+fn set_less_than(first: u32, second: u32) -> u32 {
+    ((first as i32) < (second as i32)) as u32
+}
+
+// Shared by SLTU and SLTIU: 0xFFFFFFFF is not less than 1
+fn set_less_than_unsigned(first: u32, second: u32) -> u32 {
+    (first < second) as u32
+}
+
+// Lays out labelled register values four to a row
+fn write_grid<T: Write>(out: &mut T, labels: &[String], values: &[u32]) -> io::Result<()> {
+    const COLUMNS: usize = 4;
+    for (row, values) in values.chunks(COLUMNS).enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            if col > 0 {
+                write!(out, "   ")?;
+            }
+            write!(out, "{} {:#010X}", labels[row * COLUMNS + col], value)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build, temp_path, SharedBuf};
+    use std::fs;
+
+    const SLTIU: u32 = 0x0B;
+    const ORI: u32 = 0x0D;
+    const LUI: u32 = 0x0F;
+    const ADDIU: u32 = 0x09;
+
+    const SLL: u32 = 0x00;
+    const SRL: u32 = 0x02;
+    const SRA: u32 = 0x03;
+
+    fn itype(op: u32, rs: u32, rt: u32, imm: u16) -> u32 {
+        (op << 26) | (rs << 21) | (rt << 16) | imm as u32
+    }
+
+    fn shift(funct: u32, rt: u32, rd: u32, shamt: u32) -> u32 {
+        (rt << 16) | (rd << 11) | (shamt << 6) | funct
+    }
+
+    // Runs `comp` until it halts, failing the test if it never does
+    fn run_to_end(comp: &mut MipsComputer) {
+        assert!(comp.run_to_halt(10_000).is_some(), "program did not halt");
+    }
+
+    // Assembles and runs `src` to its end
+    fn run(src: &str) -> MipsComputer {
+        let mut comp = build(src);
+        run_to_end(&mut comp);
+        comp
+    }
+
+    fn reg(comp: &MipsComputer, name: &str) -> u32 {
+        comp.curr_state.regs[parse_reg(name).expect("bad register name") as usize]
+    }
+
+    // Runs `words` from the start of the text region, one cycle per word
+    fn run_words(words: &[u32]) -> MipsComputer {
+        let mut comp = MipsComputer::new(&[]).expect("Cannot create machine");
+        comp.load_words(MEM_TEXT_START as u32, words)
+            .expect("Cannot load test program");
+        comp.run(words.len() as u32);
+        comp
+    }
+
+    #[test]
+    fn sltiu_sign_extends_then_compares_unsigned() {
+        // 0xFFFF is 0xFFFFFFFF once extended, which 0x10000 is below
+        let comp = run_words(&[
+            itype(LUI, 0, 8, 1),
+            itype(SLTIU, 8, 9, 0xFFFF),
+            itype(ADDIU, 0, 10, 0xFFFF),
+            itype(SLTIU, 10, 11, 0xFFFF),
+        ]);
+        assert_eq!(comp.curr_state.regs[9], 1);
+        assert_eq!(comp.curr_state.regs[11], 0);
+    }
+
+    #[test]
+    fn sltiu_at_the_largest_positive_immediate() {
+        let comp = run_words(&[
+            itype(ADDIU, 0, 8, 0x7FFE),
+            itype(SLTIU, 8, 9, 0x7FFF),
+            itype(ADDIU, 0, 8, 0x7FFF),
+            itype(SLTIU, 8, 10, 0x7FFF),
+            itype(ORI, 0, 8, 0x8000),
+            itype(SLTIU, 8, 11, 0x7FFF),
+        ]);
+        assert_eq!(comp.curr_state.regs[9], 1);
+        assert_eq!(comp.curr_state.regs[10], 0);
+        assert_eq!(comp.curr_state.regs[11], 0);
+    }
+
+    #[test]
+    fn sltiu_at_the_smallest_negative_immediate() {
+        // 0x8000 extends to 0xFFFF8000, far above 0x8000
+        let comp = run_words(&[
+            itype(ORI, 0, 8, 0x8000),
+            itype(SLTIU, 8, 9, 0x8000),
+            itype(LUI, 0, 8, 0xFFFF),
+            itype(ORI, 8, 8, 0x7FFF),
+            itype(SLTIU, 8, 10, 0x8000),
+            itype(LUI, 0, 8, 0xFFFF),
+            itype(ORI, 8, 8, 0x8000),
+            itype(SLTIU, 8, 11, 0x8000),
+        ]);
+        assert_eq!(comp.curr_state.regs[9], 1);
+        assert_eq!(comp.curr_state.regs[10], 1);
+        assert_eq!(comp.curr_state.regs[11], 0);
+    }
+
+    #[test]
+    fn shifts_by_the_extreme_amounts() {
+        let comp = run_words(&[
+            itype(LUI, 0, 8, 0x8000),
+            itype(ORI, 8, 8, 0x1234),
+            shift(SRA, 8, 9, 31),
+            shift(SRL, 8, 10, 0),
+            itype(ADDIU, 0, 11, 3),
+            shift(SLL, 11, 12, 31),
+            itype(LUI, 0, 13, 0x4000),
+            shift(SRA, 13, 14, 31),
+        ]);
+        assert_eq!(comp.curr_state.regs[9], 0xFFFFFFFF);
+        assert_eq!(comp.curr_state.regs[10], 0x80001234);
+        assert_eq!(comp.curr_state.regs[12], 0x80000000);
+        assert_eq!(comp.curr_state.regs[14], 0);
+    }
+
+    #[test]
+    fn loaded_words_run_from_their_base() {
+        let base = MEM_TEXT_START as u32 + 8;
+        let mut comp = MipsComputer::new(&[]).unwrap();
+        comp.load_words(base, &[0x24080005, 0x25090007]).unwrap();
+        assert_eq!(comp.curr_state.pc, base);
+        comp.step();
+        comp.step();
+        assert_eq!(comp.curr_state.regs[8], 5);
+        assert_eq!(comp.curr_state.regs[9], 12);
+        assert_eq!(comp.curr_state.pc, base + 8);
+    }
+
+    #[test]
+    fn stack_shows_a_pushed_value_at_offset_zero() {
+        let mut comp = build(
+            "lui $t0, 0xCAFE
+            ori $t0, $t0, 0xF00D
+            sw $t0, 0($sp)",
+        );
+        let top = (MEM_STACK_START + MEM_STACK_SIZE) as u32;
+        comp.curr_state.set_reg(29, top - 4);
+        comp.next_state = comp.curr_state;
+        comp.run(3);
+        let mut out = Vec::new();
+        comp.stack_intern(1, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let line = format!("$sp+0    {:#010X} : 0xCAFEF00D", top - 4);
+        assert!(out.contains(&line), "{}", out);
+    }
+
+    #[test]
+    fn storing_a_byte_to_the_console_prints_it() {
+        let mut comp = build(
+            "lui $t0, 0xFFFF
+            addiu $t1, $zero, 0x58
+            sb $t1, 12($t0)",
+        );
+        let console = SharedBuf::default();
+        comp.set_console_output(Box::new(console.clone()));
+        comp.run(3);
+        assert_eq!(console.contents(), "X");
+    }
+
+    #[test]
+    fn lb_from_the_keyboard_returns_a_fed_byte() {
+        let mut comp = build(
+            "lui $t0, 0xFFFF
+            lw $t1, 0($t0)
+            lb $t2, 4($t0)
+            lw $t3, 0($t0)",
+        );
+        comp.push_keyboard_input(b"q");
+        comp.run(4);
+        assert_eq!(reg(&comp, "t1") & 1, 1);
+        assert_eq!(reg(&comp, "t2"), b'q' as u32);
+        assert_eq!(reg(&comp, "t3") & 1, 0);
+    }
+
+    #[test]
+    fn memstats_counts_loads_stores_and_words() {
+        let comp = run("lui $t0, 0x1000
+            sw $t1, 0($t0)
+            sw $t1, 4($t0)
+            lw $t2, 0($t0)
+            lw $t3, 0($t0)
+            lb $t4, 5($t0)");
+        let stats = comp.mem_stats();
+        assert_eq!(stats.loads(), 3);
+        assert_eq!(stats.stores(), 2);
+        assert_eq!(stats.unique_words(), 2);
+    }
+
+    #[test]
+    fn reset_rewinds_to_the_load_and_zeroes_memstats() {
+        let mut comp = build(
+            "lui $t0, 0x1000
+            addiu $t1, $zero, 7
+            sw $t1, 0($t0)
+            lw $t2, 0($t0)",
+        );
+        run_to_end(&mut comp);
+        assert_eq!(comp.mem_read_32(MEM_DATA_START), Some(7));
+        comp.clear_dirty();
+        comp.reset();
+        assert_eq!(comp.pc(), MEM_TEXT_START as u32);
+        assert_eq!(comp.instr_count(), 0);
+        assert_eq!(reg(&comp, "t2"), 0);
+        assert_eq!(comp.mem_read_32(MEM_DATA_START), Some(0));
+        let stats = comp.mem_stats();
+        assert_eq!(
+            (stats.loads(), stats.stores(), stats.unique_words()),
+            (0, 0, 0)
+        );
+
+        // The rewound program runs the same way again
+        run_to_end(&mut comp);
+        assert_eq!(reg(&comp, "t2"), 7);
+        assert_eq!(
+            (comp.mem_stats().loads(), comp.mem_stats().stores()),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn icache_misses_on_the_first_iteration_only() {
+        // The loop body sits alone in the second 16-byte line
+        let mut comp = build(
+            "addiu $t1, $zero, 3
+            sll $zero, $zero, 0
+            sll $zero, $zero, 0
+            sll $zero, $zero, 0
+            loop: addiu $t0, $t0, 1
+            bne $t0, $t1, loop",
+        );
+        comp.enable_icache(16, 64);
+        comp.run(6);
+        let icache = comp.icache().unwrap();
+        assert_eq!((icache.misses(), icache.hits()), (2, 4));
+        // Two more passes round the loop
+        comp.run(4);
+        let icache = comp.icache().unwrap();
+        assert_eq!((icache.misses(), icache.hits()), (2, 8));
+    }
+
+    #[test]
+    fn cycle_count_adds_each_instruction_cost() {
+        let mut comp = build(
+            "lui $t0, 0x1000
+            sw $t0, 0($t0)
+            lw $t1, 0($t0)
+            mult $t1, $t1
+            j next
+            next: addiu $v0, $zero, 10
+            syscall",
+        );
+        comp.set_cycle_costs(CycleCosts {
+            alu: 1,
+            load: 3,
+            store: 4,
+            mult_div: 7,
+            fpu: 2,
+            branch: 2,
+            syscall: 5,
+            miss_penalty: 10,
+        });
+        run_to_end(&mut comp);
+        assert_eq!(comp.cycle_count(), 1 + 4 + 3 + 7 + 2 + 1 + 5);
+    }
+
+    #[test]
+    fn until_stops_at_the_address() {
+        let mut comp = build(
+            "addiu $t0, $zero, 1
+            addiu $t1, $zero, 2
+            addiu $t2, $zero, 3
+            addiu $t3, $zero, 4",
+        );
+        let target = comp.curr_state.pc + 12;
+        comp.run_until(target);
+        assert_eq!(comp.curr_state.pc, target);
+        assert_eq!(comp.instr_cnt, 3);
+        assert_eq!(reg(&comp, "t2"), 3);
+        assert_eq!(reg(&comp, "t3"), 0);
+    }
+
+    #[test]
+    fn finish_stops_at_the_return_address() {
+        let mut comp = build(
+            "jal sub
+            addiu $t5, $zero, 7
+            addiu $v0, $zero, 10
+            syscall
+            sub: addu $s0, $ra, $zero
+            jal leaf
+            addu $ra, $s0, $zero
+            jr $ra
+            leaf: addiu $t1, $zero, 1
+            jr $ra",
+        );
+        let base = comp.curr_state.pc;
+        comp.step();
+        assert_eq!(comp.curr_state.pc, base + 16);
+        comp.finish();
+        assert_eq!(comp.curr_state.pc, base + 4);
+        assert_eq!(reg(&comp, "t1"), 1);
+        assert_eq!(reg(&comp, "t5"), 0);
+        assert!(comp.run_bit);
+    }
+
+    #[test]
+    fn falling_through_the_last_instruction_halts() {
+        let comp = run("addiu $t0, $zero, 1
+            addiu $t1, $zero, 2");
+        assert_eq!(comp.halt_reason(), Some(HaltReason::RanOffEnd));
+        assert_eq!(comp.instr_cnt, 2);
+        assert_eq!(reg(&comp, "t1"), 2);
+    }
+
+    #[test]
+    fn slt_is_signed_and_sltu_unsigned() {
+        let comp = run("addiu $t0, $zero, -1
+            addiu $t1, $zero, 1
+            slt $s0, $t0, $t1
+            sltu $s1, $t0, $t1
+            slt $s2, $t1, $t0
+            sltu $s3, $t1, $t0
+            slti $s4, $t0, 1
+            sltiu $s5, $t0, 1");
+        assert_eq!(reg(&comp, "s0"), 1);
+        assert_eq!(reg(&comp, "s1"), 0);
+        assert_eq!(reg(&comp, "s2"), 0);
+        assert_eq!(reg(&comp, "s3"), 1);
+        assert_eq!(reg(&comp, "s4"), 1);
+        assert_eq!(reg(&comp, "s5"), 0);
+    }
+
+    #[test]
+    fn less_than_helpers_on_the_boundaries() {
+        let cases = [
+            (0xFFFFFFFF, 0x00000001, 1, 0),
+            (0x00000001, 0xFFFFFFFF, 0, 1),
+            (0x7FFFFFFF, 0x80000000, 0, 1),
+            (0x80000000, 0x7FFFFFFF, 1, 0),
+            (0x00000005, 0x00000005, 0, 0),
+        ];
+        for (first, second, signed, unsigned) in cases {
+            assert_eq!(set_less_than(first, second), signed);
+            assert_eq!(set_less_than_unsigned(first, second), unsigned);
+        }
+    }
+
+    #[test]
+    fn pretty_rdump_lays_registers_out_in_eight_rows() {
+        // GPR lines start with R and a register number
+        fn gpr_lines(out: &[u8]) -> usize {
+            let out = String::from_utf8(out.to_vec()).unwrap();
+            out.lines()
+                .filter(|line| line.starts_with('R') && line[1..].starts_with(char::is_numeric))
+                .count()
+        }
+        let comp = MipsComputer::new(&[]).unwrap();
+        let mut out = Vec::new();
+        comp.rdump_intern(&mut out, DumpFormat::Pretty).unwrap();
+        assert_eq!(gpr_lines(&out), 8);
+        let mut out = Vec::new();
+        comp.rdump_intern(&mut out, DumpFormat::Plain).unwrap();
+        assert_eq!(gpr_lines(&out), 32);
+    }
+
+    #[test]
+    fn lwc1_and_swc1_move_a_word_through_an_fpu_register() {
+        let comp = run("lui $t0, 0x1000
+            lui $t1, 0xDEAD
+            ori $t1, $t1, 0xBEEF
+            sw $t1, 0($t0)
+            lwc1 $f2, 0($t0)
+            swc1 $f2, 4($t0)
+            lw $t2, 4($t0)");
+        assert_eq!(comp.curr_state.get_fpr(2), Some(0xDEADBEEF));
+        assert_eq!(reg(&comp, "t2"), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn fpu_add_and_divide_by_zero_bit_patterns() {
+        let mut comp = build(
+            "add.s $f2, $f0, $f1
+            div.s $f5, $f3, $f4",
+        );
+        comp.curr_state.set_fpr(0, 0x3FC00000);
+        comp.curr_state.set_fpr(1, 0x40100000);
+        comp.curr_state.set_fpr(3, 0x40400000);
+        comp.next_state = comp.curr_state;
+        run_to_end(&mut comp);
+        // 1.5 + 2.25 = 3.75
+        assert_eq!(comp.curr_state.get_fpr(2), Some(0x40700000));
+        // 3.0 / 0.0 = +infinity
+        assert_eq!(comp.curr_state.get_fpr(5), Some(0x7F800000));
+    }
+
+    #[test]
+    fn mtc1_and_mfc1_keep_the_bits() {
+        let comp = run("lui $t0, 0x3F80
+            mtc1 $t0, $f0
+            mfc1 $t1, $f0");
+        assert_eq!(comp.curr_state.get_fpr(0), Some(0x3F800000));
+        assert_eq!(reg(&comp, "t1"), 0x3F800000);
+    }
+
+    #[test]
+    fn sub_traps_on_overflow_and_subu_wraps() {
+        let comp = run("lui $t0, 0x8000
+            addiu $t1, $zero, 1
+            subu $t2, $t0, $t1
+            sub $t3, $t0, $t1");
+        assert_eq!(reg(&comp, "t2"), 0x7FFFFFFF);
+        assert_eq!(reg(&comp, "t3"), 0);
+        let pc = comp.curr_state.pc;
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::Overflow))
+        );
+        assert_eq!(pc, MEM_TEXT_START as u32 + 12);
+    }
+
+    #[test]
+    fn addu_wraps_and_add_traps() {
+        let comp = run("addiu $t0, $zero, -1
+            addiu $t1, $zero, 1
+            addu $t2, $t0, $t1
+            lui $t3, 0x7FFF
+            ori $t3, $t3, 0xFFFF
+            add $t4, $t3, $t1");
+        assert_eq!(reg(&comp, "t2"), 0);
+        assert_eq!(reg(&comp, "t4"), 0);
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::Overflow))
+        );
+    }
+
+    #[test]
+    fn addiu_of_minus_one_to_zero_wraps() {
+        let comp = run("addiu $t0, $zero, -1
+            lui $t1, 0x7FFF
+            ori $t1, $t1, 0xFFFF
+            addiu $t2, $t1, 1
+            addi $t3, $t1, 1");
+        assert_eq!(reg(&comp, "t0"), 0xFFFFFFFF);
+        assert_eq!(reg(&comp, "t2"), 0x80000000);
+        assert_eq!(reg(&comp, "t3"), 0);
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::Overflow))
+        );
+    }
+
+    #[test]
+    fn cpu_states_compare_equal_until_a_step() {
+        let mut comp = build("addiu $t0, $zero, 1\n");
+        let before = *comp.curr_state();
+        assert_eq!(before, *comp.curr_state());
+        comp.step();
+        assert_ne!(before, *comp.curr_state());
+        assert!(format!("{:?}", before).contains("pc"));
+    }
+
+    #[test]
+    fn break_stops_with_the_break_reason() {
+        let comp = run("addiu $t0, $zero, 1
+            break
+            addiu $t1, $zero, 2");
+        assert_eq!(comp.halt_reason(), Some(HaltReason::Break));
+        assert_eq!(comp.pc(), MEM_TEXT_START as u32 + 4);
+        assert_eq!(reg(&comp, "t1"), 0);
+    }
+
+    #[test]
+    fn movt_and_movf_follow_the_condition_flag() {
+        let mut comp = build(
+            "addiu $t0, $zero, 7
+            movt $s0, $t0, 1
+            movf $s1, $t0, 1
+            movt $s2, $t0
+            movf $s3, $t0",
+        );
+        comp.curr_state.set_fcc(1, true);
+        comp.next_state = comp.curr_state;
+        run_to_end(&mut comp);
+        assert_eq!(reg(&comp, "s0"), 7);
+        assert_eq!(reg(&comp, "s1"), 0);
+        assert_eq!(reg(&comp, "s2"), 0);
+        assert_eq!(reg(&comp, "s3"), 7);
+    }
+
+    #[test]
+    fn watching_t0_reports_both_writes() {
+        let mut comp = build(
+            "addiu $t0, $zero, 1
+            addiu $t1, $zero, 2
+            addiu $t0, $t0, 4
+            addiu $t0, $t0, 0",
+        );
+        assert!(comp.watch_reg(8));
+        let mut reports = Vec::new();
+        while comp.halt_reason().is_none() {
+            let (pc, regs) = (comp.pc(), comp.registers());
+            comp.step();
+            reports.extend(comp.reg_watch_reports(pc, &regs));
+        }
+        assert_eq!(
+            reports,
+            [
+                "Watch $t0 at PC 0x00400000: 0x00000000 -> 0x00000001",
+                "Watch $t0 at PC 0x00400008: 0x00000001 -> 0x00000005",
+            ]
+        );
+    }
+
+    #[test]
+    fn reading_t5_before_writing_it_warns_once() {
+        let mut comp = build(
+            "addiu $t0, $zero, 1
+            addu $t1, $t5, $t0
+            addu $t2, $t5, $t0
+            addiu $t5, $zero, 3
+            addu $t3, $t5, $t0",
+        );
+        comp.set_uninit_check(true);
+        run_to_end(&mut comp);
+        // Each bit is one warning already given
+        assert_eq!(comp.warned_regs, 1 << 13);
+    }
+
+    #[test]
+    fn pretty_mdump_shows_the_signed_value() {
+        let comp = run("lui $t0, 0x1000
+            addiu $t1, $zero, -1
+            sw $t1, 0($t0)");
+        let mut out = Vec::new();
+        let start = MEM_DATA_START;
+        comp.mdump_intern(start, start, &mut out, DumpFormat::Pretty)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(": 0xFFFFFFFF (-1)"), "{}", out);
+
+        let mut out = Vec::new();
+        comp.mdump_intern(start, start, &mut out, DumpFormat::Plain)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("(-1)"), "{}", out);
+    }
+
+    #[test]
+    fn a_jump_to_a_labeled_address_shows_the_label() {
+        let mut comp = build(
+            "j target
+            sll $zero, $zero, 0
+            target: jr $ra",
+        );
+        let base = comp.pc();
+        comp.symbols_mut().insert(base + 8, "target");
+        assert_eq!(comp.disassemble(base), "j 0x00400008 <target>");
+        let mut out = Vec::new();
+        comp.dis_intern(base, 3, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\ntarget:\n"), "{}", out);
+    }
+
+    #[test]
+    fn text_is_mapped_and_dead_space_is_not() {
+        let comp = build("sll $zero, $zero, 0\n");
+        assert!(comp.is_mapped(MEM_TEXT_START));
+        assert!(!comp.is_mapped(0xDEAD0000));
+        let text = comp.regions().find(|info| info.name == "text").unwrap();
+        assert_eq!(text.start, MEM_TEXT_START);
+        assert_eq!(text.size, MEM_TEXT_SIZE);
+    }
+
+    #[test]
+    fn a_64k_text_region_holds_a_program_at_its_end() {
+        let mut config = MipsConfig::default();
+        config.text.size = 0x10000;
+        let mut comp = MipsComputer::with_config(config, &[]).unwrap();
+        let text = comp.regions().find(|info| info.name == "text").unwrap();
+        assert_eq!(text.size, 0x10000);
+        // addiu $t0, $zero, 5 in the last word of the region
+        let last = (MEM_TEXT_START + 0x10000 - 4) as u32;
+        comp.load_words(last, &[0x24080005]).unwrap();
+        assert!(comp.load_words(last + 4, &[0]).is_err());
+        run_to_end(&mut comp);
+        assert_eq!(reg(&comp, "t0"), 5);
+
+        // A text region grown into the data region is refused
+        config.text.size = MEM_DATA_START - MEM_TEXT_START + 4;
+        assert!(MipsComputer::with_config(config, &[]).is_err());
+    }
+
+    #[test]
+    fn an_unhandled_regimm_rt_raises_reserved_instruction() {
+        let mut comp = build("sll $zero, $zero, 0\n");
+        let word = (0x01 << 26) | (8 << 21) | (0x1F << 16) | 4;
+        comp.load_words(MEM_TEXT_START as u32, &[word]).unwrap();
+        run_to_end(&mut comp);
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::ReservedInstruction))
+        );
+    }
+
+    #[test]
+    fn tnei_traps_only_when_the_immediate_differs() {
+        let comp = run("addiu $t0, $zero, -3
+            tnei $t0, -3
+            addiu $t1, $zero, 1");
+        assert_eq!(comp.halt_reason(), Some(HaltReason::RanOffEnd));
+        assert_eq!(reg(&comp, "t1"), 1);
+
+        let comp = run("addiu $t0, $zero, -3
+            tnei $t0, 4
+            addiu $t1, $zero, 1");
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::Trap))
+        );
+        assert_eq!(reg(&comp, "t1"), 0);
+    }
+
+    #[test]
+    fn a_halfword_at_the_last_byte_of_a_region_faults() {
+        let last = MEM_DATA_START + MEM_DATA_SIZE - 1;
+        let comp = run(&format!(
+            "lui $t0, {:#X}
+            ori $t0, $t0, {:#X}
+            addiu $t1, $zero, 0x7F
+            sb $t1, 0($t0)
+            lbu $t2, 0($t0)
+            lh $t3, 0($t0)",
+            last >> 16,
+            last & 0xFFFF
+        ));
+        assert_eq!(reg(&comp, "t2"), 0x7F);
+        let pc = comp.pc();
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::AddressErrorLoad))
+        );
+        assert_eq!(pc, MEM_TEXT_START as u32 + 20);
+        assert_eq!(comp.bad_vaddr(), last as u32);
+
+        let comp = run(&format!(
+            "lui $t0, {:#X}\nori $t0, $t0, {:#X}\nsh $t0, 0($t0)",
+            last >> 16,
+            last & 0xFFFF
+        ));
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::AddressErrorStore))
+        );
+    }
+
+    #[test]
+    fn the_eleventh_cycle_hits_an_instruction_limit_of_10() {
+        let mut comp = build(
+            "loop: addiu $t0, $t0, 1
+            b loop",
+        );
+        comp.set_max_instructions(Some(10));
+        for _ in 0..10 {
+            comp.cycle();
+        }
+        assert!(comp.run_bit);
+        assert_eq!(comp.instr_count(), 10);
+        comp.cycle();
+        assert_eq!(comp.halt_reason(), Some(HaltReason::InstructionLimit));
+        assert_eq!(comp.instr_count(), 10);
+        assert_eq!(reg(&comp, "t0"), 5);
+    }
+
+    #[test]
+    fn a_kseg0_write_is_visible_through_kseg1() {
+        let comp = run("li $t0, 0x90000010
+            addiu $t1, $zero, 0x55
+            sw $t1, 0($t0)
+            li $t2, 0xB0000010
+            lw $t3, 0($t2)");
+        assert_eq!(reg(&comp, "t3"), 0x55);
+        assert_eq!(comp.read_byte(0xB0000010), Some(0x55));
+        assert!(comp.is_mapped(0xB0000010));
+    }
+
+    #[test]
+    fn a_faulting_lw_reports_the_ea_with_its_negative_offset() {
+        let comp = run("lui $t0, 0x1000
+            lw $t1, -4($t0)");
+        assert_eq!(comp.bad_vaddr(), 0x0FFFFFFC);
+        assert_eq!(
+            comp.halt_reason(),
+            Some(HaltReason::Exception(ExceptionCause::AddressErrorLoad))
+        );
+    }
+
+    #[test]
+    fn the_timer_advances_between_reads() {
+        let comp = run(&format!(
+            "li $t0, {:#X}
+            lw $t1, 0($t0)
+            nop
+            nop
+            nop
+            lw $t2, 0($t0)",
+            MMIO_TIMER
+        ));
+        // li is two instructions, retired before the first read
+        assert_eq!(reg(&comp, "t1"), 2);
+        assert_eq!(reg(&comp, "t2"), 6);
+    }
+
+    // Raises the interrupt flag as the given instruction retires, the way
+    // the Ctrl-C handler would from outside the run loop
+    struct InterruptAt {
+        flag: Arc<AtomicBool>,
+        count: u32,
+        at: u32,
+    }
+
+    impl TraceSink for InterruptAt {
+        fn record(&mut self, _event: &TraceEvent) -> io::Result<()> {
+            self.count += 1;
+            if self.count == self.at {
+                self.flag.store(true, Ordering::SeqCst);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_interrupt_flag_stops_go_promptly() {
+        let mut comp = build(
+            "loop: addiu $t0, $t0, 1
+            b loop",
+        );
+        // Should the interrupt be missed, stop rather than hang the test
+        comp.set_max_instructions(Some(1_000_000));
+        let flag = comp.interrupt_handle();
+        comp.set_trace_sink(Some(Box::new(InterruptAt {
+            flag: Arc::clone(&flag),
+            count: 0,
+            at: 100,
+        })));
+        comp.go();
+        assert_eq!(comp.halt_reason(), Some(HaltReason::Interrupted));
+        assert_eq!(comp.instr_count(), 100);
+        assert!(!flag.load(Ordering::SeqCst));
+        // The machine resumes where it stopped
+        comp.run(3);
+        assert_eq!(comp.halt_reason(), None);
+        assert_eq!(comp.instr_count(), 103);
+    }
+
+    #[test]
+    fn an_encoded_instr_executes_and_zero_stays_hardwired() {
+        // addiu $zero, $zero, 5 then addu $t2, $zero, $zero
+        let to_zero = decode(0x24000005).unwrap();
+        let from_zero = decode(0x00005021).unwrap();
+        let mut comp = build("nop\n");
+        let base = MEM_TEXT_START as u32;
+        comp.load_words(base, &[encode(&to_zero), encode(&from_zero)])
+            .unwrap();
+        run_to_end(&mut comp);
+        assert_eq!(comp.registers()[0], 0);
+        assert_eq!(reg(&comp, "t2"), 0);
+
+        // Handed straight to the handler, without going through memory
+        let mut comp = build("nop\n");
+        match &to_zero {
+            Instr::IType(instr) => assert!(comp.process_itype_instruction(instr)),
+            other => panic!("not an I-type instruction: {:?}", other),
+        }
+        assert_eq!(comp.next_state.get_reg(0), Some(0));
+    }
+
+    #[test]
+    fn intel_hex_and_s_records_place_their_bytes() {
+        let ihex = ":020000040040BA
+            :0400000005000824CB
+            :020000041000EA
+            :02001000ABCD76
+            :00000001FF";
+        let srec = "S309004000000500082485
+            S30710000010ABCD60
+            S70500400000BA";
+        for (suffix, src) in [(".hex", ihex), (".srec", srec)] {
+            let path = temp_path(suffix);
+            fs::write(&path, src).unwrap();
+            let mut comp = MipsComputer::new(&[]).unwrap();
+            comp.load_program(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(comp.pc(), MEM_TEXT_START as u32);
+            assert_eq!(comp.disassemble(comp.pc()), "addiu $t0, $zero, 5");
+            assert_eq!(comp.read_byte(0x10000010), Some(0xAB));
+            assert_eq!(comp.read_byte(0x10000011), Some(0xCD));
+        }
+
+        // A corrupted checksum is reported, not loaded
+        let path = temp_path(".hex");
+        fs::write(&path, ":0400000005000824CC\n:00000001FF\n").unwrap();
+        let mut comp = MipsComputer::new(&[]).unwrap();
+        let err = comp.load_program(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("bad checksum"), "{}", err);
+    }
+
+    #[test]
+    fn an_untouched_word_reads_the_fill_pattern() {
+        let config = MipsConfig {
+            fill: FillPattern::Word(0xDEADBEEF),
+            ..MipsConfig::default()
+        };
+        let mut comp = MipsComputer::with_config(config, &[]).unwrap();
+        let words = crate::asm::assemble("lui $t0, 0x1000\nlw $t1, 0x40($t0)").unwrap();
+        comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+        assert_eq!(reg(&comp, "t2"), 0xDEADBEEF);
+        comp.run(2);
+        assert_eq!(reg(&comp, "t1"), 0xDEADBEEF);
+
+        // A seed gives the same contents every time, and zero fill stays
+        // the default
+        let seeded = || {
+            let config = MipsConfig {
+                fill: FillPattern::Random(7),
+                ..MipsConfig::default()
+            };
+            MipsComputer::with_config(config, &[]).unwrap()
+        };
+        let address = MEM_DATA_START + 0x40;
+        let word = seeded().mem_read_32(address);
+        assert_eq!(word, seeded().mem_read_32(address));
+        assert_ne!(word, Some(0));
+        let comp = MipsComputer::new(&[]).unwrap();
+        assert_eq!(comp.mem_read_32(address), Some(0));
+    }
+
+    #[test]
+    fn mflo_right_after_mult_warns() {
+        let mut comp = build("nop\n");
+        comp.set_hilo_check(true);
+        let words = crate::asm::assemble(
+            "mult $t0, $t1
+            mflo $t2
+            mult $t0, $t1
+            nop
+            mflo $t2",
+        )
+        .unwrap();
+        let warned: Vec<bool> = words
+            .iter()
+            .map(|word| comp.hilo_hazard(&decode(*word).unwrap()).is_some())
+            .collect();
+        assert_eq!(warned, [false, true, false, false, false]);
+    }
+
+    #[test]
+    fn reverting_a_cycle_restores_exact_memory() {
+        let mut comp = build(
+            "lui $t0, 0x1000
+            addiu $t1, $zero, -2
+            sw $t1, 4($t0)
+            sb $t0, 5($t0)
+            sh $t1, 6($t0)
+            mult $t1, $t0",
+        );
+        let memory = |comp: &MipsComputer| -> Vec<Vec<u8>> {
+            comp.memory
+                .iter()
+                .map(|mem_reg| mem_reg.mem.clone())
+                .collect()
+        };
+        comp.set_history_depth(1);
+        while comp.run_bit {
+            let before = (memory(&comp), comp.curr_state);
+            comp.cycle();
+            let record = comp.last_undo_record().unwrap().clone();
+            let after = (memory(&comp), comp.curr_state);
+            comp.revert(&record);
+            assert_eq!((memory(&comp), comp.curr_state), before);
+            // Applying the cycle again lands on the same state
+            comp.cycle();
+            assert_eq!((memory(&comp), comp.curr_state), after);
+        }
+        let record = comp.last_undo_record().unwrap();
+        assert_eq!(record.deltas(), [Delta::Hi(0), Delta::Lo(0)]);
+        assert_eq!(comp.mem_read_32(MEM_DATA_START + 4), Some(0xFFFE00FE));
+    }
+
+    #[test]
+    fn printf_formats_an_integer_arg() {
+        let mut comp = build(
+            "lui $a0, 0x1000
+            li $a1, 7
+            li $v0, 40
+            syscall",
+        );
+        assert!(comp.mem_write_bytes(MEM_DATA_START, b"x=%d\n\0"));
+        let console = SharedBuf::default();
+        comp.set_console_output(Box::new(console.clone()));
+        run_to_end(&mut comp);
+        assert_eq!(console.contents(), "x=7\n");
+    }
+
+    #[test]
+    fn jumping_into_data_raises_execute_protection() {
+        let comp = run("lui $t0, 0x1000
+            jr $t0");
+        assert_eq!(
+            comp.last_exception(),
+            Some((ExceptionCause::ExecuteProtection, MEM_DATA_START as u32))
+        );
+        assert_eq!(comp.bad_vaddr(), MEM_DATA_START as u32);
+    }
+
+    #[test]
+    fn sb_stores_only_the_low_byte() {
+        let comp = run("lui $t0, 0x1000
+            addiu $t1, $zero, 0x1FF
+            addiu $t2, $zero, 0x1234
+            sw $t2, 0($t0)
+            sb $t1, 1($t0)
+            lb $t3, 1($t0)
+            lbu $t4, 1($t0)");
+        assert_eq!(reg(&comp, "t3"), 0xFFFFFFFF);
+        assert_eq!(reg(&comp, "t4"), 0xFF);
+        // The neighbouring bytes are untouched
+        assert_eq!(comp.mem_read_32(MEM_DATA_START), Some(0x0000FF34));
+    }
+
+    #[test]
+    fn run_to_halt_counts_the_instructions_retired() {
+        let mut comp = build(
+            "addiu $t0, $zero, 4
+            loop: addiu $t0, $t0, -1
+            bne $t0, $zero, loop
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        let count = comp.run_to_halt(1_000);
+        assert_eq!(count, Some(comp.instr_count()));
+        assert_eq!(count, Some(1 + 4 * 2 + 2));
+
+        // Too small a budget leaves the machine running
+        let mut comp = build("loop: addiu $t0, $t0, 1\nb loop");
+        assert_eq!(comp.run_to_halt(50), None);
+        assert_eq!(comp.instr_count(), 50);
+    }
+
+    #[test]
+    fn random_words_never_panic() {
+        let mut comp = build("nop\n");
+        let base = comp.config.text.start as u32;
+        let mut state: u64 = 0x9E3779B97F4A7C15;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let word = state as u32;
+            let _ = decode(word);
+            comp.load_words(base, &[word]).unwrap();
+            comp.curr_state.pc = base;
+            comp.next_state = comp.curr_state;
+            comp.run_bit = true;
+            comp.halt_reason = None;
+            comp.cycle();
+        }
+    }
+
+    #[test]
+    fn mult_is_signed() {
+        let mut comp = build(
+            "addiu $t0, $zero, -1
+            addiu $t1, $zero, -1
+            mult $t0, $t1
+            addiu $t2, $zero, -3
+            addiu $t3, $zero, 5
+            mult $t2, $t3
+            mfhi $s0
+            mflo $s1
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        comp.step();
+        comp.step();
+        comp.step();
+        assert_eq!((comp.curr_state.hi, comp.curr_state.lo), (0, 1));
+        run_to_end(&mut comp);
+        assert_eq!(comp.registers()[16], 0xFFFFFFFF);
+        assert_eq!(comp.registers()[17], -15_i32 as u32);
+    }
+
+    #[test]
+    fn div_puts_quotient_in_lo_and_remainder_in_hi() {
+        let mut comp = build(
+            "addiu $t0, $zero, -7
+            addiu $t1, $zero, 2
+            div $t0, $t1
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        run_to_end(&mut comp);
+        assert_eq!(comp.curr_state.lo, -3_i32 as u32);
+        assert_eq!(comp.curr_state.hi, -1_i32 as u32);
+    }
+
+    #[test]
+    fn divu_is_unsigned() {
+        let mut comp = build(
+            "addiu $t0, $zero, -1
+            addiu $t1, $zero, 16
+            divu $t0, $t1
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        run_to_end(&mut comp);
+        assert_eq!(comp.curr_state.lo, 0x0FFFFFFF);
+        assert_eq!(comp.curr_state.hi, 0xF);
+    }
+
+    #[test]
+    fn div_of_int_min_by_minus_one_wraps() {
+        let mut comp = build(
+            "lui $t0, 0x8000
+            addiu $t1, $zero, -1
+            div $t0, $t1
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        run_to_end(&mut comp);
+        assert_eq!(comp.curr_state.lo, 0x80000000);
+        assert_eq!(comp.curr_state.hi, 0);
+    }
+
+    #[test]
+    fn div_by_zero_leaves_hi_and_lo() {
+        let mut comp = build(
+            "addiu $t0, $zero, 9
+            mthi $t0
+            mtlo $t0
+            div $t0, $zero
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        run_to_end(&mut comp);
+        assert_eq!((comp.curr_state.hi, comp.curr_state.lo), (9, 9));
+    }
+
+    #[test]
+    fn check_abi_reports_a_clobbered_s1() {
+        let mut comp = build(
+            "addiu $s1, $zero, 3
+            jal clobber
+            addiu $t0, $zero, 1
+            clobber: addiu $s1, $s1, 1
+            addiu $t1, $zero, 2
+            jr $ra",
+        );
+        comp.set_abi_check(true);
+        comp.run(2);
+        comp.finish();
+        assert_eq!(comp.pc(), MEM_TEXT_START as u32 + 8);
+        assert_eq!(comp.abi_violations(), [(Location::Reg(17), 3, 4)]);
+
+        // A callee only touching temporaries is clean
+        let callee = MEM_TEXT_START as u32 + 16;
+        assert_eq!(comp.call(callee, &[]), Some(0));
+        assert_eq!(comp.abi_violations(), []);
+    }
+
+    #[test]
+    fn finish_mid_callee_checks_against_the_state_at_the_call() {
+        let mut comp = build(
+            "lui $sp, 0x1001
+            addiu $s0, $zero, 3
+            jal saver
+            addiu $t0, $zero, 1
+            saver: addiu $sp, $sp, -4
+            sw $s0, 0($sp)
+            addiu $s0, $zero, 9
+            lw $s0, 0($sp)
+            addiu $sp, $sp, 4
+            jr $ra",
+        );
+        comp.set_abi_check(true);
+        comp.run(6);
+        assert_eq!(reg(&comp, "s0"), 9);
+        comp.finish();
+        assert_eq!(comp.pc(), MEM_TEXT_START as u32 + 12);
+        assert_eq!(comp.abi_violations(), []);
+        assert!(comp.call_stack().is_empty());
+    }
+
+    #[test]
+    fn a_loops_backward_branch_is_logged_each_time() {
+        let mut comp = build(
+            "addiu $t0, $zero, 5
+            loop: addiu $t0, $t0, -1
+            bne $t0, $zero, loop",
+        );
+        comp.set_branch_log(true);
+        run_to_end(&mut comp);
+        let base = MEM_TEXT_START as u32;
+        let branch = BranchRecord {
+            source: base + 8,
+            target: base + 4,
+            taken: true,
+        };
+        let log = comp.branch_log().unwrap();
+        assert_eq!(log.len(), 5);
+        assert!(log.range(..4).all(|record| *record == branch));
+        assert_eq!(
+            log[4],
+            BranchRecord {
+                taken: false,
+                ..branch
+            }
+        );
+    }
+
+    #[test]
+    fn the_branch_log_keeps_only_the_latest_branches() {
+        let mut comp = build(
+            "ori $t0, $zero, 10005
+            loop: addiu $t0, $t0, -1
+            bne $t0, $zero, loop",
+        );
+        comp.set_branch_log(true);
+        assert!(comp.run_to_halt(100_000).is_some());
+        let log = comp.branch_log().unwrap();
+        assert_eq!(log.len(), BRANCH_LOG_DEPTH);
+        assert!(!log.back().unwrap().taken);
+        assert!(log.range(..BRANCH_LOG_DEPTH - 1).all(|record| record.taken));
+    }
+
+    #[test]
+    fn mdump_swaps_reversed_bounds_and_warns_of_unmapped_words() {
+        let comp = build("nop\n");
+        let (start, stop, warnings) = comp.mdump_bounds(0x10000008, 0x10000000);
+        assert_eq!((start, stop), (0x10000000, 0x10000008));
+        assert_eq!(
+            warnings,
+            ["Warning: mdump bounds are reversed, dumping 0x10000000..0x10000008"]
+        );
+
+        // The two words before the data region are not mapped
+        let (start, stop, warnings) = comp.mdump_bounds(0x0FFFFFF8, 0x10000004);
+        assert_eq!((start, stop), (0x0FFFFFF8, 0x10000004));
+        assert_eq!(
+            warnings,
+            ["Warning: 2 of 4 words in the range are unmapped"]
+        );
+        let mut out = Vec::new();
+        comp.mdump_intern(start, stop, &mut out, DumpFormat::Plain)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("<undefined address>").count(), 2, "{}", out);
+        assert!(
+            out.contains("0x10000004  (268435460) : 0x00000000"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn asciiz_data_lands_at_the_data_start() {
+        let mut comp = MipsComputer::new(&[]).unwrap();
+        comp.load_source(
+            ".data
+            greeting: .asciiz \"hi\"
+            .byte 7
+            .word 0x11223344
+            .text
+            la $t0, greeting
+            lbu $t1, 1($t0)",
+        )
+        .unwrap();
+        run_to_end(&mut comp);
+        let bytes: Vec<Option<u8>> = (0..4)
+            .map(|off| comp.read_byte(MEM_DATA_START + off))
+            .collect();
+        assert_eq!(bytes, [Some(b'h'), Some(b'i'), Some(0), Some(7)]);
+        // The word after the byte is aligned up to the next word
+        assert_eq!(comp.mem_read_32(MEM_DATA_START + 4), Some(0x11223344));
+        assert_eq!(reg(&comp, "t0"), MEM_DATA_START as u32);
+        assert_eq!(reg(&comp, "t1"), b'i' as u32);
+    }
+
+    #[test]
+    fn big_endian_data_words_are_laid_out_msb_first() {
+        let config = MipsConfig {
+            endianness: Endianness::Big,
+            ..MipsConfig::default()
+        };
+        let mut comp = MipsComputer::with_config(config, &[]).unwrap();
+        comp.load_source(
+            ".data
+            .byte 7
+            .word 0x11223344",
+        )
+        .unwrap();
+        let bytes: Vec<Option<u8>> = (4..8)
+            .map(|off| comp.read_byte(MEM_DATA_START + off))
+            .collect();
+        assert_eq!(bytes, [Some(0x11), Some(0x22), Some(0x33), Some(0x44)]);
+        assert_eq!(comp.read_byte(MEM_DATA_START), Some(7));
+    }
+
+    #[test]
+    fn set_endianness_swaps_only_words_from_binaries() {
+        let binary = temp_path(".bin");
+        // addiu $t0, $zero, 5
+        std::fs::write(&binary, 0x24080005_u32.to_le_bytes()).unwrap();
+        let mut comp = MipsComputer::new(&[]).unwrap();
+        comp.load_program(&binary).unwrap();
+        std::fs::remove_file(&binary).unwrap();
+        let base = MEM_TEXT_START as u32;
+        // Words loaded past the binary some other way are not swapped
+        comp.load_words(base + 4, &[0x24090007]).unwrap();
+        assert_eq!(comp.set_endianness(Endianness::Big), 1);
+        assert_eq!(comp.mem_read_32(base as usize), Some(0x05000824));
+        assert_eq!(comp.mem_read_32(base as usize + 4), Some(0x24090007));
+
+        let mut comp = MipsComputer::new(&[]).unwrap();
+        comp.load_source("addiu $t0, $zero, 5").unwrap();
+        assert_eq!(comp.set_endianness(Endianness::Big), 0);
+        assert_eq!(comp.mem_read_32(base as usize), Some(0x24080005));
+    }
+
+    #[test]
+    fn a_cold_load_pays_the_miss_penalty_and_a_warm_one_does_not() {
+        let mut comp = build(
+            "lui $t0, 0x1000
+            lw $t1, 0($t0)
+            lw $t2, 4($t0)",
+        );
+        comp.enable_dcache(16, 4);
+        comp.set_cycle_costs(CycleCosts {
+            miss_penalty: 20,
+            ..CycleCosts::default()
+        });
+        let load = comp.cycle_costs().load as u64;
+        comp.step();
+        let before = comp.cycle_count();
+        comp.step();
+        assert_eq!(comp.cycle_count() - before, load + 20);
+        // The same line is now cached
+        let before = comp.cycle_count();
+        comp.step();
+        assert_eq!(comp.cycle_count() - before, load);
+    }
+
+    #[test]
+    fn two_machines_hash_equally_at_each_step() {
+        let src = "lui $t0, 0x1000
+            addiu $t1, $zero, 3
+            loop: sw $t1, 0($t0)
+            addiu $t0, $t0, 4
+            addiu $t1, $t1, -1
+            bne $t1, $zero, loop
+            mult $t0, $t0";
+        let mut first = build(src);
+        // Configured differently, which the hash leaves out
+        let config = MipsConfig {
+            history_depth: 0,
+            ..MipsConfig::default()
+        };
+        let mut second = MipsComputer::with_config(config, &[]).unwrap();
+        second.set_branch_log(true);
+        second
+            .load_words(MEM_TEXT_START as u32, &crate::asm::assemble(src).unwrap())
+            .unwrap();
+        let initial = first.state_hash();
+        while first.run_bit {
+            assert_eq!(first.state_hash(), second.state_hash());
+            first.cycle();
+            second.cycle();
+        }
+        assert_eq!(first.state_hash(), second.state_hash());
+        assert_ne!(first.state_hash(), initial);
+        // Any one differing word changes it
+        second.mem_write_32(MEM_DATA_START + 64, 1);
+        assert_ne!(first.state_hash(), second.state_hash());
+    }
+
+    #[test]
+    fn a_pref_word_runs_as_a_no_op() {
+        // pref 0, 0($t0) then addiu $t1, $zero, 2
+        let words = [0xCD000000, 0x24090002];
+        assert_eq!(decode(words[0]).unwrap().to_string(), "pref 0, 0($t0)");
+        let mut comp = build("nop\n");
+        comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+        run_to_end(&mut comp);
+        assert_eq!(comp.halt_reason(), Some(HaltReason::RanOffEnd));
+        assert_eq!(reg(&comp, "t1"), 2);
+    }
+
+    #[test]
+    fn a_cache_word_runs_as_a_no_op() {
+        // cache 0x1C, 8($t0) then addiu $t1, $zero, 2
+        let words = [0xBD1C0008, 0x24090002];
+        assert_eq!(decode(words[0]).unwrap().to_string(), "cache 28, 8($t0)");
+        let mut comp = build("nop\n");
+        comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+        run_to_end(&mut comp);
+        assert_eq!(comp.halt_reason(), Some(HaltReason::RanOffEnd));
+        assert_eq!(reg(&comp, "t1"), 2);
+    }
+
+    #[test]
+    fn a_spim_dump_matches_the_same_machine_state() {
+        let dump =
+            "PC      = 00400024   EPC     = 00000000   Cause   = 00000000   BadVAddr= 00000000
+Status  = 3000ff10   HI      = 00000000   LO      = 00000004
+
+General Registers
+R0  [r0] = 00000000  R8  [t0] = 0000000a  R16 [s0] = 00000000  R24 [t8] = 00000000
+R1  [at] = 00000000  R9  [t1] = 00000002  R17 [s1] = 00000000  R25 [t9] = 00000000
+";
+        let expectations = verify::parse_spim(dump).unwrap();
+        assert_eq!(expectations.len(), 10);
+        assert!(expectations.contains(&Expectation {
+            location: Location::Reg(8),
+            value: 10,
+        }));
+        assert!(expectations.contains(&Expectation {
+            location: Location::Lo,
+            value: 4,
+        }));
+
+        let comp = run("addiu $t0, $zero, 10
+            addiu $t1, $zero, 2
+            mult $t1, $t1");
+        let mut out = Vec::new();
+        assert_eq!(comp.verify_intern(&expectations, &mut out).unwrap(), 0);
+        let comp = run("addiu $t0, $zero, 11
+            addiu $t1, $zero, 2
+            mult $t1, $t1");
+        let mut out = Vec::new();
+        assert_eq!(comp.verify_intern(&expectations, &mut out).unwrap(), 1);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("FAIL"), "{}", out);
+    }
+
+    #[test]
+    fn addu_wrapping_sets_the_carry_flag() {
+        let mut comp = build(
+            "addiu $t0, $zero, -1
+            addiu $t1, $zero, 1
+            addu $t2, $t0, $t1",
+        );
+        comp.run(3);
+        assert_eq!(reg(&comp, "t2"), 0);
+        let flags = comp.arith_flags();
+        assert!(flags.carry);
+        assert!(!flags.overflow);
+
+        let mut out = Vec::new();
+        comp.rdump_intern(&mut out, DumpFormat::Plain).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("Flags:"));
+        comp.set_show_flags(true);
+        let mut out = Vec::new();
+        comp.rdump_intern(&mut out, DumpFormat::Plain).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Flags: overflow=0 carry=1"), "{}", out);
+    }
+
+    #[test]
+    fn flags_follow_the_last_add_and_back_restores_them() {
+        let mut comp = build(
+            "addiu $t0, $zero, -1
+            addiu $t1, $zero, 1
+            addu $t2, $t0, $t1
+            addu $t3, $t1, $t1",
+        );
+        comp.set_history_depth(10);
+        comp.run(3);
+        assert!(comp.arith_flags().carry);
+        comp.step();
+        assert_eq!(comp.arith_flags(), ArithFlags::default());
+        assert_eq!(comp.back(1), 1);
+        assert!(comp.arith_flags().carry);
+        assert_eq!(comp.back(1), 1);
+        assert_eq!(comp.arith_flags(), ArithFlags::default());
+    }
+
+    #[test]
+    fn back_restores_an_earlier_cycle() {
+        let mut comp = build(
+            "addiu $t0, $zero, 1
+            lui $t1, 0x1000
+            addiu $t2, $zero, 3
+            sw $t2, 0($t1)
+            addiu $t0, $t0, 10
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        comp.set_history_depth(10);
+        comp.step();
+        comp.step();
+        let pc = comp.pc();
+        let regs = comp.registers();
+        for _ in 0..3 {
+            comp.step();
+        }
+        assert_eq!(comp.instr_count(), 5);
+        assert_eq!(comp.read_byte(MEM_DATA_START), Some(3));
+        assert_eq!(comp.back(3), 3);
+        assert_eq!(comp.pc(), pc);
+        assert_eq!(comp.registers(), regs);
+        assert_eq!(comp.instr_count(), 2);
+        assert_eq!(comp.read_byte(MEM_DATA_START), Some(0));
+    }
+
+    #[test]
+    fn history_is_off_until_a_depth_is_set() {
+        let mut comp = build("addiu $t0, $zero, 1\naddiu $t1, $zero, 2\naddiu $t2, $zero, 3\n");
+        comp.step();
+        assert_eq!(comp.history_len(), 0);
+        assert_eq!(comp.back(1), 0);
+        comp.set_history_depth(2);
+        comp.step();
+        comp.step();
+        assert_eq!(comp.history_len(), 2);
+        comp.set_history_depth(1);
+        assert_eq!(comp.history_len(), 1);
+    }
+
+    #[test]
+    fn back_after_reset_stats_does_not_underflow() {
+        let mut comp = build("addiu $t0, $zero, 1\naddiu $t1, $zero, 2\n");
+        comp.set_history_depth(10);
+        comp.step();
+        comp.step();
+        comp.reset_stats();
+        assert_eq!(comp.back(2), 2);
+        assert_eq!(comp.instr_count(), 0);
+        assert_eq!(comp.registers()[8], 0);
+    }
+
+    #[test]
+    fn call_returns_v0_from_a_leaf_function() {
+        let mut comp = build(
+            "addiu $v0, $zero, 10
+            syscall
+            addu $v0, $a0, $a1
+            jr $ra",
+        );
+        let base = comp.config.text.start as u32;
+        let ra = comp.registers()[31];
+        assert_eq!(comp.call(base + 8, &[20, 22]), Some(42));
+        assert_eq!(comp.pc(), base);
+        assert_eq!(comp.registers()[31], ra);
+    }
+
+    #[test]
+    fn call_that_does_not_return_restores_pc_and_ra() {
+        let mut comp = build(
+            "addiu $v0, $zero, 10
+            syscall
+            addiu $v0, $zero, 10
+            syscall",
+        );
+        let base = comp.config.text.start as u32;
+        let ra = comp.registers()[31];
+        assert_eq!(comp.call(base + 8, &[]), None);
+        assert_eq!(comp.pc(), base);
+        assert_eq!(comp.registers()[31], ra);
+    }
 }
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::io;
+
+// Names for addresses, read from `addr=name` lines. Addresses are hex
+// with a 0x prefix, decimal otherwise.
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: BTreeMap<u32, String>,
+}
+
+fn parse_address(inp: &str) -> Option<u32> {
+    match inp.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => inp.parse().ok(),
+    }
+}
+
+impl SymbolTable {
+    // Blank lines and lines starting with `#` are skipped
+    pub fn parse(src: &str) -> io::Result<Self> {
+        let mut table = Self::default();
+        for (idx, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line
+                .split_once('=')
+                .and_then(|(addr, name)| Some((parse_address(addr.trim())?, name.trim())));
+            match entry {
+                Some((addr, name)) if !name.is_empty() => table.insert(addr, name),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("line {}: expected addr=name", idx + 1),
+                    ));
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, addr: u32, name: &str) {
+        self.symbols.insert(addr, name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // The symbol at exactly `addr`
+    pub fn get(&self, addr: u32) -> Option<&str> {
+        self.symbols.get(&addr).map(String::as_str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(_, sym)| *sym == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    // The closest symbol at or below `addr`, with the offset from it
+    pub fn nearest(&self, addr: u32) -> Option<(&str, u32)> {
+        self.symbols
+            .range(..=addr)
+            .next_back()
+            .map(|(base, name)| (name.as_str(), addr - base))
+    }
+
+    // Formats `addr` as `<name>` or `<name+0xoff>`, or nothing if no
+    // symbol precedes it
+    pub fn annotate(&self, addr: u32) -> String {
+        match self.nearest(addr) {
+            Some((name, 0)) => format!(" <{}>", name),
+            Some((name, off)) => format!(" <{}+{:#X}>", name, off),
+            None => String::new(),
+        }
+    }
+}
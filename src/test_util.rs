@@ -0,0 +1,62 @@
+use super::asm::*;
+use super::sim::*;
+use std::cell::RefCell;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Assembles `src` into the text region of a fresh machine, with PC at its
+// first instruction. Panics on bad assembly, as tests want.
+pub fn build(src: &str) -> MipsComputer {
+    let words = assemble(src).expect("Cannot assemble test program");
+    let mut comp = MipsComputer::new(&[]).expect("Cannot create machine");
+    comp.load_words(MEM_TEXT_START as u32, &words)
+        .expect("Cannot load test program");
+    comp
+}
+
+// An output sink that can be read back after a clone of it was handed to
+// the machine, eg as its console or a trace sink's writer
+#[derive(Clone, Default)]
+pub struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuf {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+// A path in the temp directory no other test uses, ending in `suffix`
+pub fn temp_path(suffix: &str) -> PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let name = format!("mips-sim-test-{}-{}{}", std::process::id(), id, suffix);
+    std::env::temp_dir().join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_a_runnable_program() {
+        let mut comp = build("addi $t0, $zero, 5\naddi $t1, $t0, 7\n");
+        assert_eq!(comp.pc(), MEM_TEXT_START as u32);
+        comp.run(2);
+        assert_eq!(comp.registers()[8], 5);
+        assert_eq!(comp.registers()[9], 12);
+    }
+}
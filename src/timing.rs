@@ -0,0 +1,162 @@
+use super::instr::*;
+
+// Cycles charged for each class of instruction
+#[derive(Debug, Clone, Copy)]
+pub struct CycleCosts {
+    pub alu: u32,
+    pub load: u32,
+    pub store: u32,
+    pub mult_div: u32,
+    pub fpu: u32,
+    pub branch: u32,
+    pub syscall: u32,
+    // Stall added by a miss in the instruction or data cache
+    pub miss_penalty: u32,
+}
+
+impl Default for CycleCosts {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            load: 2,
+            store: 2,
+            mult_div: 5,
+            fpu: 2,
+            branch: 1,
+            syscall: 1,
+            miss_penalty: 10,
+        }
+    }
+}
+
+impl CycleCosts {
+    pub fn cost(&self, instr: &Instr) -> u32 {
+        match instr {
+            Instr::JType(_) => self.branch,
+            Instr::IType(instr) => match instr.op() {
+                IOp::LB | IOp::LH | IOp::LW | IOp::LBU | IOp::LHU | IOp::LWC1 => self.load,
+                IOp::SB | IOp::SH | IOp::SW | IOp::SWC1 => self.store,
+                IOp::BEQ
+                | IOp::BNE
+                | IOp::BLEZ
+                | IOp::BGTZ
+                | IOp::BLTZ
+                | IOp::BGEZ
+                | IOp::BLTZAL
+                | IOp::BGEZAL => self.branch,
+                _ => self.alu,
+            },
+            Instr::RType(instr) => match instr.op() {
+                ROp::MULT | ROp::MULTU | ROp::DIV | ROp::DIVU => self.mult_div,
+                ROp::JR | ROp::JALR => self.branch,
+                ROp::SYSCALL | ROp::BREAK => self.syscall,
+                _ => self.alu,
+            },
+            Instr::FRType(instr) => match instr.op() {
+                FOp::MFC1 | FOp::MTC1 => self.alu,
+                _ => self.fpu,
+            },
+        }
+    }
+}
+
+// A classic IF/ID/EX/MEM/WB pipeline with full forwarding, layered on top of
+// functional execution to count cycles. Only two things hold it up: a load
+// followed by an instruction using the loaded register stalls for one
+// cycle, and a taken branch or jump, resolved in ID, flushes the one
+// instruction fetched behind it.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    instrs: u64,
+    stalls: u64,
+    flushes: u64,
+    // The GPR written by the previous instruction if it was a load
+    pending_load: Option<u32>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Accounts for one instruction entering the pipeline
+    pub fn issue(&mut self, instr: &Instr, taken: bool) {
+        if let Some(reg) = self.pending_load {
+            if instr.read_regs().contains(&reg) {
+                self.stalls += 1;
+            }
+        }
+        self.pending_load = match instr {
+            Instr::IType(itype) => match itype.op() {
+                IOp::LB | IOp::LH | IOp::LW | IOp::LBU | IOp::LHU => {
+                    instr.written_reg().filter(|reg| *reg != 0)
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if taken {
+            self.flushes += 1;
+        }
+        self.instrs += 1;
+    }
+
+    // Zeroes the counts, keeping track of an in-flight load
+    pub fn reset_stats(&mut self) {
+        self.instrs = 0;
+        self.stalls = 0;
+        self.flushes = 0;
+    }
+
+    pub fn instrs(&self) -> u64 {
+        self.instrs
+    }
+
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    pub fn flushes(&self) -> u64 {
+        self.flushes
+    }
+
+    // Cycles until the last issued instruction leaves WB
+    pub fn cycles(&self) -> u64 {
+        if self.instrs == 0 {
+            return 0;
+        }
+        self.instrs + 4 + self.stalls + self.flushes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn issue_all(src: &str) -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        for word in assemble(src).unwrap() {
+            pipeline.issue(&decode(word).unwrap(), false);
+        }
+        pipeline
+    }
+
+    #[test]
+    fn a_load_use_dependency_stalls_once() {
+        let pipeline = issue_all(
+            "lw $t0, 0($t1)
+            addu $t2, $t0, $t0",
+        );
+        assert_eq!(pipeline.stalls(), 1);
+        assert_eq!(pipeline.cycles(), 2 + 4 + 1);
+
+        // An instruction in between hides the load latency
+        let pipeline = issue_all(
+            "lw $t0, 0($t1)
+            nop
+            addu $t2, $t0, $t0",
+        );
+        assert_eq!(pipeline.stalls(), 0);
+    }
+}
@@ -0,0 +1,292 @@
+use std::io;
+use std::io::prelude::*;
+
+// What one retired instruction did, as reported to a trace sink
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub pc: u32,
+    pub word: u32,
+    pub mnemonic: String,
+    // GPRs whose value changed, with the new value
+    pub reg_writes: Vec<(u32, u32)>,
+    // Stores made, as address and value written
+    pub mem_writes: Vec<(u32, u32)>,
+    // Cycles the instruction cost under the cycle-cost model, and the
+    // running total including it
+    pub cycles: u64,
+    pub total_cycles: u64,
+}
+
+pub trait TraceSink {
+    fn record(&mut self, event: &TraceEvent) -> io::Result<()>;
+}
+
+// Writes one JSON object per retired instruction, one per line
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+fn json_string(inp: &str) -> String {
+    let mut out = String::from("\"");
+    for c in inp.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_pairs(pairs: &[(u32, u32)], key: &str) -> String {
+    let items: Vec<String> = pairs
+        .iter()
+        .map(|(first, value)| format!("{{\"{}\":{},\"value\":{}}}", key, first, value))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+impl<W: Write> TraceSink for JsonLinesSink<W> {
+    fn record(&mut self, event: &TraceEvent) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{{\"pc\":{},\"word\":{},\"mnemonic\":{},\"reg_writes\":{},\"mem_writes\":{},\"cycles\":{},\"total_cycles\":{}}}",
+            event.pc,
+            event.word,
+            json_string(&event.mnemonic),
+            json_pairs(&event.reg_writes, "reg"),
+            json_pairs(&event.mem_writes, "addr"),
+            event.cycles,
+            event.total_cycles
+        )?;
+        self.out.flush()
+    }
+}
+
+// Writes PC, raw word and mnemonic per retired instruction, in the same
+// layout as `dis` so traces can be diffed against other simulators. Cycle
+// counts are left out, as other simulators would not match them.
+pub struct TextSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> TraceSink for TextSink<W> {
+    fn record(&mut self, event: &TraceEvent) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{:#010X} : {:08X}  {}",
+            event.pc, event.word, event.mnemonic
+        )?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build, SharedBuf};
+    use crate::timing::CycleCosts;
+    use std::collections::HashMap;
+
+    // Just enough JSON to read the trace back
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Num(u64),
+        Str(String),
+        Arr(Vec<Json>),
+        Obj(HashMap<String, Json>),
+    }
+
+    fn parse(inp: &str) -> Json {
+        let mut chars = inp.chars().peekable();
+        let value = parse_value(&mut chars);
+        assert_eq!(chars.next(), None, "trailing input in {}", inp);
+        value
+    }
+
+    fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Json {
+        match chars.next() {
+            Some('{') => {
+                let mut fields = HashMap::new();
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Json::Obj(fields);
+                }
+                loop {
+                    let key = match parse_value(chars) {
+                        Json::Str(key) => key,
+                        other => panic!("bad key {:?}", other),
+                    };
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.insert(key, parse_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Json::Obj(fields),
+                        other => panic!("bad object at {:?}", other),
+                    }
+                }
+            }
+            Some('[') => {
+                let mut items = Vec::new();
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Json::Arr(items);
+                }
+                loop {
+                    items.push(parse_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Json::Arr(items),
+                        other => panic!("bad array at {:?}", other),
+                    }
+                }
+            }
+            Some('"') => {
+                let mut out = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => return Json::Str(out),
+                        Some('\\') => match chars.next() {
+                            Some('u') => {
+                                let hex: String = chars.take(4).collect();
+                                let code = u32::from_str_radix(&hex, 16).unwrap();
+                                out.push(char::from_u32(code).unwrap());
+                            }
+                            Some(c) => out.push(c),
+                            None => panic!("unterminated escape"),
+                        },
+                        Some(c) => out.push(c),
+                        None => panic!("unterminated string"),
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut num = c.to_digit(10).unwrap() as u64;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    num = num * 10 + digit as u64;
+                    chars.next();
+                }
+                Json::Num(num)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn field<'a>(obj: &'a Json, key: &str) -> &'a Json {
+        match obj {
+            Json::Obj(fields) => &fields[key],
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn json_lines_parse_back() {
+        let mut comp = build(
+            "addiu $t0, $zero, 5
+            lui $t1, 0x1000
+            sw $t0, 0($t1)",
+        );
+        let out = SharedBuf::default();
+        comp.set_trace_sink(Some(Box::new(JsonLinesSink::new(out.clone()))));
+        comp.run(3);
+        let contents = out.contents();
+        let lines: Vec<Json> = contents.lines().map(parse).collect();
+        assert_eq!(lines.len(), 3);
+
+        let first = &lines[0];
+        assert_eq!(field(first, "pc"), &Json::Num(0x00400000));
+        assert_eq!(field(first, "word"), &Json::Num(0x24080005));
+        assert_eq!(
+            field(first, "mnemonic"),
+            &Json::Str(String::from("addiu $t0, $zero, 5"))
+        );
+        let write = Json::Obj(HashMap::from([
+            (String::from("reg"), Json::Num(8)),
+            (String::from("value"), Json::Num(5)),
+        ]));
+        assert_eq!(field(first, "reg_writes"), &Json::Arr(vec![write]));
+        assert_eq!(field(first, "mem_writes"), &Json::Arr(Vec::new()));
+
+        let store = Json::Obj(HashMap::from([
+            (String::from("addr"), Json::Num(0x10000000)),
+            (String::from("value"), Json::Num(5)),
+        ]));
+        assert_eq!(field(&lines[2], "mem_writes"), &Json::Arr(vec![store]));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+        assert_eq!(
+            parse(&json_string("a\"b\\c\n")),
+            Json::Str(String::from("a\"b\\c\n"))
+        );
+    }
+
+    #[test]
+    fn unselected_registers_are_left_out_of_the_trace() {
+        let mut comp = build(
+            "addiu $t0, $zero, 5
+            addiu $t1, $zero, 6
+            addu $t0, $t0, $t1",
+        );
+        let out = SharedBuf::default();
+        comp.set_trace_sink(Some(Box::new(JsonLinesSink::new(out.clone()))));
+        comp.set_trace_regs(1 << 8);
+        comp.run(3);
+        let contents = out.contents();
+        let writes: Vec<usize> = contents
+            .lines()
+            .map(|line| match field(&parse(line), "reg_writes") {
+                Json::Arr(writes) => writes.len(),
+                other => panic!("not an array: {:?}", other),
+            })
+            .collect();
+        // Every instruction is still traced, only the $t1 write is dropped
+        assert_eq!(writes, [1, 0, 1]);
+        assert!(!contents.contains("\"reg\":9"), "{}", contents);
+    }
+
+    #[test]
+    fn a_lw_shows_its_configured_cost() {
+        let mut comp = build(
+            "lui $t0, 0x1000
+            lw $t1, 0($t0)
+            addu $t2, $t1, $t1",
+        );
+        comp.set_cycle_costs(CycleCosts {
+            load: 4,
+            ..CycleCosts::default()
+        });
+        let out = SharedBuf::default();
+        comp.set_trace_sink(Some(Box::new(JsonLinesSink::new(out.clone()))));
+        comp.run(3);
+        let lines: Vec<Json> = out.contents().lines().map(parse).collect();
+        let cycles: Vec<(&Json, &Json)> = lines
+            .iter()
+            .map(|line| (field(line, "cycles"), field(line, "total_cycles")))
+            .collect();
+        assert_eq!(
+            cycles,
+            [
+                (&Json::Num(1), &Json::Num(1)),
+                (&Json::Num(4), &Json::Num(5)),
+                (&Json::Num(1), &Json::Num(6)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::instr::*;
+use std::fmt;
+use std::io;
+
+// Where an expected value should be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Reg(u32),
+    Hi,
+    Lo,
+    Pc,
+    // The word at an address
+    Mem(u32),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Reg(reg) => write!(f, "${}", REG_NAMES[*reg as usize]),
+            Location::Hi => write!(f, "hi"),
+            Location::Lo => write!(f, "lo"),
+            Location::Pc => write!(f, "pc"),
+            Location::Mem(address) => write!(f, "[{:#010X}]", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expectation {
+    pub location: Location,
+    pub value: u32,
+}
+
+// Accepts hex with a 0x prefix, decimal otherwise, optionally negative
+fn parse_value(inp: &str) -> Option<u32> {
+    let (neg, inp) = match inp.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, inp),
+    };
+    let val = match inp.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => inp.parse().ok()?,
+    };
+    Some(if neg { val.wrapping_neg() } else { val })
+}
+
+fn parse_location(inp: &str) -> Option<Location> {
+    match inp {
+        "hi" => Some(Location::Hi),
+        "lo" => Some(Location::Lo),
+        "pc" => Some(Location::Pc),
+        _ => match inp
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            Some(address) => parse_value(address.trim()).map(Location::Mem),
+            None => inp.strip_prefix('$').and_then(parse_reg).map(Location::Reg),
+        },
+    }
+}
+
+// Lines are `location = value`, eg, `$t0 = 0x5` or `[0x10000000] = 42`.
+// Blank lines and lines starting with `#` are skipped.
+pub fn parse(src: &str) -> io::Result<Vec<Expectation>> {
+    let mut expectations = Vec::new();
+    for (idx, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let expectation = line.split_once('=').and_then(|(location, value)| {
+            Some(Expectation {
+                location: parse_location(location.trim())?,
+                value: parse_value(value.trim())?,
+            })
+        });
+        match expectation {
+            Some(expectation) => expectations.push(expectation),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("line {}: expected location = value", idx + 1),
+                ));
+            }
+        }
+    }
+    Ok(expectations)
+}
+
+// Reads the GPRs, HI and LO from a SPIM or QtSpim register dump, with
+// lines like `HI      = 00000000   LO      = 00000004` and
+// `R8  (t0) = 0000000a  R16 (s0) = 00000000`, values being hex. Brackets may
+// be square, as QtSpim writes them. PC is left out, as SPIM stops in its
+// exit code, and so are the FPU and coprocessor 0 registers.
+pub fn parse_spim(src: &str) -> io::Result<Vec<Expectation>> {
+    let mut expectations = Vec::new();
+    for (idx, line) in src.lines().enumerate() {
+        let spaced = line.replace('=', " = ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        // The key is the first token after the previous value, eg, `R8`
+        // in `R8  (t0) = 0000000a`
+        let mut key_start = 0;
+        for (pos, token) in tokens.iter().enumerate() {
+            if *token != "=" {
+                continue;
+            }
+            let key = tokens.get(key_start).filter(|_| key_start < pos);
+            let value = tokens.get(pos + 1);
+            key_start = pos + 2;
+            let location = match key.map(|key| key.to_uppercase()) {
+                Some(key) if key == "HI" => Location::Hi,
+                Some(key) if key == "LO" => Location::Lo,
+                Some(key) => match key.strip_prefix('R').and_then(|num| num.parse().ok()) {
+                    Some(reg) if reg < 32 => Location::Reg(reg),
+                    _ => continue,
+                },
+                None => continue,
+            };
+            let value = value
+                .map(|value| value.trim_start_matches("0x"))
+                .and_then(|value| u32::from_str_radix(value, 16).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("line {}: expected a hex value for {}", idx + 1, location),
+                    )
+                })?;
+            expectations.push(Expectation { location, value });
+        }
+    }
+    if expectations.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no registers found in SPIM dump",
+        ));
+    }
+    Ok(expectations)
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const BIN: &str = env!("CARGO_BIN_EXE_mips-sim");
+
+// addiu $t0, $zero, 5; addiu $t1, $t1, 1; addu $t2, $t0, $t1; syscall
+const PROGRAM: [u32; 4] = [0x24080005, 0x25290001, 0x01095021, 0x0000000C];
+
+// A scratch directory per test, as the simulator writes its dump file
+// into the working directory
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mips-sim-cli-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_program(dir: &Path, words: &[u32]) -> PathBuf {
+    let path = dir.join("prog.bin");
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn disasm_prints_the_golden_listing() {
+    let dir = scratch_dir("disasm");
+    let program = write_program(&dir, &PROGRAM);
+    let out = Command::new(BIN)
+        .current_dir(&dir)
+        .arg("disasm")
+        .arg(&program)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let listing: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.trim_start().starts_with("0x"))
+        .collect();
+    assert_eq!(
+        listing,
+        [
+            "    0x00400000 : 24080005  addiu $t0, $zero, 5",
+            "    0x00400004 : 25290001  addiu $t1, $t1, 1",
+            "    0x00400008 : 01095021  addu $t2, $t0, $t1",
+            "    0x0040000C : 0000000C  syscall",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn trace_to_logs_one_line_per_retired_instruction() {
+    let dir = scratch_dir("trace");
+    let program = write_program(&dir, &PROGRAM);
+    let trace = dir.join("trace.txt");
+    let mut child = Command::new(BIN)
+        .current_dir(&dir)
+        .arg("--trace-to")
+        .arg(&trace)
+        .arg(&program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"go\ninfo\nquit\n")
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    // info reports the retired count as "| N instrs |"
+    let retired: usize = stdout
+        .lines()
+        .find_map(|line| {
+            line.split(" instrs")
+                .next()?
+                .rsplit("| ")
+                .next()?
+                .parse()
+                .ok()
+        })
+        .expect("no instruction count in the output");
+    assert_eq!(retired, PROGRAM.len());
+    let lines = fs::read_to_string(&trace).unwrap();
+    assert_eq!(lines.lines().count(), retired);
+    assert!(lines.starts_with("0x00400000 : 24080005  addiu $t0, $zero, 5\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_syscall_10_exit_gives_status_0() {
+    let dir = scratch_dir("exit");
+    // addiu $v0, $zero, 10; syscall
+    let program = write_program(&dir, &[0x2402000A, 0x0000000C]);
+    let mut child = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--script", "-"])
+        .arg(&program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"go\nquit\n")
+        .unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(0));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_a_script_fails_on_an_unclean_halt() {
+    let dir = scratch_dir("unclean");
+    // lui $t0, 0x1000; lw $t1, -4($t0)
+    let program = write_program(&dir, &[0x3C081000, 0x8D09FFFC]);
+    let script = dir.join("commands");
+    fs::write(&script, "go\n").unwrap();
+    let status = |args: &[&str]| {
+        let mut child = Command::new(BIN)
+            .current_dir(&dir)
+            .args(args)
+            .arg(&program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"go\n").unwrap();
+        child.wait().unwrap().code()
+    };
+    assert_eq!(status(&[]), Some(0));
+    assert_eq!(status(&["--script", "-"]), Some(2));
+    assert_eq!(status(&["--script", script.to_str().unwrap()]), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
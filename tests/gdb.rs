@@ -0,0 +1,113 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use mips_sim::asm::assemble;
+use mips_sim::gdb;
+use mips_sim::sim::*;
+
+fn send(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, data: &str) -> String {
+    let checksum = data.bytes().fold(0_u8, |sum, byte| sum.wrapping_add(byte));
+    write!(stream, "${}#{:02x}", data, checksum).unwrap();
+    let mut byte = [0_u8; 1];
+    reader.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'+', "packet {} was not acked", data);
+    reader.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'$');
+    let mut reply = Vec::new();
+    reader.read_until(b'#', &mut reply).unwrap();
+    reply.pop();
+    let mut checksum = [0_u8; 2];
+    reader.read_exact(&mut checksum).unwrap();
+    String::from_utf8(reply).unwrap()
+}
+
+// A free port to hand to the stub
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// Connects to the stub once it is listening on `port`
+fn connect(port: u16) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .map_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Cannot connect to the stub");
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+// The little-endian hex gdb uses for the register at `idx`
+fn register(regs: &str, idx: usize) -> &str {
+    &regs[idx * 8..idx * 8 + 8]
+}
+
+#[test]
+fn g_and_m_packets_read_a_loaded_program() {
+    let words = assemble("addiu $t0, $zero, 5\naddiu $t1, $t0, 1\n").unwrap();
+    let mut comp = MipsComputer::new(&[]).unwrap();
+    comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+
+    let port = free_port();
+    // The machine is not Send, so the client talks to the stub from a thread
+    let client = thread::spawn(move || {
+        let (mut stream, mut reader) = connect(port);
+
+        let regs = send(&mut stream, &mut reader, "g");
+        assert_eq!(regs.len(), 72 * 8);
+        assert_eq!(register(&regs, 37), "00004000");
+        assert_eq!(register(&regs, 8), "00000000");
+
+        // addiu $t0, $zero, 5 is 0x24080005, sent lowest byte first
+        assert_eq!(send(&mut stream, &mut reader, "m400000,4"), "05000824");
+        assert_eq!(send(&mut stream, &mut reader, "m0,4"), "E14");
+
+        assert_eq!(send(&mut stream, &mut reader, "s"), "S05");
+        let regs = send(&mut stream, &mut reader, "g");
+        assert_eq!(register(&regs, 37), "04004000");
+        assert_eq!(register(&regs, 8), "05000000");
+
+        assert_eq!(send(&mut stream, &mut reader, "D"), "OK");
+    });
+    gdb::serve(&mut comp, port).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn an_interrupt_byte_stops_a_continue() {
+    let words = assemble("loop: addiu $t0, $t0, 1\nb loop\n").unwrap();
+    let mut comp = MipsComputer::new(&[]).unwrap();
+    comp.load_words(MEM_TEXT_START as u32, &words).unwrap();
+
+    let port = free_port();
+    let client = thread::spawn(move || {
+        let (mut stream, mut reader) = connect(port);
+        write!(stream, "$c#63").unwrap();
+        let mut byte = [0_u8; 1];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        // Let the loop spin for a while before interrupting it
+        thread::sleep(Duration::from_millis(50));
+        stream.write_all(&[0x03]).unwrap();
+        let mut reply = Vec::new();
+        reader.read_until(b'#', &mut reply).unwrap();
+        assert_eq!(reply, b"$S02#");
+        let mut checksum = [0_u8; 2];
+        reader.read_exact(&mut checksum).unwrap();
+
+        let regs = send(&mut stream, &mut reader, "g");
+        assert_ne!(register(&regs, 8), "00000000");
+        assert_eq!(send(&mut stream, &mut reader, "D"), "OK");
+    });
+    gdb::serve(&mut comp, port).unwrap();
+    client.join().unwrap();
+}